@@ -1,10 +1,32 @@
-use doji::Engine;
+use std::{env, process::ExitCode};
 
-fn main() {
+use doji::{Engine, Error};
+
+fn main() -> ExitCode {
     let engine = Engine::builder().build();
 
-    match engine.evaluate_inline::<i64>("3 + 4") {
-        Ok(answer) => println!("3 + 4 = {}", answer),
-        Err(error) => eprintln!("error: {}", error),
+    let result = match env::args().nth(1) {
+        Some(path) => engine.evaluate_file::<i64>(path),
+        None => engine.evaluate_inline::<i64>("3 + 4"),
+    };
+
+    for warnings in engine.warnings() {
+        eprintln!("{}\n", warnings);
+    }
+
+    match result {
+        Ok(value) => {
+            println!("{}", value);
+            ExitCode::SUCCESS
+        }
+        // Diagnostics already say what kind of problem they are.
+        Err(error @ Error::Compile(_)) => {
+            eprintln!("{}", error);
+            ExitCode::FAILURE
+        }
+        Err(error) => {
+            eprintln!("error: {}", error);
+            ExitCode::FAILURE
+        }
     }
 }
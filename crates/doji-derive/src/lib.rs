@@ -0,0 +1,495 @@
+//! Derives for converting Rust types to and from doji values.
+//!
+//! Structs with named fields become maps keyed by their field names, and can be converted back
+//! from either maps or records with those fields. Newtype structs become the value they wrap.
+//! Unit enum variants become strings of their names, and other variants become maps with the
+//! name of the variant under `type`, alongside their fields (or their single value under
+//! `value`).
+//!
+//! Fields can be annotated with `#[doji(rename = "name")]`, `#[doji(default)]` to fall back to
+//! their default when missing, and `#[doji(skip)]` to leave them out entirely. Variants can be
+//! renamed too.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    Attribute, Data, DeriveInput, Error, Fields, Generics, Ident, LitStr, Result, Type,
+    WhereClause, parse_macro_input, parse_quote,
+};
+
+/// Derives `IntoValue`, so that the type can be handed to scripts.
+///
+/// ```
+/// use doji::{Engine, IntoValue};
+///
+/// #[derive(IntoValue)]
+/// struct Config {
+///     name: String,
+///     #[doji(rename = "max-retries")]
+///     retries: u32,
+///     #[doji(skip)]
+///     cache: Vec<String>,
+/// }
+///
+/// #[derive(IntoValue)]
+/// enum Shape {
+///     Circle { radius: i64 },
+///     Named(String),
+///     Empty,
+/// }
+///
+/// let config = Config { name: "doji".into(), retries: 3, cache: Vec::new() };
+/// let shapes = vec![Shape::Circle { radius: 2 }, Shape::Named("star".into()), Shape::Empty];
+/// let engine = Engine::builder()
+///     .global("config", config)
+///     .global("shapes", shapes)
+///     .build();
+///
+/// let config: (String, i64, bool) = engine
+///     .evaluate_inline(r#"[config.name, config["max-retries"], "cache" in config]"#)
+///     .unwrap();
+/// assert_eq!(config, ("doji".to_string(), 3, false));
+///
+/// let shapes: (String, i64, String, String) = engine
+///     .evaluate_inline("[shapes[0].type, shapes[0].radius, shapes[1].value, shapes[2]]")
+///     .unwrap();
+/// assert_eq!(shapes, ("Circle".into(), 2, "star".into(), "Empty".into()));
+/// ```
+///
+/// Skipped fields of variants are never bound, so they don't cause warnings.
+///
+/// ```
+/// #![deny(unused_variables)]
+///
+/// #[derive(doji::IntoValue)]
+/// enum Event {
+///     Click {
+///         x: i64,
+///         #[doji(skip)]
+///         handled: bool,
+///     },
+/// }
+/// ```
+///
+/// Tuple structs and variants can only have a single field.
+///
+/// ```compile_fail
+/// #[derive(doji::IntoValue)]
+/// struct Point(i64, i64);
+/// ```
+#[proc_macro_derive(IntoValue, attributes(doji))]
+pub fn derive_into_value(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    into_value(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Derives `TryFromValue`, so that the type can be converted from the values of scripts.
+///
+/// ```
+/// use doji::{Engine, TryFromValue};
+///
+/// #[derive(Debug, PartialEq, TryFromValue)]
+/// struct Config {
+///     name: String,
+///     #[doji(default)]
+///     verbose: bool,
+///     #[doji(skip)]
+///     cache: Vec<String>,
+/// }
+///
+/// #[derive(Debug, PartialEq, TryFromValue)]
+/// enum Shape {
+///     Circle { radius: i64 },
+///     #[doji(rename = "named")]
+///     Named(String),
+///     Empty,
+/// }
+///
+/// let engine = Engine::builder().build();
+///
+/// let config: Config = engine.evaluate_inline(r#"{ name: "doji" }"#).unwrap();
+/// assert_eq!(config, Config { name: "doji".into(), verbose: false, cache: Vec::new() });
+///
+/// // Records with the same fields can be converted too.
+/// let source = r#"struct Options { name, verbose } Options { name: "doji", verbose: true }"#;
+/// let config: Config = engine.evaluate_inline(source).unwrap();
+/// assert_eq!(config, Config { name: "doji".into(), verbose: true, cache: Vec::new() });
+///
+/// let source = r#"[{ type: "Circle", radius: 2 }, { type: "named", value: "star" }, "Empty"]"#;
+/// let shapes: Vec<Shape> = engine.evaluate_inline(source).unwrap();
+/// assert_eq!(
+///     shapes,
+///     [Shape::Circle { radius: 2 }, Shape::Named("star".into()), Shape::Empty],
+/// );
+///
+/// let error = engine.evaluate_inline::<Shape>(r#""Square""#).unwrap_err();
+/// assert_eq!(error.to_string(), "unknown variant `Square` of Shape");
+/// ```
+///
+/// Only the options `rename`, `default` and `skip` are recognised.
+///
+/// ```compile_fail
+/// #[derive(doji::TryFromValue)]
+/// struct Config {
+///     #[doji(flatten)]
+///     name: String,
+/// }
+/// ```
+#[proc_macro_derive(TryFromValue, attributes(doji))]
+pub fn derive_try_from_value(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    try_from_value(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn into_value(input: &DeriveInput) -> Result<TokenStream2> {
+    let name = &input.ident;
+    let body = match &input.data {
+        Data::Struct(data) => match Shape::of(&data.fields)? {
+            Shape::Named(fields) => {
+                let map = map_into_value(&fields, None, |field| {
+                    let ident = &field.ident;
+                    quote!(self.#ident)
+                });
+                quote!(#map)
+            }
+            Shape::Newtype => quote!(::doji::IntoValue::into_value(self.0, cx)),
+            Shape::Unit => quote!(::core::result::Result::Ok(::doji::Value::NIL)),
+        },
+        Data::Enum(data) => {
+            let arms = data
+                .variants
+                .iter()
+                .map(|variant| {
+                    let ident = &variant.ident;
+                    let tag = Attrs::parse(&variant.attrs)?.name(ident);
+                    Ok(match Shape::of(&variant.fields)? {
+                        Shape::Named(fields) => {
+                            // Skipped fields are left to `..`, so that they aren't unused bindings.
+                            let bindings = fields
+                                .iter()
+                                .filter(|field| !field.attrs.skip)
+                                .map(|field| &field.ident);
+                            let map = map_into_value(&fields, Some(&tag), |field| {
+                                let ident = &field.ident;
+                                quote!(#ident)
+                            });
+                            quote!(Self::#ident { #(#bindings,)* .. } => #map)
+                        }
+                        Shape::Newtype => {
+                            let map = tagged_map(
+                                &tag,
+                                quote! {
+                                    (
+                                        ::doji::HashKey::string(cx, "value"),
+                                        ::doji::IntoValue::into_value(value, cx)?,
+                                    ),
+                                },
+                            );
+                            quote!(Self::#ident(value) => #map)
+                        }
+                        Shape::Unit => {
+                            quote!(Self::#ident => ::doji::IntoValue::into_value(#tag, cx))
+                        }
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            quote! {
+                match self {
+                    #(#arms,)*
+                }
+            }
+        }
+        Data::Union(_) => return Err(Error::new_spanned(name, "unions cannot be converted")),
+    };
+
+    let generics = add_lifetime(&input.generics);
+    let (impl_generics, _, _) = generics.split_for_impl();
+    let (_, ty_generics, where_clause) = input.generics.split_for_impl();
+    let bounds = field_types(input)
+        .map(|ty| quote!(#ty: ::doji::IntoValue<'gc>))
+        .collect::<Vec<_>>();
+    let where_clause = extend_where_clause(where_clause, &bounds);
+
+    Ok(quote! {
+        impl #impl_generics ::doji::IntoValue<'gc> for #name #ty_generics #where_clause {
+            fn into_value(
+                self,
+                cx: &::doji::Context<'gc>,
+            ) -> ::core::result::Result<::doji::Value<'gc>, ::doji::ErrorPtr<'gc>> {
+                #body
+            }
+        }
+    })
+}
+
+fn try_from_value(input: &DeriveInput) -> Result<TokenStream2> {
+    let name = &input.ident;
+    let type_name = name.to_string();
+    let body = match &input.data {
+        Data::Struct(data) => match Shape::of(&data.fields)? {
+            Shape::Named(fields) => {
+                let from_map = fields_from(&fields, FieldSource::Map);
+                let from_record = fields_from(&fields, FieldSource::Record);
+                quote! {
+                    if let Ok(record) = value.try_into::<::doji::RecordPtr<'gc>>(cx) {
+                        let record = record.borrow();
+                        return Ok(Self { #from_record });
+                    }
+                    let map: ::doji::MapPtr<'gc> = value.try_into(cx)?;
+                    let map = map.borrow();
+                    Ok(Self { #from_map })
+                }
+            }
+            Shape::Newtype => quote!(Ok(Self(value.try_into(cx)?))),
+            Shape::Unit => quote!(Ok(Self)),
+        },
+        Data::Enum(data) => {
+            let mut unit_arms = Vec::new();
+            let mut map_arms = Vec::new();
+            for variant in &data.variants {
+                let ident = &variant.ident;
+                let tag = Attrs::parse(&variant.attrs)?.name(ident);
+                match Shape::of(&variant.fields)? {
+                    Shape::Named(fields) => {
+                        let init = fields_from(&fields, FieldSource::Map);
+                        map_arms.push(quote!(#tag => Ok(Self::#ident { #init })));
+                    }
+                    Shape::Newtype => {
+                        map_arms.push(quote!(#tag => Ok(Self::#ident(map.field(cx, "value")?))));
+                    }
+                    Shape::Unit => unit_arms.push(quote!(#tag => Ok(Self::#ident))),
+                }
+            }
+            quote! {
+                let unknown = |variant: &str| {
+                    ::doji::ErrorValue::new_message_ptr(
+                        cx,
+                        format!("unknown variant `{}` of {}", variant, #type_name),
+                    )
+                };
+                if let Some(variant) = value.as_str() {
+                    return match variant {
+                        #(#unit_arms,)*
+                        variant => Err(unknown(variant)),
+                    };
+                }
+                let map: ::doji::MapPtr<'gc> = value.try_into(cx)?;
+                let map = map.borrow();
+                let variant: String = map.field(cx, "type")?;
+                match variant.as_str() {
+                    #(#map_arms,)*
+                    variant => Err(unknown(variant)),
+                }
+            }
+        }
+        Data::Union(_) => return Err(Error::new_spanned(name, "unions cannot be converted")),
+    };
+
+    let generics = add_lifetime(&input.generics);
+    let (impl_generics, _, _) = generics.split_for_impl();
+    let (_, ty_generics, where_clause) = input.generics.split_for_impl();
+    let bounds = field_types(input)
+        .map(|ty| quote!(#ty: ::doji::TryFromValue<'gc>))
+        .collect::<Vec<_>>();
+    let where_clause = extend_where_clause(where_clause, &bounds);
+
+    Ok(quote! {
+        impl #impl_generics ::doji::TryFromValue<'gc> for #name #ty_generics #where_clause {
+            fn try_from_value(
+                value: ::doji::Value<'gc>,
+                cx: &::doji::Context<'gc>,
+            ) -> ::core::result::Result<Self, ::doji::ErrorPtr<'gc>> {
+                #body
+            }
+        }
+    })
+}
+
+/// Builds a map from the fields that aren't skipped, with the value of each given by `access`.
+fn map_into_value(
+    fields: &[Field],
+    tag: Option<&String>,
+    access: impl Fn(&Field) -> TokenStream2,
+) -> TokenStream2 {
+    let entries = fields
+        .iter()
+        .filter(|field| !field.attrs.skip)
+        .map(|field| {
+            let key = field.attrs.name(&field.ident);
+            let value = access(field);
+            quote! {
+                (
+                    ::doji::HashKey::string(cx, #key),
+                    ::doji::IntoValue::into_value(#value, cx)?,
+                ),
+            }
+        })
+        .collect::<TokenStream2>();
+    match tag {
+        Some(tag) => tagged_map(tag, entries),
+        None => quote!(::core::result::Result::Ok(
+            ::doji::MapValue::new_ptr(cx, [#entries]).into()
+        )),
+    }
+}
+
+fn tagged_map(tag: &str, entries: TokenStream2) -> TokenStream2 {
+    quote! {
+        ::core::result::Result::Ok(
+            ::doji::MapValue::new_ptr(
+                cx,
+                [
+                    (
+                        ::doji::HashKey::string(cx, "type"),
+                        ::doji::IntoValue::into_value(#tag, cx)?,
+                    ),
+                    #entries
+                ],
+            )
+            .into(),
+        )
+    }
+}
+
+/// Where the fields of a struct or variant are converted from.
+#[derive(Clone, Copy)]
+enum FieldSource {
+    /// The entries of `map`, a borrowed `MapValue`.
+    Map,
+    /// The fields of `record`, a borrowed `RecordValue`.
+    Record,
+}
+
+/// Initializes the fields from the source.
+fn fields_from(fields: &[Field], source: FieldSource) -> TokenStream2 {
+    let (source, convert, convert_or_default) = match source {
+        FieldSource::Map => (quote!(map), quote!(field), quote!(field_or_default)),
+        FieldSource::Record => (
+            quote!(record),
+            quote!(convert_field),
+            quote!(convert_field_or_default),
+        ),
+    };
+    let mut init = TokenStream2::new();
+    for field in fields {
+        let ident = &field.ident;
+        let key = field.attrs.name(ident);
+        init.extend(if field.attrs.skip {
+            quote!(#ident: ::core::default::Default::default(),)
+        } else if field.attrs.default {
+            quote!(#ident: #source.#convert_or_default(cx, #key)?,)
+        } else {
+            quote!(#ident: #source.#convert(cx, #key)?,)
+        });
+    }
+    init
+}
+
+fn add_lifetime(generics: &Generics) -> Generics {
+    let mut generics = generics.clone();
+    generics.params.insert(0, parse_quote!('gc));
+    generics
+}
+
+fn extend_where_clause(
+    where_clause: Option<&WhereClause>,
+    bounds: &[TokenStream2],
+) -> TokenStream2 {
+    let predicates = where_clause.map(|where_clause| &where_clause.predicates);
+    quote!(where #predicates #(#bounds,)*)
+}
+
+/// The types of all the fields that are converted, which must implement the conversion trait.
+fn field_types(input: &DeriveInput) -> impl Iterator<Item = &Type> {
+    let fields: Vec<&syn::Field> = match &input.data {
+        Data::Struct(data) => data.fields.iter().collect(),
+        Data::Enum(data) => data
+            .variants
+            .iter()
+            .flat_map(|variant| &variant.fields)
+            .collect(),
+        Data::Union(_) => Vec::new(),
+    };
+    fields
+        .into_iter()
+        .filter(|field| !Attrs::parse(&field.attrs).is_ok_and(|attrs| attrs.skip))
+        .map(|field| &field.ty)
+}
+
+/// How the fields of a struct or variant are laid out.
+enum Shape {
+    Named(Vec<Field>),
+    Newtype,
+    Unit,
+}
+
+impl Shape {
+    fn of(fields: &Fields) -> Result<Self> {
+        match fields {
+            Fields::Named(named) => named
+                .named
+                .iter()
+                .map(|field| {
+                    Ok(Field {
+                        ident: field.ident.clone().expect("named fields have names"),
+                        attrs: Attrs::parse(&field.attrs)?,
+                    })
+                })
+                .collect::<Result<_>>()
+                .map(Shape::Named),
+            Fields::Unnamed(unnamed) if unnamed.unnamed.len() == 1 => Ok(Shape::Newtype),
+            Fields::Unnamed(unnamed) => Err(Error::new_spanned(
+                unnamed,
+                "only tuple structs and variants with a single field can be converted, use named fields instead",
+            )),
+            Fields::Unit => Ok(Shape::Unit),
+        }
+    }
+}
+
+struct Field {
+    ident: Ident,
+    attrs: Attrs,
+}
+
+/// The options given in `#[doji(...)]` attributes.
+#[derive(Default)]
+struct Attrs {
+    rename: Option<String>,
+    default: bool,
+    skip: bool,
+}
+
+impl Attrs {
+    fn parse(attrs: &[Attribute]) -> Result<Self> {
+        let mut parsed = Attrs::default();
+        for attr in attrs.iter().filter(|attr| attr.path().is_ident("doji")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    let name: LitStr = meta.value()?.parse()?;
+                    parsed.rename = Some(name.value());
+                } else if meta.path.is_ident("default") {
+                    parsed.default = true;
+                } else if meta.path.is_ident("skip") {
+                    parsed.skip = true;
+                } else {
+                    return Err(meta.error("expected `rename`, `default` or `skip`"));
+                }
+                Ok(())
+            })?;
+        }
+        Ok(parsed)
+    }
+
+    /// The name the field or variant has in scripts.
+    fn name(&self, ident: &Ident) -> String {
+        self.rename
+            .clone()
+            .unwrap_or_else(|| ident.to_string().trim_start_matches("r#").to_string())
+    }
+}
@@ -1,6 +1,15 @@
-use gc_arena::{Collect, Gc};
+use gc_arena::{
+    Collect, Gc,
+    lock::{GcRefLock, RefLock},
+};
 
-use crate::{context::Context, function::FunctionPtr, value::Value};
+use crate::{
+    context::Context,
+    error::{EngineError, ErrorPtr, ErrorValue},
+    fiber::FiberPtr,
+    function::FunctionPtr,
+    value::Value,
+};
 
 pub type ClosurePtr<'gc> = Gc<'gc, ClosureValue<'gc>>;
 
@@ -33,13 +42,137 @@ impl<'gc> ClosureValue<'gc> {
     pub fn function(&self) -> FunctionPtr<'gc> {
         self.function
     }
+
+    /// Checks that the closure can be called with the number of arguments.
+    pub fn check_arity(&self, cx: &Context<'gc>, arg_count: usize) -> Result<(), ErrorPtr<'gc>> {
+        let arity = self.function.arity();
+        if arg_count == arity {
+            return Ok(());
+        }
+        Err(ErrorValue::new_message_ptr(
+            cx,
+            format!(
+                "expected {} argument{}, but got {}",
+                arity,
+                if arity == 1 { "" } else { "s" },
+                arg_count
+            ),
+        ))
+    }
+
+    pub fn upvalue(&self, index: usize) -> Result<UpvaluePtr<'gc>, EngineError> {
+        self.upvalues
+            .get(index)
+            .copied()
+            .ok_or(EngineError::InvalidUpvalueIndex(index))
+    }
 }
 
-pub type UpvaluePtr<'gc> = Gc<'gc, Upvalue<'gc>>;
+pub type UpvaluePtr<'gc> = GcRefLock<'gc, Upvalue<'gc>>;
 
 #[derive(Collect, Debug)]
 #[collect(no_drop)]
 pub enum Upvalue<'gc> {
-    Open(usize),
+    /// The captured variable still lives on the stack of the fiber, at the given slot. Closures
+    /// can be spawned onto other fibers, so we need to know whose stack it is.
+    Open(FiberPtr<'gc>, usize),
     Closed(Value<'gc>),
 }
+
+impl<'gc> Upvalue<'gc> {
+    pub fn new_open_ptr(cx: &Context<'gc>, fiber: FiberPtr<'gc>, slot: usize) -> UpvaluePtr<'gc> {
+        Gc::new(cx.mutation(), RefLock::new(Self::Open(fiber, slot)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Engine;
+
+    fn evaluate(source: &str) -> Vec<i64> {
+        Engine::builder().build().evaluate_inline(source).unwrap()
+    }
+
+    #[test]
+    fn closures_share_captured_variables() {
+        assert_eq!(
+            evaluate(
+                "let x = 1;
+                let get = fn() { x };
+                let set = fn(value) { x = value };
+                set(5);
+                [get(), x]"
+            ),
+            [5, 5]
+        );
+    }
+
+    #[test]
+    fn captured_variables_outlive_their_function() {
+        assert_eq!(
+            evaluate(
+                "fn counter() {
+                    let n = 0;
+                    fn() { n = n + 1; n }
+                }
+                let a = counter();
+                let b = counter();
+                a();
+                a();
+                [a(), b()]"
+            ),
+            [3, 1]
+        );
+    }
+
+    #[test]
+    fn captures_through_enclosing_functions() {
+        assert_eq!(
+            evaluate(
+                "fn outer() {
+                    let a = 1;
+                    fn middle() { fn inner() { a = a + 1; a } inner }
+                    let inner = middle();
+                    [inner(), inner(), a]
+                }
+                outer()"
+            ),
+            [2, 3, 3]
+        );
+    }
+
+    #[test]
+    fn each_iteration_captures_its_own_variables() {
+        assert_eq!(
+            evaluate(
+                "let fs = [];
+                let i = 0;
+                while i < 3 {
+                    let j = i;
+                    fs.push(fn() { j = j + 10; j });
+                    i = i + 1;
+                }
+                let first = fs[0]();
+                [first, fs[0](), fs[1](), fs[2]()]"
+            ),
+            [10, 20, 11, 12]
+        );
+    }
+
+    #[test]
+    fn variables_declared_outside_a_loop_are_shared() {
+        assert_eq!(
+            evaluate(
+                "let fs = [];
+                let i = 0;
+                while i < 3 {
+                    fs.push(fn() { i });
+                    i = i + 1;
+                }
+                let first = fs[0]();
+                [first, fs[2]()]"
+            ),
+            [3, 3]
+        );
+    }
+}
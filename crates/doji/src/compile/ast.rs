@@ -0,0 +1,186 @@
+use crate::compile::span::Span;
+
+#[derive(Debug)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+
+    /// Whether this expression ends with a block, and can therefore be used as a statement
+    /// without a trailing semicolon.
+    pub fn is_block_like(&self) -> bool {
+        matches!(
+            self.kind,
+            ExprKind::Block(_)
+                | ExprKind::Function(_)
+                | ExprKind::If(..)
+                | ExprKind::While(..)
+                | ExprKind::Loop(_)
+                | ExprKind::Try(..)
+                | ExprKind::Match(..)
+        )
+    }
+
+    pub fn is_assignable(&self) -> bool {
+        matches!(
+            self.kind,
+            ExprKind::Identifier(_) | ExprKind::Index(..) | ExprKind::Field(..)
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum ExprKind {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Identifier(String),
+    List(Vec<Expr>),
+    Map(Vec<(Expr, Expr)>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Logical(LogicalOp, Box<Expr>, Box<Expr>),
+    Assign(Box<Expr>, Box<Expr>),
+    Call(Box<Expr>, Vec<Expr>),
+    /// Calls the method with the name on the receiver, such as `xs.push(1)`.
+    Method(Box<Expr>, Ident, Vec<Expr>),
+    Index(Box<Expr>, Box<Expr>),
+    Field(Box<Expr>, Ident),
+    /// Creates a record of the type, such as `Point { x: 1, y: 2 }`.
+    Record(Box<Expr>, Vec<(Ident, Expr)>),
+    Function(FunctionExpr),
+    Block(Block),
+    If(Box<Expr>, Block, Option<Box<Expr>>),
+    While(Box<Expr>, Block),
+    Loop(Block),
+    Try(Block, Option<Ident>, Block),
+    /// Runs the body of the first arm whose pattern matches the value.
+    Match(Box<Expr>, Vec<MatchArm>),
+    Spawn(Box<Expr>),
+    Yield(Box<Expr>),
+    Join(Box<Expr>),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum UnaryOp {
+    Negate,
+    Not,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    In,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum LogicalOp {
+    And,
+    Or,
+}
+
+#[derive(Debug)]
+pub struct Ident {
+    pub name: String,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub struct FunctionExpr {
+    pub name: Option<Ident>,
+    pub params: Vec<Ident>,
+    pub body: Block,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub guard: Option<Expr>,
+    pub body: Expr,
+}
+
+#[derive(Debug)]
+pub struct Pattern {
+    pub kind: PatternKind,
+    pub span: Span,
+}
+
+impl Pattern {
+    pub fn new(kind: PatternKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+
+    /// Whether this pattern matches every value.
+    pub fn is_irrefutable(&self) -> bool {
+        matches!(self.kind, PatternKind::Wildcard | PatternKind::Binding(_))
+    }
+}
+
+#[derive(Debug)]
+pub enum PatternKind {
+    /// Matches anything, written `_`.
+    Wildcard,
+    /// Matches anything, binding it to a new local.
+    Binding(Ident),
+    /// Matches values equal to the literal expression.
+    Literal(Expr),
+    /// Matches the variant of the enum, such as `Shape.Circle(r)`, if its fields match the
+    /// patterns.
+    Variant(Ident, Ident, Vec<Pattern>),
+}
+
+#[derive(Debug)]
+pub struct Block {
+    pub stmts: Vec<Stmt>,
+    pub expr: Option<Box<Expr>>,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+impl Stmt {
+    pub fn new(kind: StmtKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+}
+
+#[derive(Debug)]
+pub enum StmtKind {
+    Let(Ident, Expr),
+    Function(FunctionExpr),
+    /// Declares a record type with the named fields.
+    Struct(Ident, Vec<Ident>),
+    /// Declares an enum type with the variants, each of which has the named fields.
+    Enum(Ident, Vec<(Ident, Vec<Ident>)>),
+    Return(Option<Expr>),
+    Break(Option<Expr>),
+    Continue,
+    Throw(Expr),
+    /// Imports the module at the path, binding the listed exports as locals.
+    Import(Vec<Ident>, String),
+    /// A `let`, function, struct or enum statement whose variable is exported from the module.
+    Export(Box<Stmt>),
+    Expr(Expr),
+}
@@ -0,0 +1,1183 @@
+use core::mem;
+
+use std::collections::HashSet;
+
+use gc_arena::Gc;
+
+use crate::{
+    compile::{
+        SourceKind,
+        ast::{
+            BinaryOp, Block, Expr, ExprKind, FunctionExpr, Ident, LogicalOp, MatchArm, Pattern,
+            PatternKind, Stmt, StmtKind, UnaryOp,
+        },
+        diagnostic::Issue,
+        span::Span,
+    },
+    context::Context,
+    function::{Capture, Constant, Function, FunctionBuilder, FunctionPtr, NO_OPERAND, opcode},
+    record::RecordType,
+    source::SourceId,
+    string::StringValue,
+    variant::{EnumType, EnumTypePtr, Variant},
+};
+
+const MAX_OPERAND: usize = (1 << 24) - 1;
+
+pub struct Compiler<'a, 'gc> {
+    cx: &'a Context<'gc>,
+    source: SourceId,
+    kind: SourceKind,
+    // The functions currently being compiled, from the outermost to the innermost.
+    functions: Vec<FunctionState<'gc>>,
+    // The span of the node currently being compiled, which emitted instructions and issues are
+    // attributed to.
+    span: Span,
+    issues: Vec<Issue>,
+}
+
+struct FunctionState<'gc> {
+    builder: FunctionBuilder<'gc>,
+    locals: Vec<Local<'gc>>,
+    // The variables captured from enclosing functions, in the order of their upvalue indices.
+    captures: Vec<Capture>,
+    // The loops enclosing the code currently being compiled, from the outermost to the innermost.
+    loops: Vec<Loop>,
+    // The number of `try` bodies enclosing the code currently being compiled.
+    try_depth: usize,
+    scope_depth: usize,
+    // The number of values this function will have on the stack at the current point in the code,
+    // relative to the bottom of its frame. This is how we know which slot a local lives in.
+    stack_size: usize,
+}
+
+struct Local<'gc> {
+    // Empty for the locals that hold values the compiler needs to keep around.
+    name: String,
+    span: Span,
+    slot: usize,
+    depth: usize,
+    used: bool,
+    // The offset of the first instruction the local is in scope for.
+    start: usize,
+    // The enum declared by this local, which lets matches on its variants be checked when they
+    // are compiled.
+    enum_type: Option<EnumTypePtr<'gc>>,
+}
+
+struct Loop {
+    // Where `continue` jumps to.
+    start: usize,
+    // The stack size on entry to the loop, which `break` and `continue` unwind the stack to.
+    stack_size: usize,
+    // The try depth on entry to the loop, since `break` and `continue` must end any `try` bodies
+    // they jump out of.
+    try_depth: usize,
+    // The `break` jumps waiting to be patched with the end of the loop.
+    breaks: Vec<usize>,
+}
+
+enum Variable {
+    Local(u32),
+    Upvalue(u32),
+    // The operand is the constant holding the name of the global.
+    Global(u32),
+}
+
+impl<'a, 'gc> Compiler<'a, 'gc> {
+    pub fn new(cx: &'a Context<'gc>, source: SourceId, kind: SourceKind) -> Self {
+        Self {
+            cx,
+            source,
+            kind,
+            functions: Vec::new(),
+            span: Span::default(),
+            issues: Vec::new(),
+        }
+    }
+
+    /// Compiles the block as the body of the top-level function. Compilation carries on past
+    /// errors so that all of them are reported, in which case the returned function must not be
+    /// run.
+    pub fn compile_script(mut self, block: &Block) -> (FunctionPtr<'gc>, Vec<Issue>) {
+        let function = self.function_body(Some("<script>"), &[], block);
+        (function, self.issues)
+    }
+
+    fn function_body(
+        &mut self,
+        name: Option<&str>,
+        params: &[Ident],
+        body: &Block,
+    ) -> FunctionPtr<'gc> {
+        let mut builder = Function::builder();
+        if let Some(name) = name {
+            builder = builder.name(StringValue::new_ptr(self.cx, name.to_string()));
+        }
+        builder.arity(params.len());
+        builder.source(self.source);
+        self.functions.push(FunctionState {
+            builder,
+            locals: Vec::new(),
+            captures: Vec::new(),
+            loops: Vec::new(),
+            try_depth: 0,
+            scope_depth: 0,
+            stack_size: 0,
+        });
+
+        // Arguments are placed at the bottom of the frame by the caller, in order.
+        self.declared_twice(params.iter(), "parameter");
+        self.begin_scope();
+        for param in params {
+            // A duplicate is already an error, so the parameter it shadows isn't also reported as
+            // unused.
+            if let Some(shadowed) = self
+                .current()
+                .locals
+                .iter_mut()
+                .find(|local| local.name == param.name)
+            {
+                shadowed.used = true;
+            }
+            self.current().stack_size += 1;
+            self.declare_local(param);
+        }
+        self.block(body);
+        self.end_scope();
+        self.emit(opcode::RETURN, NO_OPERAND);
+
+        let function = self
+            .functions
+            .pop()
+            .expect("function state is pushed above");
+        function.builder.build_ptr(self.cx)
+    }
+
+    fn function(&mut self, function: &FunctionExpr) {
+        let name = function.name.as_ref().map(|name| name.name.as_str());
+        let compiled = self.function_body(name, &function.params, &function.body);
+        let index = self.current().builder.function(compiled);
+        let operand = self.operand(index, "functions");
+        self.emit(opcode::CLOSURE, operand);
+    }
+
+    fn block(&mut self, block: &Block) {
+        self.begin_scope();
+
+        // The keyword of the last statement if it jumped away, making whatever follows it
+        // unreachable.
+        let mut jumped = None;
+        for stmt in &block.stmts {
+            if let Some(keyword) = jumped.take() {
+                self.unreachable(stmt.span, keyword);
+            }
+            self.stmt(stmt);
+            jumped = match stmt.kind {
+                StmtKind::Return(_) => Some("return"),
+                StmtKind::Break(_) => Some("break"),
+                StmtKind::Continue => Some("continue"),
+                StmtKind::Throw(_) => Some("throw"),
+                _ => jumped,
+            };
+        }
+        match &block.expr {
+            Some(expr) => {
+                if let Some(keyword) = jumped {
+                    self.unreachable(expr.span, keyword);
+                }
+                self.expr(expr);
+            }
+            None => {
+                self.span = block.span;
+                self.emit(opcode::NIL, NO_OPERAND);
+            }
+        }
+
+        self.end_scope();
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        self.span = stmt.span;
+        match &stmt.kind {
+            StmtKind::Let(name, value) => {
+                // The value is left on the stack, and becomes the slot of the new local.
+                self.expr(value);
+                self.declare_local(name);
+            }
+            StmtKind::Function(function) => {
+                let name = function
+                    .name
+                    .as_ref()
+                    .expect("function statement has a name");
+                // Declare the local before compiling the function, so that it can call itself.
+                let slot = self.current().stack_size;
+                self.add_local(name, slot);
+                self.function(function);
+
+                // The top-level functions of scripts are also defined as globals, so that the host
+                // can call them by name and later scripts can refer to them.
+                if self.kind == SourceKind::Script && self.at_top_level() {
+                    self.define_global(name, slot);
+                }
+            }
+            StmtKind::Struct(name, fields) => {
+                if fields.is_empty() {
+                    // `Name {}` would be a block, so there would be no way to create one.
+                    self.error(format!("struct `{}` has no fields", name.name));
+                }
+                self.declared_twice(fields.iter(), "field");
+                let fields = fields.iter().map(|field| field.name.clone()).collect();
+                let ty = RecordType::new_ptr(self.cx, name.name.clone(), fields);
+                self.constant(Constant::RecordType(ty));
+                self.declare_local(name);
+            }
+            StmtKind::Enum(name, variants) => {
+                self.declared_twice(variants.iter().map(|(variant, _)| variant), "variant");
+                let variants = variants
+                    .iter()
+                    .map(|(variant, fields)| {
+                        self.declared_twice(fields.iter(), "field");
+                        let fields = fields.iter().map(|field| field.name.clone()).collect();
+                        Variant::new(variant.name.clone(), fields)
+                    })
+                    .collect();
+                let ty = EnumType::new_ptr(self.cx, name.name.clone(), variants);
+                self.constant(Constant::EnumType(ty));
+                self.declare_local(name);
+                let local = self.current().locals.last_mut().expect("declared above");
+                local.enum_type = Some(ty);
+            }
+            StmtKind::Return(value) => {
+                match value {
+                    Some(value) => self.expr(value),
+                    None => self.emit(opcode::NIL, NO_OPERAND),
+                }
+                self.emit(opcode::RETURN, NO_OPERAND);
+            }
+            StmtKind::Break(value) => {
+                match value {
+                    Some(value) => self.expr(value),
+                    None => self.emit(opcode::NIL, NO_OPERAND),
+                }
+                let stack_size = self.current().stack_size;
+                let Some(base) = self.current().loops.last().map(|l| l.stack_size) else {
+                    self.error("`break` outside of a loop");
+                    return;
+                };
+
+                // Leave only the value of the loop on top of the stack it was entered with.
+                self.end_loop_tries();
+                self.drop_values(stack_size - 1 - base);
+                let jump = self.emit_jump(opcode::JUMP);
+                let innermost = self.current().loops.last_mut().expect("checked above");
+                innermost.breaks.push(jump);
+
+                // Nothing after the break runs, so carry on as if the statement left the stack as
+                // it was.
+                self.current().stack_size = stack_size - 1;
+            }
+            StmtKind::Continue => {
+                let stack_size = self.current().stack_size;
+                let Some((start, base)) =
+                    self.current().loops.last().map(|l| (l.start, l.stack_size))
+                else {
+                    self.error("`continue` outside of a loop");
+                    return;
+                };
+
+                self.end_loop_tries();
+                if stack_size > base {
+                    self.emit(opcode::NIL, NO_OPERAND);
+                    self.drop_values(stack_size - base);
+                    self.emit(opcode::POP, NO_OPERAND);
+                }
+                self.emit_loop(start);
+                self.current().stack_size = stack_size;
+            }
+            StmtKind::Throw(value) => {
+                self.expr(value);
+                self.emit(opcode::THROW, NO_OPERAND);
+            }
+            StmtKind::Import(names, path) => {
+                let path = self.string_constant(path);
+                if names.is_empty() {
+                    self.emit(opcode::IMPORT, path);
+                    self.emit(opcode::POP, NO_OPERAND);
+                }
+                // Modules are only ever evaluated once, so importing again for each name is cheap.
+                for name in names {
+                    self.emit(opcode::IMPORT, path);
+                    let export = self.string_constant(&name.name);
+                    self.emit(opcode::GET_EXPORT, export);
+                    self.declare_local(name);
+                }
+            }
+            StmtKind::Export(inner) => {
+                if !self.at_top_level() {
+                    self.error(
+                        "only top-level variables, functions, structs and enums can be exported",
+                    );
+                }
+                self.stmt(inner);
+                self.span = stmt.span;
+                let name = match &inner.kind {
+                    StmtKind::Let(name, _) => name,
+                    StmtKind::Function(function) => function
+                        .name
+                        .as_ref()
+                        .expect("function statement has a name"),
+                    StmtKind::Struct(name, _) | StmtKind::Enum(name, _) => name,
+                    _ => unreachable!("parser only allows exporting declarations"),
+                };
+                let function = self.functions.len() - 1;
+                let slot = self
+                    .resolve_local(function, &name.name)
+                    .expect("declared by the statement above");
+                self.emit(opcode::GET_LOCAL, slot as u32);
+                let export = self.string_constant(&name.name);
+                self.emit(opcode::EXPORT, export);
+            }
+            StmtKind::Expr(expr) => {
+                self.expr(expr);
+                self.emit(opcode::POP, NO_OPERAND);
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        let parent_span = mem::replace(&mut self.span, expr.span);
+        self.expr_kind(&expr.kind);
+        self.span = parent_span;
+    }
+
+    fn expr_kind(&mut self, kind: &ExprKind) {
+        match kind {
+            ExprKind::Nil => self.emit(opcode::NIL, NO_OPERAND),
+            ExprKind::Bool(true) => self.emit(opcode::TRUE, NO_OPERAND),
+            ExprKind::Bool(false) => self.emit(opcode::FALSE, NO_OPERAND),
+            ExprKind::Int(int) => {
+                if (0..=MAX_OPERAND as i64).contains(int) {
+                    self.emit(opcode::INT, *int as u32);
+                } else {
+                    self.constant(Constant::Int(*int));
+                }
+            }
+            ExprKind::Float(float) => self.constant(Constant::Float(*float)),
+            ExprKind::String(string) => {
+                let string = StringValue::new_ptr(self.cx, string.clone());
+                self.constant(Constant::String(string));
+            }
+            ExprKind::Identifier(name) => match self.resolve(name) {
+                Some(Variable::Local(slot)) => self.emit(opcode::GET_LOCAL, slot),
+                Some(Variable::Upvalue(index)) => self.emit(opcode::GET_UPVALUE, index),
+                Some(Variable::Global(name)) => self.emit(opcode::GET_GLOBAL, name),
+                // Keep the stack balanced even though the code will never run.
+                None => self.emit(opcode::NIL, NO_OPERAND),
+            },
+            ExprKind::List(items) => {
+                for item in items {
+                    self.expr(item);
+                }
+                let operand = self.operand(items.len(), "list items");
+                self.emit(opcode::LIST, operand);
+            }
+            ExprKind::Map(entries) => {
+                for (key, value) in entries {
+                    self.expr(key);
+                    self.expr(value);
+                }
+                let operand = self.operand(entries.len(), "map entries");
+                self.emit(opcode::MAP, operand);
+            }
+            ExprKind::Record(ty, fields) => {
+                // Each value sits above the name of the field it is for.
+                self.expr(ty);
+                for (i, (name, value)) in fields.iter().enumerate() {
+                    if fields[..i].iter().any(|(other, _)| other.name == name.name) {
+                        self.issues.push(Issue::error(
+                            format!("field `{}` is given twice", name.name),
+                            name.span,
+                        ));
+                    }
+                    let name = self.string_constant(&name.name);
+                    self.emit(opcode::CONST, name);
+                    self.expr(value);
+                }
+                let operand = self.operand(fields.len(), "record fields");
+                self.emit(opcode::RECORD, operand);
+            }
+            ExprKind::Unary(op, operand) => {
+                self.expr(operand);
+                match op {
+                    UnaryOp::Negate => self.emit(opcode::NEG, NO_OPERAND),
+                    UnaryOp::Not => self.emit(opcode::NOT, NO_OPERAND),
+                }
+            }
+            ExprKind::Binary(op, left, right) => {
+                self.expr(left);
+                self.expr(right);
+                let opcode = match op {
+                    BinaryOp::Add => opcode::ADD,
+                    BinaryOp::Sub => opcode::SUB,
+                    BinaryOp::Mul => opcode::MUL,
+                    BinaryOp::Div => opcode::DIV,
+                    BinaryOp::Mod => opcode::MOD,
+                    BinaryOp::Equal => opcode::EQ,
+                    BinaryOp::NotEqual => opcode::NE,
+                    BinaryOp::Less => opcode::LT,
+                    BinaryOp::LessEqual => opcode::LE,
+                    BinaryOp::Greater => opcode::GT,
+                    BinaryOp::GreaterEqual => opcode::GE,
+                    BinaryOp::In => opcode::IN,
+                };
+                self.emit(opcode, NO_OPERAND);
+            }
+            ExprKind::Logical(op, left, right) => {
+                self.expr(left);
+                // Leave the left value as the result if it decides the outcome on its own.
+                let jump = self.emit_jump(match op {
+                    LogicalOp::And => opcode::JUMP_IF_FALSE_OR_POP,
+                    LogicalOp::Or => opcode::JUMP_IF_TRUE_OR_POP,
+                });
+                self.expr(right);
+                self.patch_jump(jump);
+            }
+            ExprKind::Assign(target, value) => {
+                if let ExprKind::Index(list, index) = &target.kind {
+                    self.expr(list);
+                    self.expr(index);
+                    self.expr(value);
+                    self.emit(opcode::SET_INDEX, NO_OPERAND);
+                    return;
+                }
+                if let ExprKind::Field(object, name) = &target.kind {
+                    self.expr(object);
+                    self.expr(value);
+                    let name = self.string_constant(&name.name);
+                    self.emit(opcode::SET_FIELD, name);
+                    return;
+                }
+                self.expr(value);
+                match &target.kind {
+                    ExprKind::Identifier(name) => match self.resolve(name) {
+                        Some(Variable::Local(slot)) => self.emit(opcode::SET_LOCAL, slot),
+                        Some(Variable::Upvalue(index)) => self.emit(opcode::SET_UPVALUE, index),
+                        Some(Variable::Global(name)) => self.emit(opcode::SET_GLOBAL, name),
+                        None => {}
+                    },
+                    _ => unreachable!("parser only allows assignable targets"),
+                }
+            }
+            ExprKind::Call(callee, args) => {
+                self.expr(callee);
+                for arg in args {
+                    self.expr(arg);
+                }
+                self.emit(opcode::CALL, args.len() as u32);
+            }
+            ExprKind::Method(receiver, name, args) => {
+                // The name sits between the receiver and the arguments.
+                self.expr(receiver);
+                let name = self.string_constant(&name.name);
+                self.emit(opcode::CONST, name);
+                for arg in args {
+                    self.expr(arg);
+                }
+                self.emit(opcode::INVOKE, args.len() as u32);
+            }
+            ExprKind::Index(list, index) => {
+                self.expr(list);
+                self.expr(index);
+                self.emit(opcode::GET_INDEX, NO_OPERAND);
+            }
+            ExprKind::Field(object, name) => {
+                self.expr(object);
+                let name = self.string_constant(&name.name);
+                self.emit(opcode::GET_FIELD, name);
+            }
+            ExprKind::Function(function) => self.function(function),
+            ExprKind::Block(block) => self.block(block),
+            ExprKind::If(condition, then, otherwise) => {
+                self.expr(condition);
+                let else_jump = self.emit_jump(opcode::JUMP_IF_FALSE);
+                self.block(then);
+                let end_jump = self.emit_jump(opcode::JUMP);
+
+                // Only one of the branches runs, so the else branch starts from the same stack.
+                self.current().stack_size -= 1;
+                self.patch_jump(else_jump);
+                match otherwise {
+                    Some(otherwise) => self.expr(otherwise),
+                    None => self.emit(opcode::NIL, NO_OPERAND),
+                }
+                self.patch_jump(end_jump);
+            }
+            ExprKind::While(condition, body) => {
+                let start = self.begin_loop();
+                self.expr(condition);
+                let exit_jump = self.emit_jump(opcode::JUMP_IF_FALSE);
+                self.block(body);
+                self.emit(opcode::POP, NO_OPERAND);
+                self.emit_loop(start);
+
+                self.patch_jump(exit_jump);
+                self.emit(opcode::NIL, NO_OPERAND);
+                self.end_loop();
+            }
+            ExprKind::Loop(body) => {
+                let start = self.begin_loop();
+                self.block(body);
+                self.emit(opcode::POP, NO_OPERAND);
+                self.emit_loop(start);
+
+                // The loop can only be left with a `break`, which leaves a value on the stack.
+                self.current().stack_size += 1;
+                self.end_loop();
+            }
+            ExprKind::Try(body, name, handler) => {
+                let catch_jump = self.emit_jump(opcode::TRY_BEGIN);
+                self.current().try_depth += 1;
+                self.block(body);
+                self.current().try_depth -= 1;
+                self.emit(opcode::TRY_END, NO_OPERAND);
+                let end_jump = self.emit_jump(opcode::JUMP);
+
+                // The stack is unwound to where the try began before the error is pushed, so the
+                // error takes the place of the result of the body.
+                self.patch_jump(catch_jump);
+                self.begin_scope();
+                match name {
+                    Some(name) => self.declare_local(name),
+                    None => self.emit(opcode::POP, NO_OPERAND),
+                }
+                self.block(handler);
+                self.end_scope();
+                self.patch_jump(end_jump);
+            }
+            ExprKind::Match(scrutinee, arms) => self.match_expr(scrutinee, arms),
+            ExprKind::Spawn(closure) => {
+                self.expr(closure);
+                self.emit(opcode::SPAWN, NO_OPERAND);
+            }
+            ExprKind::Yield(op) => {
+                self.expr(op);
+                self.emit(opcode::YIELD, NO_OPERAND);
+            }
+            ExprKind::Join(fiber) => {
+                self.expr(fiber);
+                self.emit(opcode::JOIN, NO_OPERAND);
+            }
+        }
+    }
+
+    /// Compiles a `match`, which keeps the value being matched in a local for the arms to test in
+    /// turn. If the leading arms match variants of an enum declared in this module, a jump table
+    /// goes straight to the ones for the variant of the value instead.
+    fn match_expr(&mut self, scrutinee: &Expr, arms: &[MatchArm]) {
+        self.begin_scope();
+        self.expr(scrutinee);
+        let slot = self.current().stack_size - 1;
+        self.add_hidden_local(slot);
+
+        let mut ends = Vec::new();
+        let mut rest = arms;
+        if let Some((ty, indices)) = self.jump_table(arms) {
+            let (table_arms, others) = arms.split_at(indices.len());
+            rest = others;
+
+            self.emit(opcode::GET_LOCAL, slot as u32);
+            self.constant(Constant::EnumType(ty));
+            let count = ty.variants().len();
+            self.emit(opcode::SWITCH, count as u32);
+            let jumps = (0..=count)
+                .map(|_| self.emit_jump(opcode::JUMP))
+                .collect::<Vec<_>>();
+
+            // Values without an arm in the table carry on with the arms after it.
+            let mut fallbacks = vec![jumps[count]];
+            for (index, &jump) in jumps[..count].iter().enumerate() {
+                let mut variant_arms = table_arms
+                    .iter()
+                    .zip(&indices)
+                    .filter(|&(_, &arm_index)| arm_index == index)
+                    .peekable();
+                if variant_arms.peek().is_none() {
+                    fallbacks.push(jump);
+                    continue;
+                }
+                self.patch_jump(jump);
+                for (arm, _) in variant_arms {
+                    self.match_arm(arm, slot, true, &mut ends);
+                }
+                fallbacks.push(self.emit_jump(opcode::JUMP));
+            }
+            for jump in fallbacks {
+                self.patch_jump(jump);
+            }
+        }
+        for arm in rest {
+            self.match_arm(arm, slot, false, &mut ends);
+        }
+
+        let catch_all = arms
+            .iter()
+            .position(|arm| arm.guard.is_none() && arm.pattern.is_irrefutable());
+        match catch_all {
+            Some(index) => {
+                if let Some(arm) = arms.get(index + 1) {
+                    self.issues.push(
+                        Issue::warning("unreachable match arm", arm.pattern.span)
+                            .with_note("the arm before it matches every value"),
+                    );
+                }
+            }
+            None => {
+                self.check_exhaustive(arms);
+                self.emit(opcode::GET_LOCAL, slot as u32);
+                self.emit(opcode::NO_MATCH, NO_OPERAND);
+            }
+        }
+
+        // Every arm that matched left its result on the stack.
+        self.current().stack_size += 1;
+        for jump in ends {
+            self.patch_jump(jump);
+        }
+        self.end_scope();
+    }
+
+    /// The enum type and variant indices of the leading arms, if there are enough of them that
+    /// match variants of the same enum for a jump table to be worth it.
+    fn jump_table(&mut self, arms: &[MatchArm]) -> Option<(EnumTypePtr<'gc>, Vec<usize>)> {
+        let mut table: Option<EnumTypePtr<'gc>> = None;
+        let mut indices = Vec::new();
+        for arm in arms {
+            let PatternKind::Variant(ty_name, variant, fields) = &arm.pattern.kind else {
+                break;
+            };
+            let Some(ty) = self.known_enum(&ty_name.name) else {
+                break;
+            };
+            if table.is_some_and(|table| !Gc::ptr_eq(table, ty)) {
+                break;
+            }
+            // Arms with mistakes in them are reported when they are compiled.
+            let Some(index) = ty.variant_index(&variant.name) else {
+                break;
+            };
+            if ty.variants()[index].fields().len() != fields.len() {
+                break;
+            }
+            table = Some(ty);
+            indices.push(index);
+        }
+        match table {
+            Some(ty) if indices.len() > 1 => Some((ty, indices)),
+            _ => None,
+        }
+    }
+
+    /// Compiles an arm of a `match`. If its pattern doesn't match, or its guard is false, the
+    /// code carries on after the arm.
+    fn match_arm(&mut self, arm: &MatchArm, slot: usize, in_table: bool, ends: &mut Vec<usize>) {
+        let base = self.current().stack_size;
+        let mut failures = Vec::new();
+        self.begin_scope();
+        self.emit(opcode::GET_LOCAL, slot as u32);
+        match &arm.pattern.kind {
+            // The jump table has already checked the variant.
+            PatternKind::Variant(_, _, fields) if in_table => {
+                let parent_span = mem::replace(&mut self.span, arm.pattern.span);
+                self.unpack(fields, &mut failures);
+                self.span = parent_span;
+            }
+            _ => self.pattern(&arm.pattern, &mut failures),
+        }
+        if let Some(guard) = &arm.guard {
+            self.expr(guard);
+            self.emit_failure(&mut failures);
+        }
+        self.expr(&arm.body);
+        self.end_scope();
+        ends.push(self.emit_jump(opcode::JUMP));
+        self.patch_failures(failures, base);
+    }
+
+    /// Tests the value at the top of the stack against the pattern, replacing it with the locals
+    /// the pattern binds if it matches.
+    fn pattern(&mut self, pattern: &Pattern, failures: &mut Vec<(usize, usize)>) {
+        let parent_span = mem::replace(&mut self.span, pattern.span);
+        match &pattern.kind {
+            PatternKind::Wildcard => self.emit(opcode::POP, NO_OPERAND),
+            PatternKind::Binding(name) => self.declare_local(name),
+            PatternKind::Literal(literal) => {
+                self.expr(literal);
+                self.emit(opcode::EQ, NO_OPERAND);
+                self.emit_failure(failures);
+            }
+            PatternKind::Variant(ty_name, variant, fields) => {
+                match self.known_enum(&ty_name.name) {
+                    Some(ty) => {
+                        // The variant can be checked now, rather than when the code runs.
+                        match ty.variant_index(&variant.name) {
+                            Some(index) if ty.variants()[index].fields().len() != fields.len() => {
+                                self.error(format!(
+                                    "wrong number of fields in pattern for {}.{}: expected {}, got {}",
+                                    ty.name(),
+                                    variant.name,
+                                    ty.variants()[index].fields().len(),
+                                    fields.len()
+                                ))
+                            }
+                            Some(_) => {}
+                            None => self.error(format!(
+                                "{} has no variant named `{}`",
+                                ty.name(),
+                                variant.name
+                            )),
+                        }
+                        self.constant(Constant::EnumType(ty));
+                    }
+                    None => self.expr_kind(&ExprKind::Identifier(ty_name.name.clone())),
+                }
+                let name = self.string_constant(&variant.name);
+                self.emit(opcode::IS_VARIANT, name);
+                self.emit_failure(failures);
+                self.unpack(fields, failures);
+            }
+        }
+        self.span = parent_span;
+    }
+
+    /// Replaces the variant at the top of the stack with its fields, and tests each of them
+    /// against its pattern.
+    fn unpack(&mut self, fields: &[Pattern], failures: &mut Vec<(usize, usize)>) {
+        let count = self.operand(fields.len(), "fields in a pattern");
+        self.emit(opcode::UNPACK, count);
+        let first = self.current().stack_size - fields.len();
+        // The fields stay where they are as locals, which the patterns binding them name.
+        for (i, field) in fields.iter().enumerate() {
+            match &field.kind {
+                PatternKind::Binding(name) => self.add_local(name, first + i),
+                _ => self.add_hidden_local(first + i),
+            }
+        }
+        for (i, field) in fields.iter().enumerate() {
+            if !field.is_irrefutable() {
+                self.emit(opcode::GET_LOCAL, (first + i) as u32);
+                self.pattern(field, failures);
+            }
+        }
+    }
+
+    /// Emits a jump for when the condition at the top of the stack is false, remembering how
+    /// many values are on the stack at that point.
+    fn emit_failure(&mut self, failures: &mut Vec<(usize, usize)>) {
+        let jump = self.emit_jump(opcode::JUMP_IF_FALSE);
+        failures.push((jump, self.current().stack_size));
+    }
+
+    /// Makes the failure jumps of an arm jump to the next instruction, popping whatever each of
+    /// them left on the stack above the base.
+    fn patch_failures(&mut self, failures: Vec<(usize, usize)>, base: usize) {
+        let top = failures
+            .iter()
+            .map(|&(_, stack_size)| stack_size)
+            .max()
+            .unwrap_or(base);
+        self.current().stack_size = top;
+        for stack_size in (base..=top).rev() {
+            for &(jump, _) in failures.iter().filter(|&&(_, size)| size == stack_size) {
+                self.patch_jump(jump);
+            }
+            if stack_size > base {
+                self.emit(opcode::POP, NO_OPERAND);
+            }
+        }
+    }
+
+    /// Warns about the variants a `match` doesn't have an arm for, if its arms match variants of
+    /// an enum declared in this module.
+    fn check_exhaustive(&mut self, arms: &[MatchArm]) {
+        let Some(ty) = arms.iter().find_map(|arm| match &arm.pattern.kind {
+            PatternKind::Variant(ty, ..) => self.known_enum(&ty.name),
+            _ => None,
+        }) else {
+            return;
+        };
+        // Only arms that match every value of their variant count towards covering it.
+        let covered = arms
+            .iter()
+            .filter(|arm| arm.guard.is_none())
+            .filter_map(|arm| match &arm.pattern.kind {
+                PatternKind::Variant(ty_name, variant, fields)
+                    if fields.iter().all(Pattern::is_irrefutable) =>
+                {
+                    Some((ty_name, variant))
+                }
+                _ => None,
+            })
+            .filter(|(ty_name, _)| {
+                self.known_enum(&ty_name.name)
+                    .is_some_and(|other| Gc::ptr_eq(other, ty))
+            })
+            .map(|(_, variant)| variant.name.clone())
+            .collect::<HashSet<_>>();
+        let missing = ty
+            .variants()
+            .iter()
+            .filter(|variant| !covered.contains(variant.name()))
+            .map(|variant| format!("`{}.{}`", ty.name(), variant.name()))
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            self.issues.push(
+                Issue::warning(
+                    format!("match does not cover {}", missing.join(", ")),
+                    self.span,
+                )
+                .with_note("add arms for them, or a `_` arm to match anything else"),
+            );
+        }
+    }
+
+    /// The enum type declared by the variable, if it is a local of this or an enclosing function
+    /// declared by an `enum` statement.
+    fn known_enum(&mut self, name: &str) -> Option<EnumTypePtr<'gc>> {
+        let local = self
+            .functions
+            .iter_mut()
+            .rev()
+            .find_map(|function| function.locals.iter_mut().rev().find(|l| l.name == name))?;
+        local.used = true;
+        local.enum_type
+    }
+
+    /// Defines a global with the value of the local, unless that would replace one defined by the
+    /// host.
+    fn define_global(&mut self, name: &Ident, slot: usize) {
+        if self.cx.state().is_host_global(&name.name) {
+            self.issues.push(
+                Issue::warning(
+                    format!("`{}` is already a global defined by the host", name.name),
+                    name.span,
+                )
+                .with_note("the function is only a local, so the global is left as it is"),
+            );
+            return;
+        }
+        self.resolve_local(0, &name.name);
+        self.emit(opcode::GET_LOCAL, slot as u32);
+        let global = self.string_constant(&name.name);
+        self.emit(opcode::SET_GLOBAL, global);
+        self.emit(opcode::POP, NO_OPERAND);
+    }
+
+    /// Whether statements are being compiled directly in the body of the script.
+    fn at_top_level(&mut self) -> bool {
+        // The body of the top-level function is the second scope, after its parameters.
+        self.functions.len() == 1 && self.current().scope_depth <= 2
+    }
+
+    fn begin_scope(&mut self) {
+        self.current().scope_depth += 1;
+    }
+
+    /// Ends the current scope, removing its locals from beneath the value at the top of the
+    /// stack, which is the result of the scope.
+    fn end_scope(&mut self) {
+        let function = self.current();
+        function.scope_depth -= 1;
+
+        let depth = function.scope_depth;
+        let count = function
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth > depth)
+            .count();
+        let scope_locals = function.locals.split_off(function.locals.len() - count);
+
+        let end = function.builder.offset();
+        for local in scope_locals.iter().filter(|local| !local.name.is_empty()) {
+            function
+                .builder
+                .local(local.name.clone(), local.slot, local.start, end);
+        }
+
+        for local in &scope_locals {
+            if !local.used && !local.name.starts_with('_') {
+                self.issues.push(
+                    Issue::warning(format!("unused variable `{}`", local.name), local.span)
+                        .with_note(format!(
+                            "if this is intentional, prefix it with an underscore: `_{}`",
+                            local.name
+                        )),
+                );
+            }
+        }
+
+        self.drop_values(count);
+    }
+
+    /// Begins a loop starting at the next instruction, returning its offset.
+    fn begin_loop(&mut self) -> usize {
+        let function = self.current();
+        let start = function.builder.offset();
+        let stack_size = function.stack_size;
+        let try_depth = function.try_depth;
+        function.loops.push(Loop {
+            start,
+            stack_size,
+            try_depth,
+            breaks: Vec::new(),
+        });
+        start
+    }
+
+    /// Ends the innermost loop, making its `break`s jump to the next instruction.
+    fn end_loop(&mut self) {
+        let innermost = self.current().loops.pop().expect("should be in a loop");
+        for jump in innermost.breaks {
+            self.patch_jump(jump);
+        }
+    }
+
+    /// Ends the `try` bodies between the current code and the innermost loop.
+    fn end_loop_tries(&mut self) {
+        let function = self.current();
+        let loop_try_depth = function
+            .loops
+            .last()
+            .expect("should be in a loop")
+            .try_depth;
+        for _ in loop_try_depth..function.try_depth {
+            self.emit(opcode::TRY_END, NO_OPERAND);
+        }
+    }
+
+    /// Removes the given number of values from beneath the value at the top of the stack.
+    fn drop_values(&mut self, count: usize) {
+        if count > 0 {
+            self.emit(opcode::DROP, count as u32);
+        }
+    }
+
+    /// Declares a local for the value at the top of the stack.
+    fn declare_local(&mut self, name: &Ident) {
+        let slot = self.current().stack_size - 1;
+        self.add_local(name, slot);
+    }
+
+    /// Declares a local for a value that the compiler keeps on the stack, which code can't name.
+    fn add_hidden_local(&mut self, slot: usize) {
+        let span = self.span;
+        self.add_local(
+            &Ident {
+                name: String::new(),
+                span,
+            },
+            slot,
+        );
+        self.current().locals.last_mut().expect("added above").used = true;
+    }
+
+    fn add_local(&mut self, name: &Ident, slot: usize) {
+        let function = self.current();
+        let depth = function.scope_depth;
+        let start = function.builder.offset();
+        function.locals.push(Local {
+            name: name.name.clone(),
+            span: name.span,
+            slot,
+            depth,
+            used: false,
+            start,
+            enum_type: None,
+        });
+        if slot > MAX_OPERAND {
+            self.error("too many local variables in a single function");
+        }
+    }
+
+    fn resolve(&mut self, name: &str) -> Option<Variable> {
+        let function = self.functions.len() - 1;
+        if let Some(slot) = self.resolve_local(function, name) {
+            // Too many locals are reported when they are declared.
+            return Some(Variable::Local(slot as u32));
+        }
+        if let Some(index) = self.resolve_upvalue(function, name) {
+            let operand = self.operand(index, "captured variables");
+            return Some(Variable::Upvalue(operand));
+        }
+        // Globals are defined by the host, or by scripts evaluated earlier, so they must already
+        // exist.
+        if self.cx.state().global(name).is_some() {
+            return Some(Variable::Global(self.string_constant(name)));
+        }
+        self.error(format!("cannot find `{}` in this scope", name));
+        None
+    }
+
+    fn resolve_local(&mut self, function: usize, name: &str) -> Option<usize> {
+        let local = self.functions[function]
+            .locals
+            .iter_mut()
+            .rev()
+            .find(|local| local.name == name)?;
+        local.used = true;
+        Some(local.slot)
+    }
+
+    /// Resolves a variable of an enclosing function, capturing it through every function in
+    /// between.
+    fn resolve_upvalue(&mut self, function: usize, name: &str) -> Option<usize> {
+        if function == 0 {
+            return None;
+        }
+        let capture = match self.resolve_local(function - 1, name) {
+            Some(slot) => Capture::Local(slot),
+            None => Capture::Upvalue(self.resolve_upvalue(function - 1, name)?),
+        };
+
+        let state = &mut self.functions[function];
+        if let Some(index) = state.captures.iter().position(|c| *c == capture) {
+            return Some(index);
+        }
+        state.captures.push(capture);
+        Some(state.builder.capture(capture))
+    }
+
+    /// Adds the string to the constants, returning its index as an operand.
+    fn string_constant(&mut self, string: &str) -> u32 {
+        let string = StringValue::new_ptr(self.cx, string.to_string());
+        let index = self.current().builder.constant(Constant::String(string));
+        self.operand(index, "constants")
+    }
+
+    fn constant(&mut self, constant: Constant<'gc>) {
+        let index = self.current().builder.constant(constant);
+        let operand = self.operand(index, "constants");
+        self.emit(opcode::CONST, operand);
+    }
+
+    fn operand(&mut self, index: usize, what: &str) -> u32 {
+        if index > MAX_OPERAND {
+            self.issues.push(
+                Issue::error(format!("too many {} in a single function", what), self.span)
+                    .with_note("split this function into smaller functions"),
+            );
+            return NO_OPERAND;
+        }
+        index as u32
+    }
+
+    /// Emits a jump whose target is filled in later with `patch_jump`, returning its offset.
+    fn emit_jump(&mut self, op: u8) -> usize {
+        self.emit(op, NO_OPERAND);
+        self.current().builder.offset() - 1
+    }
+
+    /// Makes the jump at the offset jump to the next instruction.
+    fn patch_jump(&mut self, offset: usize) {
+        let target = self.current().builder.offset();
+        let operand = self.operand(target, "instructions");
+        self.current().builder.patch(offset, operand);
+    }
+
+    /// Emits a jump back to the start of a loop.
+    fn emit_loop(&mut self, start: usize) {
+        let operand = self.operand(start, "instructions");
+        self.emit(opcode::JUMP, operand);
+    }
+
+    fn emit(&mut self, op: u8, operand: u32) {
+        let line = self.span.line();
+        let function = self.current();
+        function.stack_size = function
+            .stack_size
+            .checked_add_signed(stack_effect(op, operand))
+            .expect("stack size should never be negative");
+        function.builder.instruction(op, operand, line);
+    }
+
+    fn current(&mut self) -> &mut FunctionState<'gc> {
+        self.functions
+            .last_mut()
+            .expect("should be compiling a function")
+    }
+
+    /// Reports the names that appear again after their first declaration.
+    fn declared_twice<'n>(&mut self, names: impl Iterator<Item = &'n Ident>, what: &str) {
+        let mut seen = HashSet::new();
+        for name in names {
+            if !seen.insert(name.name.as_str()) {
+                self.issues.push(Issue::error(
+                    format!("duplicate {} `{}`", what, name.name),
+                    name.span,
+                ));
+            }
+        }
+    }
+
+    fn error(&mut self, message: impl Into<String>) {
+        self.issues.push(Issue::error(message, self.span));
+    }
+
+    fn unreachable(&mut self, span: Span, keyword: &str) {
+        self.issues.push(
+            Issue::warning("unreachable code", span)
+                .with_note(format!("any code following a `{}` will never run", keyword)),
+        );
+    }
+}
+
+/// The number of values the instruction adds to (or removes from, if negative) the stack.
+fn stack_effect(op: u8, operand: u32) -> isize {
+    match op {
+        opcode::NO_OP => 0,
+        opcode::POP => -1,
+        opcode::DROP => -(operand as isize),
+        opcode::NIL
+        | opcode::TRUE
+        | opcode::FALSE
+        | opcode::INT
+        | opcode::CONST
+        | opcode::CLOSURE => 1,
+        opcode::ADD | opcode::SUB | opcode::MUL | opcode::DIV | opcode::MOD => -1,
+        opcode::NEG => 0,
+        opcode::EQ | opcode::NE | opcode::LT | opcode::LE | opcode::GT | opcode::GE => -1,
+        opcode::NOT => 0,
+        opcode::RETURN => -1,
+        opcode::CALL => -(operand as isize),
+        opcode::INVOKE => -(operand as isize) - 1,
+        opcode::JUMP => 0,
+        // The jumps that keep their condition are counted as if they fall through.
+        opcode::JUMP_IF_FALSE | opcode::JUMP_IF_FALSE_OR_POP | opcode::JUMP_IF_TRUE_OR_POP => -1,
+        opcode::SPAWN | opcode::YIELD | opcode::JOIN => 0,
+        opcode::TRY_BEGIN | opcode::TRY_END => 0,
+        opcode::THROW => -1,
+        opcode::GET_LOCAL => 1,
+        opcode::SET_LOCAL => 0,
+        opcode::GET_UPVALUE => 1,
+        opcode::SET_UPVALUE => 0,
+        opcode::GET_GLOBAL => 1,
+        opcode::SET_GLOBAL => 0,
+        opcode::IMPORT => 1,
+        opcode::GET_EXPORT => 0,
+        opcode::EXPORT => -1,
+        opcode::LIST => 1 - operand as isize,
+        opcode::GET_INDEX => -1,
+        opcode::SET_INDEX => -2,
+        opcode::MAP => 1 - 2 * operand as isize,
+        opcode::IN => -1,
+        opcode::RECORD => -2 * operand as isize,
+        opcode::GET_FIELD => 0,
+        opcode::SET_FIELD => -1,
+        opcode::SWITCH => -2,
+        opcode::IS_VARIANT => 0,
+        opcode::UNPACK => operand as isize - 1,
+        opcode::NO_MATCH => -1,
+        _ => unreachable!("unknown opcode {:#04x}", op),
+    }
+}
@@ -0,0 +1,325 @@
+use core::fmt::{self, Display, Formatter};
+use std::sync::Arc;
+
+use crate::compile::span::Span;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    severity: Severity,
+    message: String,
+    file: Arc<str>,
+    span: Span,
+    source_line: String,
+    notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn file(&self) -> &str {
+        &self.file
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn line(&self) -> u32 {
+        self.span.line()
+    }
+
+    pub fn column(&self) -> u32 {
+        self.span.column()
+    }
+
+    /// The full source line on which the span starts, without the trailing newline.
+    pub fn source_line(&self) -> &str {
+        &self.source_line
+    }
+
+    pub fn notes(&self) -> &[String] {
+        &self.notes
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let line_number = self.line().to_string();
+        let gutter = " ".repeat(line_number.len());
+
+        writeln!(f, "{}: {}", self.severity, self.message)?;
+        writeln!(
+            f,
+            "{}--> {}:{}:{}",
+            gutter,
+            self.file,
+            self.line(),
+            self.column()
+        )?;
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", line_number, self.source_line)?;
+
+        // Underline the span, clamped to the first line it appears on.
+        let padding = self.column().saturating_sub(1) as usize;
+        let remaining = self.source_line.chars().count().saturating_sub(padding);
+        let width = self.span.end().saturating_sub(self.span.start());
+        let carets = width.clamp(1, remaining.max(1));
+        write!(
+            f,
+            "{} | {}{}",
+            gutter,
+            " ".repeat(padding),
+            "^".repeat(carets)
+        )?;
+
+        for note in &self.notes {
+            write!(f, "\n{} = help: {}", gutter, note)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A problem found while compiling, which is turned into a [`Diagnostic`] once the file name and
+/// source are attached to it.
+#[derive(Debug)]
+pub struct Issue {
+    severity: Severity,
+    message: String,
+    span: Span,
+    notes: Vec<String>,
+}
+
+impl Issue {
+    pub fn error(message: impl Into<String>, span: Span) -> Self {
+        Self::new(Severity::Error, message, span)
+    }
+
+    pub fn warning(message: impl Into<String>, span: Span) -> Self {
+        Self::new(Severity::Warning, message, span)
+    }
+
+    fn new(severity: Severity, message: impl Into<String>, span: Span) -> Self {
+        Self {
+            severity,
+            message: message.into(),
+            span,
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    pub fn into_diagnostic(self, file: Arc<str>, source: &str) -> Diagnostic {
+        Diagnostic {
+            severity: self.severity,
+            message: self.message,
+            file,
+            span: self.span,
+            source_line: source_line(source, self.span.start()).to_string(),
+            notes: self.notes,
+        }
+    }
+}
+
+/// All the diagnostics reported while compiling a single source, in source order.
+#[derive(Clone, Debug, Default)]
+pub struct Diagnostics(Vec<Diagnostic>);
+
+impl Diagnostics {
+    pub fn new(file: &str, source: &str, mut issues: Vec<Issue>) -> Self {
+        let file: Arc<str> = file.into();
+        issues.sort_by_key(|issue| issue.span.start());
+        Self(
+            issues
+                .into_iter()
+                .map(|issue| issue.into_diagnostic(file.clone(), source))
+                .collect(),
+        )
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.errors().next().is_some()
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.iter()
+            .filter(|diagnostic| diagnostic.severity == Severity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.iter()
+            .filter(|diagnostic| diagnostic.severity == Severity::Warning)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.0.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl IntoIterator for Diagnostics {
+    type Item = Diagnostic;
+    type IntoIter = std::vec::IntoIter<Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl Display for Diagnostics {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (index, diagnostic) in self.0.iter().enumerate() {
+            if index > 0 {
+                write!(f, "\n\n")?;
+            }
+            write!(f, "{}", diagnostic)?;
+        }
+        Ok(())
+    }
+}
+
+fn source_line(source: &str, offset: usize) -> &str {
+    let offset = offset.min(source.len());
+    let start = source[..offset].rfind('\n').map_or(0, |index| index + 1);
+    let end = source[offset..]
+        .find('\n')
+        .map_or(source.len(), |index| offset + index);
+    source[start..end].trim_end_matches('\r')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(source: &str, issues: Vec<Issue>) -> String {
+        Diagnostics::new("main.doji", source, issues).to_string()
+    }
+
+    #[test]
+    fn underline_span() {
+        let source = "let x = 1;\nlet y = x + true;\n";
+        let issue = Issue::error("cannot add bool to int", Span::new(19, 27, 2, 9));
+        assert_eq!(
+            render(source, vec![issue]),
+            "\
+error: cannot add bool to int
+ --> main.doji:2:9
+  |
+2 | let y = x + true;
+  |         ^^^^^^^^"
+        );
+    }
+
+    #[test]
+    fn notes_and_wide_line_numbers() {
+        let source = format!("{}let unused = 1;", "\n".repeat(9));
+        let issue = Issue::warning("unused variable `unused`", Span::new(13, 19, 10, 5))
+            .with_note("prefix the name with `_` to silence this warning")
+            .with_note("or remove the variable");
+        assert_eq!(
+            render(&source, vec![issue]),
+            "\
+warning: unused variable `unused`
+  --> main.doji:10:5
+   |
+10 | let unused = 1;
+   |     ^^^^^^
+   = help: prefix the name with `_` to silence this warning
+   = help: or remove the variable"
+        );
+    }
+
+    #[test]
+    fn multi_line_span() {
+        // Only the first line of the span is shown, underlined up to its end.
+        let source = "let f = fn() {\n  1\n};";
+        let issue = Issue::error("bad function", Span::new(8, 20, 1, 9));
+        assert_eq!(
+            render(source, vec![issue]),
+            "\
+error: bad function
+ --> main.doji:1:9
+  |
+1 | let f = fn() {
+  |         ^^^^^^"
+        );
+    }
+
+    #[test]
+    fn empty_span_at_end_of_input() {
+        let source = "fn (";
+        let issue = Issue::error(
+            "expected identifier, found end of input",
+            Span::new(4, 4, 1, 5),
+        );
+        assert_eq!(
+            render(source, vec![issue]),
+            "\
+error: expected identifier, found end of input
+ --> main.doji:1:5
+  |
+1 | fn (
+  |     ^"
+        );
+    }
+
+    #[test]
+    fn several_diagnostics_in_source_order() {
+        let source = "a;\nb;";
+        let issues = vec![
+            Issue::error("second", Span::new(3, 4, 2, 1)),
+            Issue::warning("first", Span::new(0, 1, 1, 1)),
+        ];
+        assert_eq!(
+            render(source, issues),
+            "\
+warning: first
+ --> main.doji:1:1
+  |
+1 | a;
+  | ^
+
+error: second
+ --> main.doji:2:1
+  |
+2 | b;
+  | ^"
+        );
+    }
+}
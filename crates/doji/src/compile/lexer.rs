@@ -0,0 +1,516 @@
+use core::fmt::{self, Display, Formatter};
+
+use crate::compile::{diagnostic::Issue, span::Span};
+
+#[derive(Clone, Debug)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TokenKind {
+    Int(i64),
+    Float(f64),
+    String(String),
+    Identifier(String),
+
+    Let,
+    Fn,
+    Return,
+    Nil,
+    True,
+    False,
+    Spawn,
+    Yield,
+    Join,
+    If,
+    Else,
+    While,
+    Loop,
+    Break,
+    Continue,
+    And,
+    Or,
+    Not,
+    Try,
+    Catch,
+    Throw,
+    Import,
+    Export,
+    From,
+    In,
+    Struct,
+    Enum,
+    Match,
+
+    LeftParen,
+    RightParen,
+    LeftBrace,
+    RightBrace,
+    LeftBracket,
+    RightBracket,
+    Comma,
+    Colon,
+    Dot,
+    Semicolon,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    Equal,
+    EqualEqual,
+    FatArrow,
+    BangEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+
+    Eof,
+}
+
+impl Display for TokenKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenKind::Int(int) => write!(f, "`{}`", int),
+            TokenKind::Float(float) => write!(f, "`{}`", float),
+            TokenKind::String(string) => write!(f, "{:?}", string),
+            TokenKind::Identifier(identifier) => write!(f, "`{}`", identifier),
+            TokenKind::Let => write!(f, "`let`"),
+            TokenKind::Fn => write!(f, "`fn`"),
+            TokenKind::Return => write!(f, "`return`"),
+            TokenKind::Nil => write!(f, "`nil`"),
+            TokenKind::True => write!(f, "`true`"),
+            TokenKind::False => write!(f, "`false`"),
+            TokenKind::Spawn => write!(f, "`spawn`"),
+            TokenKind::Yield => write!(f, "`yield`"),
+            TokenKind::Join => write!(f, "`join`"),
+            TokenKind::If => write!(f, "`if`"),
+            TokenKind::Else => write!(f, "`else`"),
+            TokenKind::While => write!(f, "`while`"),
+            TokenKind::Loop => write!(f, "`loop`"),
+            TokenKind::Break => write!(f, "`break`"),
+            TokenKind::Continue => write!(f, "`continue`"),
+            TokenKind::And => write!(f, "`and`"),
+            TokenKind::Or => write!(f, "`or`"),
+            TokenKind::Not => write!(f, "`not`"),
+            TokenKind::Try => write!(f, "`try`"),
+            TokenKind::Catch => write!(f, "`catch`"),
+            TokenKind::Throw => write!(f, "`throw`"),
+            TokenKind::Import => write!(f, "`import`"),
+            TokenKind::Export => write!(f, "`export`"),
+            TokenKind::From => write!(f, "`from`"),
+            TokenKind::In => write!(f, "`in`"),
+            TokenKind::Struct => write!(f, "`struct`"),
+            TokenKind::Enum => write!(f, "`enum`"),
+            TokenKind::Match => write!(f, "`match`"),
+            TokenKind::LeftParen => write!(f, "`(`"),
+            TokenKind::RightParen => write!(f, "`)`"),
+            TokenKind::LeftBrace => write!(f, "`{{`"),
+            TokenKind::RightBrace => write!(f, "`}}`"),
+            TokenKind::LeftBracket => write!(f, "`[`"),
+            TokenKind::RightBracket => write!(f, "`]`"),
+            TokenKind::Comma => write!(f, "`,`"),
+            TokenKind::Colon => write!(f, "`:`"),
+            TokenKind::Dot => write!(f, "`.`"),
+            TokenKind::Semicolon => write!(f, "`;`"),
+            TokenKind::Plus => write!(f, "`+`"),
+            TokenKind::Minus => write!(f, "`-`"),
+            TokenKind::Star => write!(f, "`*`"),
+            TokenKind::Slash => write!(f, "`/`"),
+            TokenKind::Percent => write!(f, "`%`"),
+            TokenKind::Equal => write!(f, "`=`"),
+            TokenKind::EqualEqual => write!(f, "`==`"),
+            TokenKind::FatArrow => write!(f, "`=>`"),
+            TokenKind::BangEqual => write!(f, "`!=`"),
+            TokenKind::Less => write!(f, "`<`"),
+            TokenKind::LessEqual => write!(f, "`<=`"),
+            TokenKind::Greater => write!(f, "`>`"),
+            TokenKind::GreaterEqual => write!(f, "`>=`"),
+            TokenKind::Eof => write!(f, "end of input"),
+        }
+    }
+}
+
+pub struct Lexer<'a> {
+    source: &'a str,
+    offset: usize,
+    line: u32,
+    column: u32,
+    start: usize,
+    start_line: u32,
+    start_column: u32,
+    issues: Vec<Issue>,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(source: &'a str) -> Self {
+        Self {
+            source,
+            offset: 0,
+            line: 1,
+            column: 1,
+            start: 0,
+            start_line: 1,
+            start_column: 1,
+            issues: Vec::new(),
+        }
+    }
+
+    /// Returns the issues found so far. The lexer skips over anything it cannot understand, so
+    /// these are reported alongside any issues found by the parser.
+    pub fn take_issues(&mut self) -> Vec<Issue> {
+        std::mem::take(&mut self.issues)
+    }
+
+    pub fn next_token(&mut self) -> Token {
+        loop {
+            self.skip_whitespace_and_comments();
+
+            self.start = self.offset;
+            self.start_line = self.line;
+            self.start_column = self.column;
+
+            if let Some(kind) = self.token_kind() {
+                return Token {
+                    kind,
+                    span: self.span(),
+                };
+            }
+        }
+    }
+
+    fn token_kind(&mut self) -> Option<TokenKind> {
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return Some(TokenKind::Eof),
+        };
+
+        if c.is_ascii_digit() {
+            return Some(self.number());
+        }
+        if is_identifier_start(c) {
+            return Some(self.identifier_or_keyword());
+        }
+        if c == '"' {
+            return Some(self.string());
+        }
+
+        self.advance();
+        let kind = match c {
+            '(' => TokenKind::LeftParen,
+            ')' => TokenKind::RightParen,
+            '{' => TokenKind::LeftBrace,
+            '}' => TokenKind::RightBrace,
+            '[' => TokenKind::LeftBracket,
+            ']' => TokenKind::RightBracket,
+            ',' => TokenKind::Comma,
+            ':' => TokenKind::Colon,
+            '.' => TokenKind::Dot,
+            ';' => TokenKind::Semicolon,
+            '+' => TokenKind::Plus,
+            '-' => TokenKind::Minus,
+            '*' => TokenKind::Star,
+            '/' => TokenKind::Slash,
+            '%' => TokenKind::Percent,
+            '=' if self.eat('=') => TokenKind::EqualEqual,
+            '=' if self.eat('>') => TokenKind::FatArrow,
+            '=' => TokenKind::Equal,
+            '!' if self.eat('=') => TokenKind::BangEqual,
+            '<' if self.eat('=') => TokenKind::LessEqual,
+            '<' => TokenKind::Less,
+            '>' if self.eat('=') => TokenKind::GreaterEqual,
+            '>' => TokenKind::Greater,
+            '!' => {
+                self.issues.push(
+                    Issue::error("unexpected character `!`", self.span())
+                        .with_note("use `not` to negate a value"),
+                );
+                return None;
+            }
+            _ => {
+                self.issues.push(Issue::error(
+                    format!("unexpected character `{}`", c),
+                    self.span(),
+                ));
+                return None;
+            }
+        };
+        Some(kind)
+    }
+
+    /// The span from the start of the current token up to the current offset.
+    fn span(&self) -> Span {
+        Span::new(self.start, self.offset, self.start_line, self.start_column)
+    }
+
+    fn skip_whitespace_and_comments(&mut self) {
+        loop {
+            match self.peek() {
+                Some(c) if c.is_whitespace() => {
+                    self.advance();
+                }
+                Some('/') if self.peek_second() == Some('/') => {
+                    while !matches!(self.peek(), Some('\n') | None) {
+                        self.advance();
+                    }
+                }
+                _ => return,
+            }
+        }
+    }
+
+    fn number(&mut self) -> TokenKind {
+        let start = self.offset;
+        self.advance_while(|c| c.is_ascii_digit());
+
+        // Only treat the dot as part of the number if a digit follows it, so that we leave room
+        // for method calls on integers.
+        let is_float =
+            self.peek() == Some('.') && self.peek_second().is_some_and(|c| c.is_ascii_digit());
+        if is_float {
+            self.advance();
+            self.advance_while(|c| c.is_ascii_digit());
+        }
+
+        let text = &self.source[start..self.offset];
+        if is_float {
+            match text.parse() {
+                Ok(float) => TokenKind::Float(float),
+                Err(_) => {
+                    let issue =
+                        Issue::error(format!("invalid float literal `{}`", text), self.span());
+                    self.issues.push(issue);
+                    TokenKind::Float(0.0)
+                }
+            }
+        } else {
+            match text.parse() {
+                Ok(int) => TokenKind::Int(int),
+                Err(_) => {
+                    let issue = Issue::error(
+                        format!("integer literal `{}` is too large", text),
+                        self.span(),
+                    )
+                    .with_note(format!("integers must be at most {}", i64::MAX));
+                    self.issues.push(issue);
+                    TokenKind::Int(0)
+                }
+            }
+        }
+    }
+
+    fn identifier_or_keyword(&mut self) -> TokenKind {
+        let start = self.offset;
+        self.advance_while(is_identifier_continue);
+
+        match &self.source[start..self.offset] {
+            "let" => TokenKind::Let,
+            "fn" => TokenKind::Fn,
+            "return" => TokenKind::Return,
+            "nil" => TokenKind::Nil,
+            "true" => TokenKind::True,
+            "false" => TokenKind::False,
+            "spawn" => TokenKind::Spawn,
+            "yield" => TokenKind::Yield,
+            "join" => TokenKind::Join,
+            "if" => TokenKind::If,
+            "else" => TokenKind::Else,
+            "while" => TokenKind::While,
+            "loop" => TokenKind::Loop,
+            "break" => TokenKind::Break,
+            "continue" => TokenKind::Continue,
+            "and" => TokenKind::And,
+            "or" => TokenKind::Or,
+            "not" => TokenKind::Not,
+            "try" => TokenKind::Try,
+            "catch" => TokenKind::Catch,
+            "throw" => TokenKind::Throw,
+            "import" => TokenKind::Import,
+            "export" => TokenKind::Export,
+            "from" => TokenKind::From,
+            "in" => TokenKind::In,
+            "struct" => TokenKind::Struct,
+            "enum" => TokenKind::Enum,
+            "match" => TokenKind::Match,
+            identifier => TokenKind::Identifier(identifier.to_string()),
+        }
+    }
+
+    fn string(&mut self) -> TokenKind {
+        // Skip the opening quote.
+        self.advance();
+
+        let mut string = String::new();
+        loop {
+            let escape_span = Span::new(self.offset, self.offset + 2, self.line, self.column);
+            match self.advance() {
+                Some('"') => return TokenKind::String(string),
+                Some('\\') => {
+                    let escaped = match self.advance() {
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('0') => '\0',
+                        Some('\\') => '\\',
+                        Some('"') => '"',
+                        Some(c) => {
+                            self.issues.push(
+                                Issue::error(
+                                    format!("unknown escape sequence `\\{}`", c.escape_default()),
+                                    escape_span,
+                                )
+                                .with_note(
+                                    "valid escape sequences are `\\n`, `\\r`, `\\t`, `\\0`, `\\\\` and `\\\"`",
+                                ),
+                            );
+                            continue;
+                        }
+                        None => return self.unterminated_string(string),
+                    };
+                    string.push(escaped);
+                }
+                Some(c) => string.push(c),
+                None => return self.unterminated_string(string),
+            }
+        }
+    }
+
+    fn unterminated_string(&mut self, string: String) -> TokenKind {
+        let issue = Issue::error(
+            "unterminated string literal",
+            Span::new(
+                self.start,
+                self.start + 1,
+                self.start_line,
+                self.start_column,
+            ),
+        )
+        .with_note("add a closing `\"` to end the string");
+        self.issues.push(issue);
+        TokenKind::String(string)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.source[self.offset..].chars().next()
+    }
+
+    fn peek_second(&self) -> Option<char> {
+        let mut chars = self.source[self.offset..].chars();
+        chars.next();
+        chars.next()
+    }
+
+    fn advance(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.offset += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn advance_while(&mut self, predicate: impl Fn(char) -> bool) {
+        while self.peek().is_some_and(&predicate) {
+            self.advance();
+        }
+    }
+}
+
+fn is_identifier_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+fn is_identifier_continue(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The kinds of all the tokens in the source, up to and including the end of input.
+    fn kinds(source: &str) -> Vec<TokenKind> {
+        let mut lexer = Lexer::new(source);
+        let mut kinds = Vec::new();
+        loop {
+            let kind = lexer.next_token().kind;
+            kinds.push(kind.clone());
+            if kind == TokenKind::Eof {
+                return kinds;
+            }
+        }
+    }
+
+    #[test]
+    fn tokens() {
+        use TokenKind::*;
+        assert_eq!(
+            kinds("let x = fn(a) { a * 2.5 } // ignored\n x(1) >= 3"),
+            [
+                Let,
+                Identifier("x".into()),
+                Equal,
+                Fn,
+                LeftParen,
+                Identifier("a".into()),
+                RightParen,
+                LeftBrace,
+                Identifier("a".into()),
+                Star,
+                Float(2.5),
+                RightBrace,
+                Identifier("x".into()),
+                LeftParen,
+                Int(1),
+                RightParen,
+                GreaterEqual,
+                Int(3),
+                Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn string_escapes() {
+        assert_eq!(
+            kinds(r#""a\n\t\"b\\""#),
+            [TokenKind::String("a\n\t\"b\\".into()), TokenKind::Eof]
+        );
+    }
+
+    #[test]
+    fn spans() {
+        let mut lexer = Lexer::new("let\n  value");
+        lexer.next_token();
+        let span = lexer.next_token().span;
+        assert_eq!((span.line(), span.column()), (2, 3));
+        assert_eq!((span.start(), span.end()), (6, 11));
+    }
+
+    #[test]
+    fn unexpected_characters_are_skipped() {
+        let mut lexer = Lexer::new("1 ! 2");
+        let kinds =
+            [lexer.next_token(), lexer.next_token(), lexer.next_token()].map(|token| token.kind);
+        assert_eq!(
+            kinds,
+            [TokenKind::Int(1), TokenKind::Int(2), TokenKind::Eof]
+        );
+        assert_eq!(lexer.take_issues().len(), 1);
+    }
+}
@@ -1,39 +1,307 @@
+pub use crate::compile::{
+    diagnostic::{Diagnostic, Diagnostics, Severity},
+    span::Span,
+};
 use crate::{
+    compile::{codegen::Compiler, diagnostic::Issue, parser::Parser},
     context::Context,
-    error::ErrorPtr,
-    function::{
-        Function, FunctionPtr, NO_OPERAND,
-        opcode::{self},
-    },
+    function::FunctionPtr,
 };
 
-pub fn compile<'gc>(cx: &Context<'gc>, source: &str) -> Result<FunctionPtr<'gc>, ErrorPtr<'gc>> {
-    let mut builder = Function::builder();
-    builder.arity(0);
+mod ast;
+mod codegen;
+mod diagnostic;
+mod lexer;
+mod parser;
+mod span;
+
+/// How a compiled source is going to be run.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SourceKind {
+    /// Evaluated directly, so its top-level functions are defined as globals.
+    Script,
+    /// Imported by another source, so it only shares what it exports.
+    Module,
+}
+
+/// Compiles the source into a function, returning all the diagnostics reported along the way.
+/// The function is only returned if there were no errors.
+pub fn compile<'gc>(
+    cx: &Context<'gc>,
+    name: &str,
+    source: &str,
+    kind: SourceKind,
+) -> (Option<FunctionPtr<'gc>>, Diagnostics) {
+    let (block, mut issues) = Parser::new(source).parse();
+
+    // Don't bother generating code for a broken syntax tree, since that would only lead to
+    // confusing follow-up errors.
+    let function = if issues.iter().any(Issue::is_error) {
+        None
+    } else {
+        let source_id = cx.state().add_source(cx, name);
+        let (function, codegen_issues) = Compiler::new(cx, source_id, kind).compile_script(&block);
+        issues.extend(codegen_issues);
+        Some(function)
+    };
+
+    let diagnostics = Diagnostics::new(name, source, issues);
+    if diagnostics.has_errors() {
+        (None, diagnostics)
+    } else {
+        (function, diagnostics)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Engine, Severity};
+
+    /// The severity, message, line and column of every diagnostic for the source.
+    fn check(source: &str) -> Vec<(Severity, String, u32, u32)> {
+        Engine::builder()
+            .build()
+            .check(source)
+            .iter()
+            .map(|diagnostic| {
+                (
+                    diagnostic.severity(),
+                    diagnostic.message().to_string(),
+                    diagnostic.line(),
+                    diagnostic.column(),
+                )
+            })
+            .collect()
+    }
+
+    fn evaluate(source: &str) -> i64 {
+        Engine::builder().build().evaluate_inline(source).unwrap()
+    }
+
+    #[test]
+    fn operator_precedence() {
+        assert_eq!(evaluate("1 + 2 * 3 - 4 / 2"), 5);
+        assert_eq!(evaluate("(1 + 2) * 3"), 9);
+        assert_eq!(evaluate("-2 * 3"), -6);
+        assert_eq!(evaluate("7 % 4 * 2"), 6);
+        assert_eq!(evaluate("10 - 4 - 3"), 3);
+
+        let engine = Engine::builder().build();
+        for source in ["1 < 2 and 2 < 3", "not 1 == 2", "1 + 1 == 2 or false"] {
+            assert!(
+                engine.evaluate_inline::<bool>(source).unwrap(),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn block_scopes() {
+        assert_eq!(evaluate("let a = 1; { let a = 2; } a"), 1);
+        assert_eq!(evaluate("let a = 1; { a = 2; } a"), 2);
+        assert_eq!(evaluate("let x = { let y = 2; y * 3 }; x"), 6);
+        // Slots freed by a block are reused by later locals.
+        assert_eq!(
+            evaluate("let a = 1; { let b = 2; let c = 3; b + c; } let d = 4; a + d"),
+            5
+        );
+    }
+
+    #[test]
+    fn shadowing() {
+        assert_eq!(evaluate("let a = 1; let a = a + 1; a"), 2);
+        assert_eq!(
+            evaluate("let a = 1; { let a = a + 10; let a = a * 2; a }"),
+            22
+        );
+    }
+
+    #[test]
+    fn locals_go_out_of_scope() {
+        assert_eq!(
+            check("{ let b = 1; b; } b"),
+            vec![(
+                Severity::Error,
+                "cannot find `b` in this scope".into(),
+                1,
+                19
+            )]
+        );
+        assert_eq!(
+            check("if true { let c = 1; c; } else { c; }"),
+            vec![(
+                Severity::Error,
+                "cannot find `c` in this scope".into(),
+                1,
+                34
+            )]
+        );
+    }
+
+    #[test]
+    fn if_expressions() {
+        assert_eq!(evaluate("let x = if 1 < 2 { 10 } else { 20 }; x"), 10);
+        assert_eq!(evaluate("if 1 > 2 { 10 } else { 20 }"), 20);
+        assert_eq!(
+            evaluate("let n = 5; if n < 3 { 1 } else if n < 6 { 2 } else { 3 }"),
+            2
+        );
+        assert_eq!(evaluate("let x = 1; if x == 1 { x = 2; } x"), 2);
+        assert_eq!(evaluate("1 + if true { let y = 2; y * 3 } else { 0 }"), 7);
+    }
 
-    builder.instruction(opcode::INT, 3);
-    builder.instruction(opcode::INT, 4);
-    builder.instruction(opcode::ADD, NO_OPERAND);
+    #[test]
+    fn while_loops() {
+        assert_eq!(
+            evaluate("let i = 0; let sum = 0; while i < 5 { i = i + 1; sum = sum + i; } sum"),
+            15
+        );
+        assert_eq!(evaluate("let i = 0; while false { i = 1; } i"), 0);
+        assert_eq!(
+            evaluate("let i = 0; while true { i = i + 1; if i == 3 { break; } } i"),
+            3
+        );
+    }
 
-    let function = builder.function({
-        let mut builder = Function::builder();
-        builder.arity(0);
+    #[test]
+    fn break_with_a_value() {
+        assert_eq!(
+            evaluate("let i = 0; let x = loop { i = i + 1; if i == 4 { break i * 10; } }; x"),
+            40
+        );
+        // Values left on the stack by the body are dropped before the loop's value is pushed.
+        assert_eq!(
+            evaluate("let x = loop { let a = 1; let b = 2; break a + b; }; x + 1"),
+            4
+        );
+        // `break` on its own makes the loop evaluate to nil.
+        assert_eq!(
+            evaluate("let x = loop { break; }; if x == nil { 1 } else { 2 }"),
+            1
+        );
+    }
 
-        builder.instruction(opcode::INT, 5000);
-        builder.instruction(opcode::YIELD, NO_OPERAND);
+    #[test]
+    fn continue_in_nested_loops() {
+        // `continue` only applies to the innermost loop.
+        let source = "
+            let count = 0;
+            let i = 0;
+            while i < 3 {
+                i = i + 1;
+                let j = 0;
+                while j < 4 {
+                    j = j + 1;
+                    if j % 2 == 0 { continue; }
+                    count = count + 1;
+                }
+                if i == 2 { continue; }
+                count = count + 10;
+            }
+            count
+        ";
+        assert_eq!(evaluate(source), 26);
+        assert_eq!(
+            evaluate("let n = 0; loop { n = n + 1; let m = n; if m < 5 { continue; } break m; }"),
+            5
+        );
+    }
 
-        builder.instruction(opcode::RETURN, NO_OPERAND);
+    #[test]
+    fn break_and_continue_outside_loops() {
+        assert_eq!(
+            check("break;"),
+            vec![(Severity::Error, "`break` outside of a loop".into(), 1, 1)]
+        );
+        assert_eq!(
+            check("fn f() { continue; } loop { f(); break; }"),
+            vec![(
+                Severity::Error,
+                "`continue` outside of a loop".into(),
+                1,
+                10
+            )]
+        );
+    }
 
-        builder.build_ptr(cx)
-    });
+    #[test]
+    fn recovers_from_parse_errors() {
+        assert_eq!(
+            check("let = 1;\nlet b = ;\nfn ("),
+            vec![
+                (
+                    Severity::Error,
+                    "expected identifier, found `=`".into(),
+                    1,
+                    5
+                ),
+                (
+                    Severity::Error,
+                    "expected expression, found `;`".into(),
+                    2,
+                    9
+                ),
+                (
+                    Severity::Error,
+                    "expected identifier, found end of input".into(),
+                    3,
+                    5
+                ),
+            ]
+        );
+    }
 
-    builder.instruction(opcode::CLOSURE, function as u32);
-    builder.instruction(opcode::SPAWN, NO_OPERAND);
+    #[test]
+    fn recovers_within_a_statement_list() {
+        assert_eq!(
+            check("let a = 1 +; let b = 2 * ; b"),
+            vec![
+                (
+                    Severity::Error,
+                    "expected expression, found `;`".into(),
+                    1,
+                    12
+                ),
+                (
+                    Severity::Error,
+                    "expected expression, found `;`".into(),
+                    1,
+                    26
+                ),
+            ]
+        );
+    }
 
-    builder.instruction(opcode::INT, 2000);
-    builder.instruction(opcode::YIELD, NO_OPERAND);
+    #[test]
+    fn duplicate_parameters() {
+        assert_eq!(
+            check("fn f(a, a) { a } f(1, 2)"),
+            vec![(Severity::Error, "duplicate parameter `a`".into(), 1, 9)]
+        );
+        assert_eq!(
+            check("let g = fn(x, y, x) { x + y }; g"),
+            vec![(Severity::Error, "duplicate parameter `x`".into(), 1, 18)]
+        );
+    }
 
-    builder.instruction(opcode::RETURN, NO_OPERAND);
+    #[test]
+    fn duplicate_fields_and_variants() {
+        assert_eq!(
+            check("enum E { A, A(x, x) } E"),
+            vec![
+                (Severity::Error, "duplicate variant `A`".into(), 1, 13),
+                (Severity::Error, "duplicate field `x`".into(), 1, 18),
+            ]
+        );
+    }
 
-    Ok(builder.build_ptr(cx))
+    #[test]
+    fn reports_warnings_without_errors() {
+        assert_eq!(
+            check("let x = 1; 2"),
+            vec![(Severity::Warning, "unused variable `x`".into(), 1, 5)]
+        );
+        assert!(check("let _x = 1; 2").is_empty());
+    }
 }
@@ -0,0 +1,792 @@
+use crate::compile::{
+    ast::{
+        BinaryOp, Block, Expr, ExprKind, FunctionExpr, Ident, LogicalOp, MatchArm, Pattern,
+        PatternKind, Stmt, StmtKind, UnaryOp,
+    },
+    diagnostic::Issue,
+    lexer::{Lexer, Token, TokenKind},
+    span::Span,
+};
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+enum Precedence {
+    Lowest,
+    Assignment,
+    Or,
+    And,
+    Equality,
+    Comparison,
+    Term,
+    Factor,
+    Unary,
+    Call,
+}
+
+enum BlockItem {
+    Stmt(Stmt),
+    Expr(Expr),
+}
+
+pub struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    issues: Vec<Issue>,
+}
+
+impl Parser {
+    pub fn new(source: &str) -> Self {
+        let mut lexer = Lexer::new(source);
+        let mut tokens = Vec::new();
+        loop {
+            let token = lexer.next_token();
+            let is_eof = token.kind == TokenKind::Eof;
+            tokens.push(token);
+            if is_eof {
+                break;
+            }
+        }
+        Self {
+            tokens,
+            position: 0,
+            issues: lexer.take_issues(),
+        }
+    }
+
+    /// Parses the whole source, recovering from errors at statement boundaries so that as many
+    /// issues as possible are reported at once.
+    pub fn parse(mut self) -> (Block, Vec<Issue>) {
+        let start = self.peek_span();
+        let block = self.block_body(&TokenKind::Eof, start);
+        (block, self.issues)
+    }
+
+    fn block(&mut self) -> Result<Block, Issue> {
+        let start = self.peek_span();
+        self.expect(&TokenKind::LeftBrace)?;
+        let block = self.block_body(&TokenKind::RightBrace, start);
+        self.expect(&TokenKind::RightBrace)?;
+        Ok(Block {
+            span: start.to(self.previous_span()),
+            ..block
+        })
+    }
+
+    fn block_body(&mut self, end: &TokenKind, start: Span) -> Block {
+        let mut stmts = Vec::new();
+        let mut expr = None;
+
+        while self.peek() != end && self.peek() != &TokenKind::Eof {
+            if self.peek() == &TokenKind::RightBrace {
+                self.issues
+                    .push(Issue::error("unmatched `}`", self.peek_span()));
+                self.advance();
+                continue;
+            }
+
+            let position = self.position;
+            match self.block_item(end) {
+                Ok(BlockItem::Stmt(stmt)) => stmts.push(stmt),
+                Ok(BlockItem::Expr(block_expr)) => expr = Some(Box::new(block_expr)),
+                Err(issue) => {
+                    self.issues.push(issue);
+                    self.synchronize();
+                    // Always make progress, otherwise a stray token would make us loop forever.
+                    if self.position == position {
+                        self.advance();
+                    }
+                }
+            }
+        }
+
+        Block {
+            stmts,
+            expr,
+            span: start.to(self.previous_span()),
+        }
+    }
+
+    fn block_item(&mut self, end: &TokenKind) -> Result<BlockItem, Issue> {
+        let stmt = match self.peek() {
+            TokenKind::Let => self.let_stmt()?,
+            TokenKind::Fn if matches!(self.peek_second(), TokenKind::Identifier(_)) => {
+                self.function_stmt()?
+            }
+            TokenKind::Struct => self.struct_stmt()?,
+            TokenKind::Enum => self.enum_stmt()?,
+            TokenKind::Import => self.import_stmt()?,
+            TokenKind::Export => self.export_stmt()?,
+            TokenKind::Return => self.return_stmt()?,
+            TokenKind::Break => self.break_stmt()?,
+            TokenKind::Continue => self.continue_stmt()?,
+            TokenKind::Throw => self.throw_stmt()?,
+            _ => {
+                let expr = self.expression()?;
+                if self.eat(&TokenKind::Semicolon) {
+                    let span = expr.span.to(self.previous_span());
+                    Stmt::new(StmtKind::Expr(expr), span)
+                } else if self.peek() == end {
+                    return Ok(BlockItem::Expr(expr));
+                } else if expr.is_block_like() {
+                    let span = expr.span;
+                    Stmt::new(StmtKind::Expr(expr), span)
+                } else {
+                    return Err(self
+                        .unexpected("`;`")
+                        .with_note("expression statements must end with `;`"));
+                }
+            }
+        };
+        Ok(BlockItem::Stmt(stmt))
+    }
+
+    /// Skips tokens until the start of the next statement, or the end of the enclosing block.
+    fn synchronize(&mut self) {
+        let mut depth = 0usize;
+        loop {
+            match self.peek() {
+                TokenKind::Eof => return,
+                TokenKind::LeftBrace => depth += 1,
+                TokenKind::RightBrace if depth == 0 => return,
+                TokenKind::RightBrace => depth -= 1,
+                TokenKind::Semicolon if depth == 0 => {
+                    self.advance();
+                    return;
+                }
+                TokenKind::Let
+                | TokenKind::Fn
+                | TokenKind::Struct
+                | TokenKind::Enum
+                | TokenKind::Return
+                | TokenKind::Break
+                | TokenKind::Continue
+                | TokenKind::Throw
+                | TokenKind::Import
+                | TokenKind::Export
+                | TokenKind::Try
+                | TokenKind::If
+                | TokenKind::While
+                | TokenKind::Loop
+                | TokenKind::Match
+                    if depth == 0 =>
+                {
+                    return;
+                }
+                _ => {}
+            }
+            self.advance();
+        }
+    }
+
+    fn let_stmt(&mut self) -> Result<Stmt, Issue> {
+        let start = self.peek_span();
+        self.expect(&TokenKind::Let)?;
+        let name = self.identifier()?;
+        self.expect(&TokenKind::Equal)?;
+        let value = self.expression()?;
+        self.expect(&TokenKind::Semicolon)?;
+        Ok(Stmt::new(
+            StmtKind::Let(name, value),
+            start.to(self.previous_span()),
+        ))
+    }
+
+    fn function_stmt(&mut self) -> Result<Stmt, Issue> {
+        let start = self.peek_span();
+        self.expect(&TokenKind::Fn)?;
+        let name = self.identifier()?;
+        let function = self.function(Some(name), start)?;
+        let span = function.span;
+        Ok(Stmt::new(StmtKind::Function(function), span))
+    }
+
+    fn struct_stmt(&mut self) -> Result<Stmt, Issue> {
+        let start = self.peek_span();
+        self.expect(&TokenKind::Struct)?;
+        let name = self.identifier()?;
+        self.expect(&TokenKind::LeftBrace)?;
+        let fields = self.identifiers(&TokenKind::RightBrace)?;
+        Ok(Stmt::new(
+            StmtKind::Struct(name, fields),
+            start.to(self.previous_span()),
+        ))
+    }
+
+    fn enum_stmt(&mut self) -> Result<Stmt, Issue> {
+        let start = self.peek_span();
+        self.expect(&TokenKind::Enum)?;
+        let name = self.identifier()?;
+        self.expect(&TokenKind::LeftBrace)?;
+        let mut variants = Vec::new();
+        while self.peek() != &TokenKind::RightBrace {
+            let variant = self.identifier()?;
+            let fields = if self.eat(&TokenKind::LeftParen) {
+                self.identifiers(&TokenKind::RightParen)?
+            } else {
+                Vec::new()
+            };
+            variants.push((variant, fields));
+            if !self.eat(&TokenKind::Comma) {
+                break;
+            }
+        }
+        self.expect(&TokenKind::RightBrace)?;
+        Ok(Stmt::new(
+            StmtKind::Enum(name, variants),
+            start.to(self.previous_span()),
+        ))
+    }
+
+    fn import_stmt(&mut self) -> Result<Stmt, Issue> {
+        let start = self.peek_span();
+        self.expect(&TokenKind::Import)?;
+
+        // Without a list of names, the module is only imported for its side effects.
+        let mut names = Vec::new();
+        if self.eat(&TokenKind::LeftBrace) {
+            while self.peek() != &TokenKind::RightBrace {
+                names.push(self.identifier()?);
+                if !self.eat(&TokenKind::Comma) {
+                    break;
+                }
+            }
+            self.expect(&TokenKind::RightBrace)?;
+            self.expect(&TokenKind::From)?;
+        }
+
+        let path = match self.peek() {
+            TokenKind::String(path) => path.clone(),
+            _ => return Err(self.unexpected("module path")),
+        };
+        self.advance();
+        self.expect(&TokenKind::Semicolon)?;
+        Ok(Stmt::new(
+            StmtKind::Import(names, path),
+            start.to(self.previous_span()),
+        ))
+    }
+
+    fn export_stmt(&mut self) -> Result<Stmt, Issue> {
+        let start = self.peek_span();
+        self.expect(&TokenKind::Export)?;
+        let stmt = match self.peek() {
+            TokenKind::Let => self.let_stmt()?,
+            TokenKind::Fn => self.function_stmt()?,
+            TokenKind::Struct => self.struct_stmt()?,
+            TokenKind::Enum => self.enum_stmt()?,
+            _ => {
+                return Err(self
+                    .unexpected("`let`, `fn`, `struct` or `enum`")
+                    .with_note("only variables, functions, structs and enums can be exported"));
+            }
+        };
+        let span = start.to(stmt.span);
+        Ok(Stmt::new(StmtKind::Export(Box::new(stmt)), span))
+    }
+
+    fn return_stmt(&mut self) -> Result<Stmt, Issue> {
+        let start = self.peek_span();
+        self.expect(&TokenKind::Return)?;
+        let value = self.jump_value()?;
+        Ok(Stmt::new(
+            StmtKind::Return(value),
+            start.to(self.previous_span()),
+        ))
+    }
+
+    fn break_stmt(&mut self) -> Result<Stmt, Issue> {
+        let start = self.peek_span();
+        self.expect(&TokenKind::Break)?;
+        let value = self.jump_value()?;
+        Ok(Stmt::new(
+            StmtKind::Break(value),
+            start.to(self.previous_span()),
+        ))
+    }
+
+    fn continue_stmt(&mut self) -> Result<Stmt, Issue> {
+        let start = self.peek_span();
+        self.expect(&TokenKind::Continue)?;
+        self.jump_end()?;
+        Ok(Stmt::new(
+            StmtKind::Continue,
+            start.to(self.previous_span()),
+        ))
+    }
+
+    fn throw_stmt(&mut self) -> Result<Stmt, Issue> {
+        let start = self.peek_span();
+        self.expect(&TokenKind::Throw)?;
+        let value = self.expression()?;
+        self.jump_end()?;
+        Ok(Stmt::new(
+            StmtKind::Throw(value),
+            start.to(self.previous_span()),
+        ))
+    }
+
+    /// Parses the optional value of a `return` or `break`, followed by its `;`.
+    fn jump_value(&mut self) -> Result<Option<Expr>, Issue> {
+        let value = if matches!(
+            self.peek(),
+            TokenKind::Semicolon | TokenKind::RightBrace | TokenKind::Eof
+        ) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        self.jump_end()?;
+        Ok(value)
+    }
+
+    /// Jumps are always the last thing to run in a block, so the `;` can be left out at the end.
+    fn jump_end(&mut self) -> Result<(), Issue> {
+        if !matches!(self.peek(), TokenKind::RightBrace | TokenKind::Eof) {
+            self.expect(&TokenKind::Semicolon)?;
+        }
+        Ok(())
+    }
+
+    /// Parses the rest of an `if` expression, after the `if` keyword.
+    fn if_expr(&mut self) -> Result<ExprKind, Issue> {
+        let condition = self.expression()?;
+        let then = self.block()?;
+        let otherwise = if self.eat(&TokenKind::Else) {
+            let start = self.peek_span();
+            let kind = if self.eat(&TokenKind::If) {
+                self.if_expr()?
+            } else {
+                ExprKind::Block(self.block()?)
+            };
+            Some(Box::new(Expr::new(kind, start.to(self.previous_span()))))
+        } else {
+            None
+        };
+        Ok(ExprKind::If(Box::new(condition), then, otherwise))
+    }
+
+    /// Parses the rest of a `match` expression, after the `match` keyword.
+    fn match_expr(&mut self) -> Result<ExprKind, Issue> {
+        let scrutinee = self.expression()?;
+        self.expect(&TokenKind::LeftBrace)?;
+        let mut arms = Vec::new();
+        while self.peek() != &TokenKind::RightBrace {
+            let pattern = self.pattern()?;
+            let guard = if self.eat(&TokenKind::If) {
+                Some(self.expression()?)
+            } else {
+                None
+            };
+            self.expect(&TokenKind::FatArrow)?;
+            let body = self.expression()?;
+            // As with statements, the comma after a body ending with a block can be left out.
+            let needs_comma = !body.is_block_like();
+            arms.push(MatchArm {
+                pattern,
+                guard,
+                body,
+            });
+            if !self.eat(&TokenKind::Comma) && needs_comma {
+                break;
+            }
+        }
+        self.expect(&TokenKind::RightBrace)?;
+        Ok(ExprKind::Match(Box::new(scrutinee), arms))
+    }
+
+    fn pattern(&mut self) -> Result<Pattern, Issue> {
+        let start = self.peek_span();
+        let kind = match self.peek() {
+            TokenKind::Identifier(name) if name == "_" => {
+                self.advance();
+                PatternKind::Wildcard
+            }
+            TokenKind::Identifier(_) if self.peek_second() == &TokenKind::Dot => {
+                let ty = self.identifier()?;
+                self.expect(&TokenKind::Dot)?;
+                let variant = self.identifier()?;
+                let mut fields = Vec::new();
+                if self.eat(&TokenKind::LeftParen) {
+                    while self.peek() != &TokenKind::RightParen {
+                        fields.push(self.pattern()?);
+                        if !self.eat(&TokenKind::Comma) {
+                            break;
+                        }
+                    }
+                    self.expect(&TokenKind::RightParen)?;
+                }
+                PatternKind::Variant(ty, variant, fields)
+            }
+            TokenKind::Identifier(_) => PatternKind::Binding(self.identifier()?),
+            TokenKind::Nil
+            | TokenKind::True
+            | TokenKind::False
+            | TokenKind::Int(_)
+            | TokenKind::Float(_)
+            | TokenKind::String(_) => PatternKind::Literal(self.prefix()?),
+            TokenKind::Minus
+                if matches!(self.peek_second(), TokenKind::Int(_) | TokenKind::Float(_)) =>
+            {
+                self.advance();
+                let number = self.prefix()?;
+                let span = start.to(number.span);
+                PatternKind::Literal(Expr::new(
+                    ExprKind::Unary(UnaryOp::Negate, Box::new(number)),
+                    span,
+                ))
+            }
+            _ => return Err(self.unexpected("pattern")),
+        };
+        Ok(Pattern::new(kind, start.to(self.previous_span())))
+    }
+
+    fn function(&mut self, name: Option<Ident>, start: Span) -> Result<FunctionExpr, Issue> {
+        self.expect(&TokenKind::LeftParen)?;
+        let mut params = Vec::new();
+        while self.peek() != &TokenKind::RightParen {
+            params.push(self.identifier()?);
+            if !self.eat(&TokenKind::Comma) {
+                break;
+            }
+        }
+        self.expect(&TokenKind::RightParen)?;
+        let body = self.block()?;
+        Ok(FunctionExpr {
+            name,
+            params,
+            body,
+            span: start.to(self.previous_span()),
+        })
+    }
+
+    fn expression(&mut self) -> Result<Expr, Issue> {
+        self.precedence(Precedence::Lowest)
+    }
+
+    fn precedence(&mut self, precedence: Precedence) -> Result<Expr, Issue> {
+        let mut expr = self.prefix()?;
+
+        loop {
+            let (op, op_precedence) = match self.peek() {
+                TokenKind::Plus => (BinaryOp::Add, Precedence::Term),
+                TokenKind::Minus => (BinaryOp::Sub, Precedence::Term),
+                TokenKind::Star => (BinaryOp::Mul, Precedence::Factor),
+                TokenKind::Slash => (BinaryOp::Div, Precedence::Factor),
+                TokenKind::Percent => (BinaryOp::Mod, Precedence::Factor),
+                TokenKind::EqualEqual => (BinaryOp::Equal, Precedence::Equality),
+                TokenKind::BangEqual => (BinaryOp::NotEqual, Precedence::Equality),
+                TokenKind::Less => (BinaryOp::Less, Precedence::Comparison),
+                TokenKind::LessEqual => (BinaryOp::LessEqual, Precedence::Comparison),
+                TokenKind::Greater => (BinaryOp::Greater, Precedence::Comparison),
+                TokenKind::GreaterEqual => (BinaryOp::GreaterEqual, Precedence::Comparison),
+                TokenKind::In => (BinaryOp::In, Precedence::Comparison),
+                TokenKind::And | TokenKind::Or => {
+                    let (op, op_precedence) = match self.peek() {
+                        TokenKind::And => (LogicalOp::And, Precedence::And),
+                        _ => (LogicalOp::Or, Precedence::Or),
+                    };
+                    if op_precedence <= precedence {
+                        break;
+                    }
+                    self.advance();
+                    let right = self.precedence(op_precedence)?;
+                    let span = expr.span.to(right.span);
+                    expr = Expr::new(ExprKind::Logical(op, Box::new(expr), Box::new(right)), span);
+                    continue;
+                }
+                TokenKind::Equal if precedence < Precedence::Assignment => {
+                    if !expr.is_assignable() {
+                        return Err(Issue::error("invalid assignment target", expr.span)
+                            .with_note(
+                                "only variables, fields, list items and map entries can be assigned to",
+                            ));
+                    }
+                    self.advance();
+                    // Parse with the lowest precedence, so that assignment is right associative.
+                    let value = self.precedence(Precedence::Lowest)?;
+                    let span = expr.span.to(value.span);
+                    expr = Expr::new(ExprKind::Assign(Box::new(expr), Box::new(value)), span);
+                    continue;
+                }
+                TokenKind::LeftParen if precedence < Precedence::Call => {
+                    self.advance();
+                    let args = self.arguments()?;
+                    let span = expr.span.to(self.previous_span());
+                    expr = Expr::new(ExprKind::Call(Box::new(expr), args), span);
+                    continue;
+                }
+                TokenKind::LeftBracket if precedence < Precedence::Call => {
+                    self.advance();
+                    let index = self.expression()?;
+                    self.expect(&TokenKind::RightBracket)?;
+                    let span = expr.span.to(self.previous_span());
+                    expr = Expr::new(ExprKind::Index(Box::new(expr), Box::new(index)), span);
+                    continue;
+                }
+                TokenKind::Dot if precedence < Precedence::Call => {
+                    self.advance();
+                    let name = self.identifier()?;
+                    let start = expr.span;
+                    let kind = if self.eat(&TokenKind::LeftParen) {
+                        ExprKind::Method(Box::new(expr), name, self.arguments()?)
+                    } else {
+                        ExprKind::Field(Box::new(expr), name)
+                    };
+                    expr = Expr::new(kind, start.to(self.previous_span()));
+                    continue;
+                }
+                _ => break,
+            };
+            if op_precedence <= precedence {
+                break;
+            }
+            self.advance();
+            let right = self.precedence(op_precedence)?;
+            let span = expr.span.to(right.span);
+            expr = Expr::new(ExprKind::Binary(op, Box::new(expr), Box::new(right)), span);
+        }
+
+        Ok(expr)
+    }
+
+    fn prefix(&mut self) -> Result<Expr, Issue> {
+        let position = self.position;
+        let token = self.advance();
+        let kind = match token.kind {
+            TokenKind::Nil => ExprKind::Nil,
+            TokenKind::True => ExprKind::Bool(true),
+            TokenKind::False => ExprKind::Bool(false),
+            TokenKind::Int(int) => ExprKind::Int(int),
+            TokenKind::Float(float) => ExprKind::Float(float),
+            TokenKind::String(string) => ExprKind::String(string),
+            TokenKind::Identifier(identifier) if self.is_record_start() => {
+                let ty = Expr::new(ExprKind::Identifier(identifier), token.span);
+                ExprKind::Record(Box::new(ty), self.record_fields()?)
+            }
+            TokenKind::Identifier(identifier) => ExprKind::Identifier(identifier),
+            TokenKind::LeftParen => {
+                let expr = self.expression()?;
+                self.expect(&TokenKind::RightParen)?;
+                expr.kind
+            }
+            TokenKind::LeftBracket => {
+                let mut items = Vec::new();
+                while self.peek() != &TokenKind::RightBracket {
+                    items.push(self.expression()?);
+                    if !self.eat(&TokenKind::Comma) {
+                        break;
+                    }
+                }
+                self.expect(&TokenKind::RightBracket)?;
+                ExprKind::List(items)
+            }
+            TokenKind::LeftBrace if self.is_map_start() => ExprKind::Map(self.map_entries()?),
+            TokenKind::LeftBrace => {
+                let block = self.block_body(&TokenKind::RightBrace, token.span);
+                self.expect(&TokenKind::RightBrace)?;
+                ExprKind::Block(Block {
+                    span: token.span.to(self.previous_span()),
+                    ..block
+                })
+            }
+            TokenKind::Minus => {
+                let operand = self.precedence(Precedence::Unary)?;
+                ExprKind::Unary(UnaryOp::Negate, Box::new(operand))
+            }
+            TokenKind::Not => {
+                // Unlike `-`, `not` applies to a whole comparison, so `not a < b` works as expected.
+                let operand = self.precedence(Precedence::And)?;
+                ExprKind::Unary(UnaryOp::Not, Box::new(operand))
+            }
+            TokenKind::If => self.if_expr()?,
+            TokenKind::Match => self.match_expr()?,
+            TokenKind::While => {
+                let condition = self.expression()?;
+                let body = self.block()?;
+                ExprKind::While(Box::new(condition), body)
+            }
+            TokenKind::Loop => ExprKind::Loop(self.block()?),
+            TokenKind::Try => {
+                let body = self.block()?;
+                self.expect(&TokenKind::Catch)?;
+                let name = match self.peek() {
+                    TokenKind::Identifier(_) => Some(self.identifier()?),
+                    _ => None,
+                };
+                let handler = self.block()?;
+                ExprKind::Try(body, name, handler)
+            }
+            TokenKind::Fn => ExprKind::Function(self.function(None, token.span)?),
+            TokenKind::Spawn => ExprKind::Spawn(Box::new(self.expression()?)),
+            TokenKind::Yield => ExprKind::Yield(Box::new(self.expression()?)),
+            TokenKind::Join => {
+                // Bind tightly, so that `join a + join b` adds the results of both fibers.
+                let fiber = self.precedence(Precedence::Unary)?;
+                ExprKind::Join(Box::new(fiber))
+            }
+            _ => {
+                // Leave the offending token in place, so that recovery can make use of it.
+                self.position = position;
+                return Err(self.unexpected("expression"));
+            }
+        };
+        Ok(Expr::new(kind, token.span.to(self.previous_span())))
+    }
+
+    /// Whether the identifier just consumed is the type of a record literal, which is the case if
+    /// it is followed by `{`, a field name and `:`. Blocks never start like this, so this doesn't
+    /// get in the way of `if ready { ... }`.
+    fn is_record_start(&self) -> bool {
+        self.peek() == &TokenKind::LeftBrace
+            && matches!(self.peek_second(), TokenKind::Identifier(_))
+            && self.peek_nth(2) == &TokenKind::Colon
+    }
+
+    fn record_fields(&mut self) -> Result<Vec<(Ident, Expr)>, Issue> {
+        self.expect(&TokenKind::LeftBrace)?;
+        let mut fields = Vec::new();
+        while self.peek() != &TokenKind::RightBrace {
+            let name = self.identifier()?;
+            self.expect(&TokenKind::Colon)?;
+            let value = self.expression()?;
+            fields.push((name, value));
+            if !self.eat(&TokenKind::Comma) {
+                break;
+            }
+        }
+        self.expect(&TokenKind::RightBrace)?;
+        Ok(fields)
+    }
+
+    /// Whether the `{` just consumed starts a map rather than a block, which is the case if it is
+    /// empty or starts with a key followed by `:`.
+    fn is_map_start(&self) -> bool {
+        match self.peek() {
+            TokenKind::RightBrace => true,
+            TokenKind::Identifier(_)
+            | TokenKind::String(_)
+            | TokenKind::Int(_)
+            | TokenKind::True
+            | TokenKind::False
+            | TokenKind::Nil => self.peek_second() == &TokenKind::Colon,
+            _ => false,
+        }
+    }
+
+    /// Parses the entries of a map up to its closing `}`. Identifier keys are shorthand for
+    /// strings, as in `{ name: "doji" }`.
+    fn map_entries(&mut self) -> Result<Vec<(Expr, Expr)>, Issue> {
+        let mut entries = Vec::new();
+        while self.peek() != &TokenKind::RightBrace {
+            let key = match self.peek() {
+                TokenKind::Identifier(_) => {
+                    let name = self.identifier()?;
+                    Expr::new(ExprKind::String(name.name), name.span)
+                }
+                _ => self.prefix()?,
+            };
+            self.expect(&TokenKind::Colon)?;
+            let value = self.expression()?;
+            entries.push((key, value));
+            if !self.eat(&TokenKind::Comma) {
+                break;
+            }
+        }
+        self.expect(&TokenKind::RightBrace)?;
+        Ok(entries)
+    }
+
+    /// Parses a comma separated list of identifiers, up to and including the closing token.
+    fn identifiers(&mut self, end: &TokenKind) -> Result<Vec<Ident>, Issue> {
+        let mut identifiers = Vec::new();
+        while self.peek() != end {
+            identifiers.push(self.identifier()?);
+            if !self.eat(&TokenKind::Comma) {
+                break;
+            }
+        }
+        self.expect(end)?;
+        Ok(identifiers)
+    }
+
+    fn arguments(&mut self) -> Result<Vec<Expr>, Issue> {
+        let mut args = Vec::new();
+        while self.peek() != &TokenKind::RightParen {
+            args.push(self.expression()?);
+            if !self.eat(&TokenKind::Comma) {
+                break;
+            }
+        }
+        self.expect(&TokenKind::RightParen)?;
+        Ok(args)
+    }
+
+    fn identifier(&mut self) -> Result<Ident, Issue> {
+        match self.peek() {
+            TokenKind::Identifier(name) => {
+                let name = name.clone();
+                let span = self.advance().span;
+                Ok(Ident { name, span })
+            }
+            _ => Err(self.unexpected("identifier")),
+        }
+    }
+
+    fn expect(&mut self, kind: &TokenKind) -> Result<(), Issue> {
+        if self.eat(kind) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&kind.to_string()))
+        }
+    }
+
+    fn eat(&mut self, kind: &TokenKind) -> bool {
+        if self.peek() == kind {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn unexpected(&self, expected: &str) -> Issue {
+        Issue::error(
+            format!("expected {}, found {}", expected, self.peek()),
+            self.peek_span(),
+        )
+    }
+
+    fn peek(&self) -> &TokenKind {
+        &self.tokens[self.position].kind
+    }
+
+    fn peek_second(&self) -> &TokenKind {
+        self.peek_nth(1)
+    }
+
+    /// The token `n` tokens after the current one.
+    fn peek_nth(&self, n: usize) -> &TokenKind {
+        self.tokens
+            .get(self.position + n)
+            .map_or(&TokenKind::Eof, |token| &token.kind)
+    }
+
+    fn peek_span(&self) -> Span {
+        self.tokens[self.position].span
+    }
+
+    fn previous_span(&self) -> Span {
+        match self.position {
+            0 => self.peek_span(),
+            position => self.tokens[position - 1].span,
+        }
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.position].clone();
+        // Never move past the end of input token.
+        if self.position < self.tokens.len() - 1 {
+            self.position += 1;
+        }
+        token
+    }
+}
@@ -0,0 +1,46 @@
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Span {
+    start: usize,
+    end: usize,
+    line: u32,
+    column: u32,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize, line: u32, column: u32) -> Self {
+        Self {
+            start,
+            end,
+            line,
+            column,
+        }
+    }
+
+    /// Byte offset of the start of this span.
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// Byte offset just past the end of this span.
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    /// One-based line number of the start of this span.
+    pub fn line(&self) -> u32 {
+        self.line
+    }
+
+    /// One-based column (in characters) of the start of this span.
+    pub fn column(&self) -> u32 {
+        self.column
+    }
+
+    /// Returns a span which starts at this span and ends at the other span.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            end: other.end.max(self.end),
+            ..self
+        }
+    }
+}
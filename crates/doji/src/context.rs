@@ -1,7 +1,11 @@
 use gc_arena::Mutation;
 
 use crate::{
-    ClosurePtr, closure::ClosureValue, compile::compile, error::ErrorPtr, fiber::FiberPtr,
+    ClosurePtr,
+    closure::ClosureValue,
+    compile::{Diagnostics, SourceKind, compile},
+    fiber::FiberPtr,
+    stash::{Fetchable, Stashable},
     state::State,
 };
 
@@ -15,10 +19,47 @@ impl<'gc> Context<'gc> {
         Self { mutation, state }
     }
 
-    pub fn compile(&self, source: &str) -> Result<ClosurePtr<'gc>, ErrorPtr<'gc>> {
-        let function = compile(self, source.as_ref())?;
-        let closure = ClosureValue::new_ptr(self, function);
-        Ok(closure)
+    /// Compiles the source into a closure. If compilation fails, all of the errors and warnings
+    /// are returned. Otherwise, any warnings are kept for [`Engine::warnings`].
+    ///
+    /// [`Engine::warnings`]: crate::Engine::warnings
+    pub fn compile(&self, name: &str, source: &str) -> Result<ClosurePtr<'gc>, Diagnostics> {
+        self.compile_as(name, source, SourceKind::Script)
+    }
+
+    /// Compiles the source of an imported module into a closure, in the same way as
+    /// [`Context::compile`].
+    pub(crate) fn compile_module(
+        &self,
+        name: &str,
+        source: &str,
+    ) -> Result<ClosurePtr<'gc>, Diagnostics> {
+        self.compile_as(name, source, SourceKind::Module)
+    }
+
+    fn compile_as(
+        &self,
+        name: &str,
+        source: &str,
+        kind: SourceKind,
+    ) -> Result<ClosurePtr<'gc>, Diagnostics> {
+        match compile(self, name, source, kind) {
+            (Some(function), warnings) => {
+                self.state.add_warnings(warnings);
+                Ok(ClosureValue::new_ptr(self, function))
+            }
+            (None, diagnostics) => Err(diagnostics),
+        }
+    }
+
+    /// Stashes the value so that it can be held onto outside of the arena.
+    pub fn stash<S: Stashable<'gc>>(&self, value: S) -> S::Stashed {
+        value.stash(self)
+    }
+
+    /// Fetches a stashed value back into the arena.
+    pub fn fetch<F: Fetchable>(&self, stashed: &F) -> F::Fetched<'gc> {
+        stashed.fetch(self)
     }
 
     pub fn spawn(&self, closure: ClosurePtr<'gc>) -> FiberPtr<'gc> {
@@ -24,7 +24,7 @@ impl Driver {
             .insert(id, (Instant::now(), duration as u64));
     }
 
-    pub fn poll<'gc>(&self, cx: &Context<'gc>) -> Option<(Id, Value<'gc>)> {
+    pub fn poll<'gc>(&self, _cx: &Context<'gc>) -> Option<(Id, Value<'gc>)> {
         let now = Instant::now();
         let ops = self.ops.borrow();
         let entry = ops.iter().find(|(_, (start_time, duration))| {
@@ -1,13 +1,17 @@
-use std::path::Path;
+use std::{fs, path::Path};
 
 use gc_arena::{Arena as GcArena, Rootable};
 
 use crate::{
+    closure::ClosurePtr,
+    compile::{Diagnostics, SourceKind, compile},
     context::Context,
     driver::Driver,
-    error::Error,
+    error::{Error, ErrorPtr, ErrorValue},
+    module::{FileModuleLoader, ModuleLoader},
+    native::{NativeFunction, NativeFunctionPtr},
     state::{State, Step},
-    value::TryFromValue,
+    value::{IntoArgs, IntoValue, TryFromValue, Value},
 };
 
 pub struct Engine {
@@ -29,12 +33,114 @@ impl Engine {
     where
         T: for<'gc> TryFromValue<'gc>,
     {
-        // Compile and spawn the initial fiber.
-        self.enter::<Result<_, Error>>(|cx| {
-            let closure = cx.compile(source.as_ref())?;
-            cx.spawn(closure);
-            Ok(())
+        self.evaluate("<inline>", source.as_ref())
+    }
+
+    /// Reads the file and evaluates it like [`Engine::evaluate_inline`], reporting diagnostics and
+    /// traces against its path.
+    pub fn evaluate_file<T>(&self, path: impl AsRef<Path>) -> Result<T, Error>
+    where
+        T: for<'gc> TryFromValue<'gc>,
+    {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path).map_err(|error| Error::Io {
+            path: path.to_path_buf(),
+            error,
         })?;
+        self.evaluate(&path.display().to_string(), &source)
+    }
+
+    /// Calls the function with the arguments, running the scheduler until it returns. Fibers it
+    /// spawns keep running alongside it.
+    ///
+    /// Functions can be called by the name of a global, which includes every function declared at
+    /// the top level of a script that has been evaluated, but not those of imported modules.
+    pub fn call<T, A>(&self, callee: impl Callee, args: A) -> Result<T, Error>
+    where
+        T: for<'gc> TryFromValue<'gc>,
+        A: IntoArgs,
+    {
+        self.call_value(|cx| callee.callee(cx), args)
+    }
+
+    /// Like [`Engine::call`], but the closure to call is picked inside the arena, such as one
+    /// returned by an earlier evaluation.
+    pub fn call_with<T, A>(
+        &self,
+        callee: impl for<'gc> FnOnce(&Context<'gc>) -> ClosurePtr<'gc>,
+        args: A,
+    ) -> Result<T, Error>
+    where
+        T: for<'gc> TryFromValue<'gc>,
+        A: IntoArgs,
+    {
+        self.call_value(|cx| Ok(callee(cx).into()), args)
+    }
+
+    fn call_value<T, A>(
+        &self,
+        callee: impl for<'gc> FnOnce(&Context<'gc>) -> Result<Value<'gc>, Error>,
+        args: A,
+    ) -> Result<T, Error>
+    where
+        T: for<'gc> TryFromValue<'gc>,
+        A: IntoArgs,
+    {
+        self.run(|cx| {
+            let callee = callee(cx)?;
+            let args = args.into_args(cx)?;
+
+            // Natives don't need a fiber, so they return straight away.
+            if let Ok(native) = callee.try_into::<NativeFunctionPtr>(cx) {
+                return Ok(Some(native.call(cx, &args)?.try_into(cx)?));
+            }
+
+            let closure: ClosurePtr = callee.try_into(cx).map_err(|_| {
+                ErrorValue::new_message_ptr(
+                    cx,
+                    format!("cannot call a value of type {}", callee.ty()),
+                )
+            })?;
+            closure.check_arity(cx, args.len())?;
+
+            // The arguments are placed at the bottom of the frame, as if they had been passed by
+            // a caller.
+            let fiber = cx.state().spawn_root(cx, closure);
+            let mut fiber = fiber.borrow_mut(cx.mutation());
+            for arg in args {
+                fiber.push(cx, arg);
+            }
+            Ok(None)
+        })
+    }
+
+    /// Compiles the source under the given name and runs it to completion.
+    fn evaluate<T>(&self, name: &str, source: &str) -> Result<T, Error>
+    where
+        T: for<'gc> TryFromValue<'gc>,
+    {
+        self.run(|cx| {
+            let closure = cx.compile(name, source)?;
+            cx.state().spawn_root(cx, closure);
+            Ok(None)
+        })
+    }
+
+    /// Starts an evaluation by spawning its root fiber, and runs the scheduler until that fiber
+    /// finishes. The evaluation may also finish straight away with a result.
+    fn run<T>(
+        &self,
+        start: impl for<'gc> FnOnce(&Context<'gc>) -> Result<Option<T>, Error>,
+    ) -> Result<T, Error>
+    where
+        T: for<'gc> TryFromValue<'gc>,
+    {
+        // Warnings are only kept for the latest evaluation.
+        self.enter(|cx| cx.state().clear_warnings());
+
+        if let Some(ret_value) = self.enter(start)? {
+            return Ok(ret_value);
+        }
 
         loop {
             // Run one step of the evaluation on the state. Return Some value if the evaluation is
@@ -46,6 +152,8 @@ impl Engine {
                     Ok(None)
                 }
                 Step::Return(value) => Ok(Some(value.try_into(cx)?)),
+                Step::Error(error) => Err(error.into()),
+                Step::Fatal(error) => Err(error.into()),
             })?;
 
             // If the evaluation is complete, return the result.
@@ -56,30 +164,304 @@ impl Engine {
             // Poll the driver for any completed operations, and wake the fibers if we find any.
             // Since waking a fiber doesn't allocate much memory, we are fine to poll to completion
             // in a single arena mutation.
-            self.enter(|cx| {
+            self.enter::<Result<_, Error>>(|cx| {
                 while let Some((id, res)) = self.driver.poll(cx) {
-                    cx.state().wake(cx, id, res);
+                    cx.state().wake(cx, id, res)?;
                 }
-            });
+                Ok(())
+            })?;
         }
     }
 
-    pub fn evaluate_file<T>(&self, path: impl AsRef<Path>) -> Result<T, Error>
+    /// Sets the global, defining it if it doesn't exist yet. Only scripts compiled afterwards can
+    /// refer to newly defined globals, and none of them can define a function over it. Fails if
+    /// the value cannot be converted.
+    pub fn set_global<V>(&self, name: impl Into<String>, value: V) -> Result<(), Error>
+    where
+        V: for<'gc> IntoValue<'gc>,
+    {
+        self.enter(|cx| {
+            let value = value.into_value(cx)?;
+            cx.state().set_host_global(cx, name, value);
+            Ok(())
+        })
+    }
+
+    /// Returns the value of the global, or `None` if it isn't defined.
+    pub fn get_global<T>(&self, name: &str) -> Result<Option<T>, Error>
     where
         T: for<'gc> TryFromValue<'gc>,
     {
-        unimplemented!()
+        self.enter(|cx| match cx.state().global(name) {
+            Some(value) => Ok(Some(value.try_into(cx)?)),
+            None => Ok(None),
+        })
+    }
+
+    /// Runs a full garbage collection, freeing every value that can no longer be reached from the
+    /// engine or from a stashed handle.
+    pub fn collect_all(&mut self) {
+        self.arena.collect_all();
+    }
+
+    /// The warnings reported while compiling the source of the latest evaluation or call, and any
+    /// modules it imported, with one set for each source that had any. They are kept whether or
+    /// not it succeeded.
+    pub fn warnings(&self) -> Vec<Diagnostics> {
+        self.enter(|cx| cx.state().warnings())
+    }
+
+    /// Compiles the source without evaluating it, returning all the errors and warnings found.
+    pub fn check(&self, source: impl AsRef<str>) -> Diagnostics {
+        self.enter(|cx| compile(cx, "<inline>", source.as_ref(), SourceKind::Script).1)
     }
 }
 
-#[derive(Default)]
-pub struct EngineBuilder {}
+/// Creates the value of a global once the engine has been built.
+type GlobalInit = Box<dyn for<'gc> FnOnce(&Context<'gc>) -> Result<Value<'gc>, ErrorPtr<'gc>>>;
+
+pub struct EngineBuilder {
+    module_loader: Box<dyn ModuleLoader>,
+    globals: Vec<(String, GlobalInit)>,
+}
 
 impl EngineBuilder {
+    /// Sets where the modules imported by scripts are loaded from. By default, they are read from
+    /// files relative to the file importing them, or to the current working directory.
+    pub fn module_loader(mut self, module_loader: impl ModuleLoader + 'static) -> Self {
+        self.module_loader = Box::new(module_loader);
+        self
+    }
+
+    /// Defines a global which every script can refer to by the name, unless it is shadowed by a
+    /// variable. Defining the same name again replaces the value.
+    ///
+    /// # Panics
+    ///
+    /// The engine panics when it is built if the value cannot be converted, such as an integer
+    /// too large for an int.
+    pub fn global<V>(mut self, name: impl Into<String>, value: V) -> Self
+    where
+        V: for<'gc> IntoValue<'gc> + 'static,
+    {
+        self.globals
+            .push((name.into(), Box::new(|cx| value.into_value(cx))));
+        self
+    }
+
+    /// Defines a global Rust function which scripts can call like any other function.
+    pub fn function<F>(mut self, name: impl Into<String>, function: F) -> Self
+    where
+        F: for<'gc> Fn(&Context<'gc>, &[Value<'gc>]) -> Result<Value<'gc>, ErrorPtr<'gc>> + 'static,
+    {
+        let name = name.into();
+        let native_name = name.clone();
+        self.globals.push((
+            name,
+            Box::new(|cx| Ok(NativeFunction::new_ptr(cx, native_name, Box::new(function)).into())),
+        ));
+        self
+    }
+
     pub fn build(self) -> Engine {
-        Engine {
-            arena: GcArena::new(|mutation| State::new(mutation)),
+        let engine = Engine {
+            arena: GcArena::new(|mutation| State::new(mutation, self.module_loader)),
             driver: Driver::default(),
+        };
+        engine.enter(|cx| {
+            for (name, init) in self.globals {
+                let value = init(cx).unwrap_or_else(|error| {
+                    panic!(
+                        "could not convert the value of global `{}`: {}",
+                        name, error
+                    )
+                });
+                cx.state().set_host_global(cx, name, value);
+            }
+        });
+        engine
+    }
+}
+
+impl Default for EngineBuilder {
+    fn default() -> Self {
+        Self {
+            module_loader: Box::new(FileModuleLoader::default()),
+            globals: Vec::new(),
         }
     }
 }
+
+/// Something that can be called from Rust with [`Engine::call`].
+pub trait Callee {
+    /// Finds the value to call inside the arena.
+    fn callee<'gc>(self, cx: &Context<'gc>) -> Result<Value<'gc>, Error>;
+}
+
+/// Calls the global with the name.
+impl Callee for &str {
+    fn callee<'gc>(self, cx: &Context<'gc>) -> Result<Value<'gc>, Error> {
+        cx.state().global(self).ok_or_else(|| {
+            ErrorValue::new_message_ptr(cx, format!("undefined global `{}`", self)).into()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn messages(warnings: &[Diagnostics]) -> Vec<String> {
+        warnings
+            .iter()
+            .flat_map(Diagnostics::iter)
+            .map(|diagnostic| diagnostic.message().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn evaluation_keeps_warnings() {
+        let engine = Engine::builder().build();
+        let value: i64 = engine.evaluate_inline("let unused = 1; 2").unwrap();
+        assert_eq!(value, 2);
+        assert_eq!(messages(&engine.warnings()), ["unused variable `unused`"]);
+
+        // Only the warnings of the latest evaluation are kept.
+        let _: i64 = engine.evaluate_inline("3").unwrap();
+        assert!(engine.warnings().is_empty());
+    }
+
+    #[test]
+    fn call_script_functions_by_name() {
+        let engine = Engine::builder().build();
+        let _: () = engine
+            .evaluate_inline("fn add(a, b) { a + b } fn unused() {} nil")
+            .unwrap();
+        assert!(engine.warnings().is_empty());
+        assert_eq!(engine.call::<i64, _>("add", (1, 2)).unwrap(), 3);
+
+        // Later scripts can refer to them too.
+        assert_eq!(engine.evaluate_inline::<i64>("add(3, 4)").unwrap(), 7);
+    }
+
+    #[test]
+    fn only_top_level_functions_are_globals() {
+        let engine = Engine::builder().build();
+        let _: () = engine
+            .evaluate_inline("let value = 1; fn f() { fn inner() {} inner } { fn g() {} g(); } nil")
+            .unwrap();
+        for name in ["value", "inner", "g"] {
+            match engine.call::<(), _>(name, ()) {
+                Err(Error::Runtime { message, .. }) => {
+                    assert_eq!(message, format!("undefined global `{}`", name))
+                }
+                result => panic!(
+                    "expected `{}` to be undefined, got {:?}",
+                    name,
+                    result.is_ok()
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn read_host_globals() {
+        let engine = Engine::builder().global("limit", 10).build();
+        assert_eq!(engine.evaluate_inline::<i64>("limit + 1").unwrap(), 11);
+        engine.set_global("step", 2).unwrap();
+        assert_eq!(engine.evaluate_inline::<i64>("limit + step").unwrap(), 12);
+        // Variables shadow globals.
+        assert_eq!(
+            engine
+                .evaluate_inline::<i64>("let limit = 1; limit")
+                .unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn assign_globals() {
+        let engine = Engine::builder().global("count", 0).build();
+        let _: () = engine.evaluate_inline("count = count + 5;").unwrap();
+        assert_eq!(engine.get_global::<i64>("count").unwrap(), Some(5));
+        assert_eq!(engine.evaluate_inline::<i64>("count").unwrap(), 5);
+        assert_eq!(engine.get_global::<i64>("missing").unwrap(), None);
+    }
+
+    #[test]
+    fn assign_unknown_globals() {
+        let engine = Engine::builder().build();
+        match engine.evaluate_inline::<()>("missing = 1;") {
+            Err(Error::Compile(diagnostics)) => assert_eq!(
+                diagnostics
+                    .iter()
+                    .map(|diagnostic| diagnostic.message())
+                    .collect::<Vec<_>>(),
+                ["cannot find `missing` in this scope"]
+            ),
+            result => panic!("expected a compile error, got {:?}", result.is_ok()),
+        }
+        assert_eq!(engine.get_global::<i64>("missing").unwrap(), None);
+    }
+
+    #[test]
+    fn module_functions_are_not_globals() {
+        let modules = HashMap::from([(
+            "m".to_string(),
+            "fn helper() { 1 } export fn get() { helper() }".to_string(),
+        )]);
+        let engine = Engine::builder().module_loader(modules).build();
+        let value: i64 = engine
+            .evaluate_inline(r#"import { get } from "m"; get()"#)
+            .unwrap();
+        assert_eq!(value, 1);
+        for name in ["helper", "get"] {
+            match engine.call::<i64, _>(name, ()) {
+                Err(Error::Runtime { message, .. }) => {
+                    assert_eq!(message, format!("undefined global `{}`", name))
+                }
+                result => panic!(
+                    "expected `{}` to be undefined, got {:?}",
+                    name,
+                    result.is_ok()
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn script_functions_do_not_replace_host_globals() {
+        let engine = Engine::builder()
+            .function("answer", |cx, _| 42.into_value(cx))
+            .global("limit", 10)
+            .build();
+        let value: i64 = engine
+            .evaluate_inline("fn answer() { 0 } fn limit() { 0 } answer() + limit()")
+            .unwrap();
+        // The script's own function shadows the native, but only within the script.
+        assert_eq!(value, 0);
+        assert_eq!(
+            messages(&engine.warnings()),
+            [
+                "`answer` is already a global defined by the host",
+                "`limit` is already a global defined by the host",
+            ]
+        );
+        assert_eq!(engine.call::<i64, _>("answer", ()).unwrap(), 42);
+        assert_eq!(engine.get_global::<i64>("limit").unwrap(), Some(10));
+        assert_eq!(engine.evaluate_inline::<i64>("answer()").unwrap(), 42);
+    }
+
+    #[test]
+    fn failed_evaluation_keeps_warnings() {
+        let engine = Engine::builder().build();
+        assert!(
+            engine
+                .evaluate_inline::<i64>("let unused = 1; 1 / 0")
+                .is_err()
+        );
+        assert_eq!(messages(&engine.warnings()), ["unused variable `unused`"]);
+    }
+}
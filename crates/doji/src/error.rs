@@ -1,8 +1,18 @@
-use core::fmt::{self, Display, Formatter};
+use core::{
+    cell::RefCell,
+    fmt::{self, Display, Formatter},
+};
+
+use std::{io, path::PathBuf, sync::Arc};
 
 use gc_arena::{Collect, Gc};
 
-use crate::{context::Context, string::StringPtr, value::Value};
+use crate::{
+    compile::Diagnostics,
+    context::Context,
+    string::{StringPtr, StringValue},
+    value::Value,
+};
 
 pub type ErrorPtr<'gc> = Gc<'gc, ErrorValue<'gc>>;
 
@@ -11,7 +21,9 @@ pub type ErrorPtr<'gc> = Gc<'gc, ErrorValue<'gc>>;
 pub struct ErrorValue<'gc> {
     message: StringPtr<'gc>,
     data: Value<'gc>,
-    trace: (),
+    // Filled in by the fiber the error is first thrown in.
+    #[collect(require_static)]
+    trace: RefCell<Trace>,
 }
 
 impl<'gc> ErrorValue<'gc> {
@@ -21,10 +33,153 @@ impl<'gc> ErrorValue<'gc> {
             Self {
                 message,
                 data,
-                trace: (),
+                trace: RefCell::default(),
             },
         )
     }
+
+    pub fn new_message_ptr(cx: &Context<'gc>, message: impl Into<String>) -> ErrorPtr<'gc> {
+        Self::new_ptr(cx, StringValue::new_ptr(cx, message.into()), Value::NIL)
+    }
+
+    pub fn message(&self) -> StringPtr<'gc> {
+        self.message
+    }
+
+    /// The value the error was thrown with, which is the thrown value itself if it wasn't an
+    /// error, or `nil` for errors raised by the engine.
+    pub fn data(&self) -> Value<'gc> {
+        self.data
+    }
+
+    /// The fields that scripts can read from a caught error, which are its `message` and `data`.
+    pub fn field(&self, name: &str) -> Option<Value<'gc>> {
+        match name {
+            "message" => Some(self.message.into()),
+            "data" => Some(self.data),
+            _ => None,
+        }
+    }
+
+    pub fn stack_trace(&self) -> Trace {
+        self.trace.borrow().clone()
+    }
+
+    /// Records where the error was thrown, unless it already has a trace because it is being
+    /// rethrown.
+    pub fn set_trace_if_empty(&self, trace: impl FnOnce() -> Trace) {
+        let mut current = self.trace.borrow_mut();
+        if current.is_empty() {
+            *current = trace();
+        }
+    }
+}
+
+/// The call stack at the point an error was thrown, from the innermost frame outwards.
+#[derive(Clone, Debug, Default)]
+pub struct Trace(Vec<TraceFrame>);
+
+impl Trace {
+    pub fn frames(&self) -> &[TraceFrame] {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl FromIterator<TraceFrame> for Trace {
+    fn from_iter<I: IntoIterator<Item = TraceFrame>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+impl Display for Trace {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (i, frame) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "  at {}", frame)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TraceFrame {
+    function: Option<String>,
+    native: bool,
+    file: Option<Arc<str>>,
+    pc: usize,
+    line: Option<u32>,
+}
+
+impl TraceFrame {
+    pub fn new(
+        function: Option<String>,
+        file: Option<Arc<str>>,
+        pc: usize,
+        line: Option<u32>,
+    ) -> Self {
+        Self {
+            function,
+            native: false,
+            file,
+            pc,
+            line,
+        }
+    }
+
+    /// A frame of a native function, which has no source to point to.
+    pub fn native(function: String) -> Self {
+        Self {
+            function: Some(function),
+            native: true,
+            file: None,
+            pc: 0,
+            line: None,
+        }
+    }
+
+    /// The name of the function, or `None` if it is anonymous.
+    pub fn function(&self) -> Option<&str> {
+        self.function.as_deref()
+    }
+
+    pub fn is_native(&self) -> bool {
+        self.native
+    }
+
+    /// The name of the source the function was compiled from, such as its file path.
+    pub fn file(&self) -> Option<&str> {
+        self.file.as_deref()
+    }
+
+    /// The offset of the instruction that was running in this frame.
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn line(&self) -> Option<u32> {
+        self.line
+    }
+}
+
+impl Display for TraceFrame {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.function.as_deref().unwrap_or("<anonymous>"))?;
+        if self.native {
+            return write!(f, " (native)");
+        }
+        match (&self.file, self.line) {
+            (Some(file), Some(line)) => write!(f, " ({}:{})", file, line),
+            (Some(file), None) => write!(f, " ({}, pc {})", file, self.pc),
+            (None, Some(line)) => write!(f, " (line {})", line),
+            (None, None) => write!(f, " (pc {})", self.pc),
+        }
+    }
 }
 
 impl<'gc> Display for ErrorValue<'gc> {
@@ -33,22 +188,66 @@ impl<'gc> Display for ErrorValue<'gc> {
     }
 }
 
-pub struct Error {
-    message: String,
-    trace: (),
+#[derive(Debug)]
+pub enum Error {
+    Compile(Diagnostics),
+    Runtime {
+        message: String,
+        trace: Trace,
+    },
+    /// The source file could not be read.
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+    /// Something went wrong inside the engine itself, such as running malformed bytecode. The
+    /// evaluation is abandoned, but the engine can still be used.
+    Fatal(EngineError),
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.message)
+        match self {
+            Error::Compile(diagnostics) => write!(f, "{}", diagnostics),
+            Error::Runtime { message, trace } => {
+                write!(f, "{}", message)?;
+                if !trace.is_empty() {
+                    write!(f, "\n{}", trace)?;
+                }
+                Ok(())
+            }
+            Error::Io { path, error } => write!(f, "could not read {}: {}", path.display(), error),
+            Error::Fatal(error) => write!(f, "fatal engine error: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<Diagnostics> for Error {
+    fn from(diagnostics: Diagnostics) -> Self {
+        Error::Compile(diagnostics)
+    }
+}
+
+impl From<EngineError> for Error {
+    fn from(error: EngineError) -> Self {
+        Error::Fatal(error)
     }
 }
 
 impl<'gc> From<ErrorPtr<'gc>> for Error {
     fn from(error: ErrorPtr<'gc>) -> Self {
-        Self {
+        Error::Runtime {
             message: error.message.to_string(),
-            trace: error.trace,
+            trace: error.stack_trace(),
         }
     }
 }
@@ -58,9 +257,14 @@ pub enum EngineError {
     InvalidInstructionOffset(usize),
     InvalidFunctionIndex(usize),
     InvalidConstantIndex(usize),
+    ExpectedStringConstant(usize),
+    InvalidUpvalueIndex(usize),
+    InvalidStackSlot(usize),
+    InvalidOpcode(u8),
     WakeNonExistentFiber,
     StackUnderflow,
     CallStackUnderflow,
+    TryStackUnderflow,
 }
 
 impl Display for EngineError {
@@ -75,9 +279,163 @@ impl Display for EngineError {
             EngineError::InvalidConstantIndex(index) => {
                 write!(f, "invalid constant index: {}", index)
             }
+            EngineError::ExpectedStringConstant(index) => {
+                write!(f, "expected a string constant at index: {}", index)
+            }
+            EngineError::InvalidUpvalueIndex(index) => {
+                write!(f, "invalid upvalue index: {}", index)
+            }
+            EngineError::InvalidStackSlot(slot) => write!(f, "invalid stack slot: {}", slot),
+            EngineError::InvalidOpcode(opcode) => write!(f, "invalid opcode: {:#04x}", opcode),
             EngineError::WakeNonExistentFiber => write!(f, "tried to wake a non-existent fiber"),
             EngineError::StackUnderflow => write!(f, "stack underflow"),
             EngineError::CallStackUnderflow => write!(f, "call stack underflow"),
+            EngineError::TryStackUnderflow => write!(f, "try stack underflow"),
         }
     }
 }
+
+impl std::error::Error for EngineError {}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Engine, Error, Trace};
+
+    fn runtime_error(source: &str) -> (String, Trace) {
+        match Engine::builder().build().evaluate_inline::<i64>(source) {
+            Err(Error::Runtime { message, trace }) => (message, trace),
+            result => panic!(
+                "expected a runtime error, got {:?}",
+                result.map_err(|e| e.to_string())
+            ),
+        }
+    }
+
+    /// The function and line of each frame, from the innermost outwards.
+    fn frames(trace: &Trace) -> Vec<(Option<&str>, Option<u32>)> {
+        trace
+            .frames()
+            .iter()
+            .map(|frame| (frame.function(), frame.line()))
+            .collect()
+    }
+
+    #[test]
+    fn catch_errors_thrown_by_callees() {
+        let engine = Engine::builder().build();
+        let value: i64 = engine
+            .evaluate_inline(
+                "fn fail() { throw \"failed\"; }
+                let x = 1;
+                let caught = try { fail(); 2 } catch { x + 10 };
+                caught + x",
+            )
+            .unwrap();
+        assert_eq!(value, 12);
+    }
+
+    #[test]
+    fn catch_thrown_values() {
+        let engine = Engine::builder().build();
+        let value: (i64, String, String) = engine
+            .evaluate_inline(
+                r#"let e = try { throw { code: 404, reason: "missing" }; } catch e { e };
+                [e.data.code, e.data.reason, e.message]"#,
+            )
+            .unwrap();
+        assert_eq!(
+            value,
+            (404, "missing".into(), "{code: 404, reason: missing}".into())
+        );
+
+        // Errors raised by the engine have no data.
+        let value: (String, Option<i64>) = engine
+            .evaluate_inline("try { 1 / 0 } catch e { [e.message, e.data] }")
+            .unwrap();
+        assert_eq!(value, ("division by zero".into(), None));
+
+        // Rethrown errors keep the value they were first thrown with.
+        let value: i64 = engine
+            .evaluate_inline(
+                "try {
+                    try { throw 7; } catch e { throw e; }
+                } catch e {
+                    e.data
+                }",
+            )
+            .unwrap();
+        assert_eq!(value, 7);
+    }
+
+    #[test]
+    fn unknown_error_fields() {
+        let (message, _) = runtime_error("try { throw 1; } catch e { e.code }");
+        assert_eq!(message, "error has no field named `code`");
+    }
+
+    #[test]
+    fn try_without_errors() {
+        let engine = Engine::builder().build();
+        let value: i64 = engine.evaluate_inline("try { 1 } catch { 2 }").unwrap();
+        assert_eq!(value, 1);
+    }
+
+    #[test]
+    fn nested_try() {
+        let engine = Engine::builder().build();
+        let value: i64 = engine
+            .evaluate_inline(
+                "let log = 0;
+                try {
+                    try { throw 1; } catch { log = log + 1; throw 2; }
+                } catch {
+                    log = log + 10;
+                }
+                log",
+            )
+            .unwrap();
+        assert_eq!(value, 11);
+    }
+
+    #[test]
+    fn uncaught_errors_have_a_stack_trace() {
+        let (message, trace) = runtime_error(
+            "fn inner() {
+                throw \"deep\";
+            }
+            fn outer() {
+                inner()
+            }
+            outer()",
+        );
+        assert_eq!(message, "deep");
+        assert_eq!(
+            frames(&trace),
+            [
+                (Some("inner"), Some(2)),
+                (Some("outer"), Some(5)),
+                (Some("<script>"), Some(7)),
+            ]
+        );
+        assert_eq!(trace.frames()[0].file(), Some("<inline>"));
+    }
+
+    #[test]
+    fn rethrown_errors_keep_their_trace() {
+        let (message, trace) = runtime_error(
+            "fn fail() {
+                1 / 0
+            }
+            try {
+                fail()
+            } catch error {
+                throw error;
+            }",
+        );
+        assert_eq!(message, "division by zero");
+        assert_eq!(
+            frames(&trace),
+            [(Some("fail"), Some(2)), (Some("<script>"), Some(5))]
+        );
+    }
+}
@@ -1,24 +1,44 @@
+use core::{cmp::Ordering, mem};
+
 use gc_arena::{
     Collect, Gc,
     lock::{GcRefLock, RefLock},
 };
 
 use crate::{
-    closure::{ClosurePtr, ClosureValue},
+    closure::{ClosurePtr, ClosureValue, Upvalue, UpvaluePtr},
     context::Context,
-    error::{EngineError, ErrorPtr},
-    function::opcode,
-    value::Value,
+    error::{EngineError, ErrorPtr, ErrorValue, Trace, TraceFrame},
+    function::{Capture, opcode},
+    list::{ListPtr, ListValue},
+    map::{MapPtr, MapValue},
+    module::{ModulePtr, ModuleStatus, ModuleValue},
+    native::NativeFunctionPtr,
+    record::{self, RecordPtr, RecordTypePtr, RecordValue},
+    string::{StringPtr, StringValue},
+    value::{HashKey, Value},
+    variant::{self, EnumTypePtr, VariantPtr, VariantValue},
 };
 
 pub type FiberPtr<'gc> = GcRefLock<'gc, FiberValue<'gc>>;
 
+/// The deepest calls can be nested in a fiber before a stack overflow is thrown.
+const MAX_FRAMES: usize = 1024;
+
 #[derive(Collect, Debug)]
 #[collect(no_drop)]
 pub struct FiberValue<'gc> {
     current_frame: Frame<'gc>,
     stack: Vec<Value<'gc>>,
     call_stack: Vec<Frame<'gc>>,
+    // Upvalues that refer to slots on the stack of this fiber, which must be closed before those
+    // slots are removed.
+    open_upvalues: Vec<UpvaluePtr<'gc>>,
+    status: Status<'gc>,
+    // The fibers waiting for this one to finish.
+    joiners: Vec<FiberPtr<'gc>>,
+    // The module this fiber is waiting for another fiber to load.
+    waiting_for: Option<ModulePtr<'gc>>,
 }
 
 impl<'gc> FiberValue<'gc> {
@@ -29,13 +49,20 @@ impl<'gc> FiberValue<'gc> {
                 current_frame: Frame::new_closure(closure, 0),
                 stack: Vec::new(),
                 call_stack: Vec::new(),
+                open_upvalues: Vec::new(),
+                status: Status::Running,
+                joiners: Vec::new(),
+                waiting_for: None,
             }),
         )
     }
 
-    pub fn step(&mut self, cx: &Context<'gc>) -> Step<'gc> {
+    /// Runs the fiber until it yields or returns. The fiber must be given a pointer to itself, so
+    /// that variables captured from its stack can be told apart from those of other fibers.
+    pub fn step(&mut self, cx: &Context<'gc>, this: FiberPtr<'gc>) -> Step<'gc> {
         match self.current_frame.steppable {
-            Steppable::Closure(closure) => self.step_closure(cx, closure),
+            Steppable::Closure(closure) => self.step_closure(cx, this, closure),
+            Steppable::Native(native) => self.step_native(cx, native),
         }
     }
 
@@ -43,26 +70,77 @@ impl<'gc> FiberValue<'gc> {
         self.stack.push(value);
     }
 
-    fn step_closure(&mut self, cx: &Context<'gc>, closure: ClosurePtr<'gc>) -> Step<'gc> {
-        let error = match self.try_step_closure(cx, closure) {
-            Ok(step) => return step,
-            Err(error) => error,
+    pub fn status(&self) -> &Status<'gc> {
+        &self.status
+    }
+
+    /// Makes the fiber wake up the joiner once it finishes.
+    pub fn add_joiner(&mut self, joiner: FiberPtr<'gc>) {
+        self.joiners.push(joiner);
+    }
+
+    pub fn take_joiners(&mut self) -> Vec<FiberPtr<'gc>> {
+        mem::take(&mut self.joiners)
+    }
+
+    fn step_closure(
+        &mut self,
+        cx: &Context<'gc>,
+        this: FiberPtr<'gc>,
+        closure: ClosurePtr<'gc>,
+    ) -> Step<'gc> {
+        match self.try_step_closure(cx, this, closure) {
+            Ok(step) => step,
+            Err(Interrupt::Error(error)) => self.throw(cx, error),
+            Err(Interrupt::Fatal(error)) => Step::Fatal(error),
+        }
+    }
+
+    /// Calls the native function with the arguments of its frame, and returns from the frame with
+    /// its result.
+    fn step_native(&mut self, cx: &Context<'gc>, native: NativeFunctionPtr<'gc>) -> Step<'gc> {
+        let args = match self.stack.get(self.current_frame.stack_bottom..) {
+            Some(args) => args,
+            None => return Step::Fatal(EngineError::StackUnderflow),
+        };
+        let value = match native.call(cx, args) {
+            Ok(value) => value,
+            Err(error) => return self.throw(cx, error),
         };
+        match self.return_from_frame(cx, value) {
+            Ok(Some(step)) => step,
+            Ok(None) => Step::Continue,
+            Err(error) => Step::Fatal(error),
+        }
+    }
+
+    /// Unwinds the stack to the innermost try, pushing the error for its catch to handle. The
+    /// fiber fails with the error if nothing catches it.
+    fn throw(&mut self, cx: &Context<'gc>, error: ErrorPtr<'gc>) -> Step<'gc> {
+        error.set_trace_if_empty(|| self.trace(cx));
 
         let current_try = loop {
             if let Some(current_try) = self.current_frame.current_try.take() {
                 break current_try;
             }
             if self.call_stack.is_empty() {
-                // FIXME: error thrown and not caught at the top-level of this fiber.
-                //        still not sure what to do here...
-                todo!()
+                // Nothing caught the error, so the whole fiber fails with it.
+                self.truncate(cx, 0);
+                self.status = Status::Failed(error);
+                return Step::Error(error);
+            }
+            // A module that fails to load is not cached, so that it can be imported again.
+            if let Some(module) = self.current_frame.module {
+                cx.state().remove_module(cx, module);
+            }
+            if let Err(error) = self.pop_frame() {
+                return Step::Fatal(error);
             }
-            self.pop_frame();
         };
 
+        self.current_frame.current_try = current_try.parent.map(|parent| *parent);
         self.current_frame.pc = current_try.pc;
-        self.stack.truncate(current_try.stack_len);
+        self.truncate(cx, current_try.stack_len);
 
         self.stack.push(error.into());
 
@@ -72,14 +150,28 @@ impl<'gc> FiberValue<'gc> {
     fn try_step_closure(
         &mut self,
         cx: &Context<'gc>,
-        closure: ClosurePtr<'gc>,
-    ) -> Result<Step<'gc>, ErrorPtr<'gc>> {
+        this: FiberPtr<'gc>,
+        mut closure: ClosurePtr<'gc>,
+    ) -> Result<Step<'gc>, Interrupt<'gc>> {
         loop {
-            let instruction = closure.function().instruction(self.current_frame.pc);
+            let instruction = closure.function().instruction(self.current_frame.pc)?;
             self.current_frame.pc += 1;
 
             match instruction.opcode() {
                 opcode::NO_OP => {}
+                opcode::POP => {
+                    self.pop()?;
+                }
+                opcode::DROP => {
+                    let value = self.pop()?;
+                    let len = self
+                        .stack
+                        .len()
+                        .checked_sub(instruction.operand() as usize)
+                        .ok_or(EngineError::StackUnderflow)?;
+                    self.truncate(cx, len);
+                    self.stack.push(value);
+                }
 
                 opcode::NIL => self.stack.push(Value::NIL),
                 opcode::TRUE => self.stack.push(Value::TRUE),
@@ -88,31 +180,508 @@ impl<'gc> FiberValue<'gc> {
                 opcode::CONST => self.stack.push(
                     closure
                         .function()
-                        .constant(instruction.operand() as usize)
+                        .constant(instruction.operand() as usize)?
                         .into(),
                 ),
                 opcode::CLOSURE => {
-                    let function = closure.function().function(instruction.operand() as usize);
-                    let closure = ClosureValue::new_ptr(cx, function);
+                    let function = closure
+                        .function()
+                        .function(instruction.operand() as usize)?;
+                    let upvalues = function
+                        .captures()
+                        .iter()
+                        .map(|capture| match *capture {
+                            Capture::Local(slot) => {
+                                let slot = self.current_frame.stack_bottom + slot;
+                                Ok(self.capture_upvalue(cx, this, slot))
+                            }
+                            Capture::Upvalue(index) => closure.upvalue(index),
+                        })
+                        .collect::<Result<_, _>>()?;
+                    let closure = ClosureValue::ptr_with_upvalues(cx, function, upvalues);
                     self.stack.push(closure.into());
                 }
 
-                opcode::ADD => self.try_int_or_float_op(cx, |a, b| a + b, |a, b| a + b)?,
-                opcode::SUB => self.try_int_or_float_op(cx, |a, b| a - b, |a, b| a - b)?,
-                opcode::MUL => self.try_int_or_float_op(cx, |a, b| a * b, |a, b| a * b)?,
-                opcode::DIV => self.try_int_or_float_op(cx, |a, b| a / b, |a, b| a / b)?,
-                opcode::MOD => self.try_int_op(cx, |a, b| a % b)?,
+                opcode::ADD => self.try_int_or_float_op(cx, i64::checked_add, |a, b| a + b)?,
+                opcode::SUB => self.try_int_or_float_op(cx, i64::checked_sub, |a, b| a - b)?,
+                opcode::MUL => self.try_int_or_float_op(cx, i64::checked_mul, |a, b| a * b)?,
+                opcode::DIV => self.try_int_or_float_op(cx, i64::checked_div, |a, b| a / b)?,
+                opcode::MOD => self.try_int_op(cx, i64::checked_rem)?,
+                opcode::EQ => {
+                    let b = self.pop()?;
+                    let a = self.pop()?;
+                    self.stack.push(a.equals(&b).into());
+                }
+                opcode::NE => {
+                    let b = self.pop()?;
+                    let a = self.pop()?;
+                    self.stack.push((!a.equals(&b)).into());
+                }
+                opcode::LT => self.try_compare_op(cx, Ordering::is_lt)?,
+                opcode::LE => self.try_compare_op(cx, Ordering::is_le)?,
+                opcode::GT => self.try_compare_op(cx, Ordering::is_gt)?,
+                opcode::GE => self.try_compare_op(cx, Ordering::is_ge)?,
+                opcode::NOT => {
+                    let value = self.pop()?;
+                    self.stack.push((!value.is_truthy()).into());
+                }
+                opcode::NEG => {
+                    let value = self.pop()?;
+                    let result = match value.as_float() {
+                        Some(float) => (-float).into(),
+                        None => value
+                            .try_into::<i64>(cx)?
+                            .checked_neg()
+                            .ok_or_else(|| ErrorValue::new_message_ptr(cx, "integer overflow"))?
+                            .into(),
+                    };
+                    self.stack.push(result);
+                }
 
-                opcode::RETURN => return Ok(Step::Return(self.pop())),
+                opcode::RETURN => {
+                    let value = self.pop()?;
+                    if let Some(step) = self.return_from_frame(cx, value)? {
+                        return Ok(step);
+                    }
+                    closure = match self.current_frame.steppable {
+                        Steppable::Closure(closure) => closure,
+                        Steppable::Native(_) => return Ok(Step::Continue),
+                    };
+                }
+                opcode::CALL => {
+                    self.call(cx, instruction.operand() as usize)?;
+                    closure = match self.current_frame.steppable {
+                        Steppable::Closure(closure) => closure,
+                        // Natives are run by stepping their frame.
+                        Steppable::Native(_) => return Ok(Step::Continue),
+                    };
+                }
+                opcode::INVOKE => self.invoke(cx, instruction.operand() as usize)?,
+                opcode::JUMP => self.current_frame.pc = instruction.operand() as usize,
+                opcode::SWITCH => {
+                    // This is followed by a jump for each variant of the enum, and then one for
+                    // any other value, which this skips ahead to.
+                    let ty: EnumTypePtr = self.pop()?.try_into(cx)?;
+                    let value = self.pop()?;
+                    let count = instruction.operand() as usize;
+                    let index = match value.try_into::<VariantPtr>(cx) {
+                        Ok(variant) if Gc::ptr_eq(variant.ty(), ty) => variant.index().min(count),
+                        _ => count,
+                    };
+                    self.current_frame.pc += index;
+                }
+                opcode::JUMP_IF_FALSE => {
+                    if !self.pop()?.is_truthy() {
+                        self.current_frame.pc = instruction.operand() as usize;
+                    }
+                }
+                opcode::JUMP_IF_FALSE_OR_POP => {
+                    if self.peek()?.is_truthy() {
+                        self.pop()?;
+                    } else {
+                        self.current_frame.pc = instruction.operand() as usize;
+                    }
+                }
+                opcode::JUMP_IF_TRUE_OR_POP => {
+                    if self.peek()?.is_truthy() {
+                        self.current_frame.pc = instruction.operand() as usize;
+                    } else {
+                        self.pop()?;
+                    }
+                }
 
                 opcode::SPAWN => {
-                    let closure = self.pop().try_into(cx)?;
+                    let closure: ClosurePtr = self.pop()?.try_into(cx)?;
+                    if closure.function().arity() != 0 {
+                        return Err(ErrorValue::new_message_ptr(
+                            cx,
+                            format!(
+                                "spawned closures must take no arguments, but this one takes {}",
+                                closure.function().arity()
+                            ),
+                        )
+                        .into());
+                    }
                     let fiber = cx.state().spawn(cx, closure);
-                    self.stack.push(fiber.clone().into());
+                    self.stack.push(fiber.into());
+                }
+                opcode::YIELD => return Ok(Step::Yield(self.pop()?)),
+                opcode::JOIN => {
+                    let fiber: FiberPtr = self.peek()?.try_into(cx)?;
+                    if Gc::ptr_eq(fiber, this) {
+                        return Err(
+                            ErrorValue::new_message_ptr(cx, "a fiber cannot join itself").into(),
+                        );
+                    }
+                    let status = match *fiber.borrow().status() {
+                        Status::Running => None,
+                        Status::Returned(value) => Some(Ok(value)),
+                        Status::Failed(error) => Some(Err(error)),
+                    };
+                    match status {
+                        Some(result) => {
+                            self.pop()?;
+                            // The error of the fiber is rethrown in this one.
+                            self.stack.push(result?);
+                        }
+                        None => {
+                            // Run this instruction again once the fiber has finished.
+                            self.current_frame.pc -= 1;
+                            return Ok(Step::Join(fiber));
+                        }
+                    }
+                }
+
+                opcode::TRY_BEGIN => {
+                    let parent = self.current_frame.current_try.take().map(Box::new);
+                    self.current_frame.current_try = Some(Try {
+                        pc: instruction.operand() as usize,
+                        stack_len: self.stack.len(),
+                        parent,
+                    });
+                }
+                opcode::TRY_END => {
+                    let current_try = self
+                        .current_frame
+                        .current_try
+                        .take()
+                        .ok_or(EngineError::TryStackUnderflow)?;
+                    self.current_frame.current_try = current_try.parent.map(|parent| *parent);
+                }
+                opcode::THROW => {
+                    let value = self.pop()?;
+                    // Errors are rethrown as they are, anything else is wrapped in a new error.
+                    let error = match value.try_into::<ErrorPtr>(cx) {
+                        Ok(error) => error,
+                        Err(_) => ErrorValue::new_ptr(
+                            cx,
+                            StringValue::new_ptr(cx, value.to_string()),
+                            value,
+                        ),
+                    };
+                    return Err(error.into());
+                }
+                opcode::NO_MATCH => {
+                    let value = self.pop()?;
+                    return Err(ErrorValue::new_ptr(
+                        cx,
+                        StringValue::new_ptr(cx, format!("no arm matches {}", value)),
+                        value,
+                    )
+                    .into());
+                }
+
+                opcode::GET_LOCAL => {
+                    let slot = self.current_frame.stack_bottom + instruction.operand() as usize;
+                    let value = *self.slot(slot)?;
+                    self.stack.push(value);
+                }
+                opcode::SET_LOCAL => {
+                    let slot = self.current_frame.stack_bottom + instruction.operand() as usize;
+                    *self.slot(slot)? = self.peek()?;
+                }
+                opcode::GET_UPVALUE => {
+                    let upvalue = closure.upvalue(instruction.operand() as usize)?;
+                    let value = match *upvalue.borrow() {
+                        Upvalue::Open(fiber, slot) if Gc::ptr_eq(fiber, this) => {
+                            *self.slot(slot)?
+                        }
+                        Upvalue::Open(fiber, slot) => {
+                            *fiber.borrow_mut(cx.mutation()).slot(slot)?
+                        }
+                        Upvalue::Closed(value) => value,
+                    };
+                    self.stack.push(value);
+                }
+                opcode::SET_UPVALUE => {
+                    let upvalue = closure.upvalue(instruction.operand() as usize)?;
+                    let value = self.peek()?;
+                    let mut upvalue = upvalue.borrow_mut(cx.mutation());
+                    match &mut *upvalue {
+                        Upvalue::Open(fiber, slot) if Gc::ptr_eq(*fiber, this) => {
+                            *self.slot(*slot)? = value
+                        }
+                        Upvalue::Open(fiber, slot) => {
+                            *fiber.borrow_mut(cx.mutation()).slot(*slot)? = value
+                        }
+                        Upvalue::Closed(closed) => *closed = value,
+                    }
+                }
+
+                opcode::GET_GLOBAL => {
+                    let name = closure
+                        .function()
+                        .string_constant(instruction.operand() as usize)?;
+                    let value = cx.state().global(name.as_str()).ok_or_else(|| {
+                        ErrorValue::new_message_ptr(cx, format!("undefined global `{}`", name))
+                    })?;
+                    self.stack.push(value);
+                }
+                opcode::SET_GLOBAL => {
+                    let name = closure
+                        .function()
+                        .string_constant(instruction.operand() as usize)?;
+                    cx.state().set_global(cx, name.as_str(), self.peek()?);
                 }
-                opcode::YIELD => return Ok(Step::Yield(self.pop())),
 
-                _ => unreachable!(),
+                opcode::IMPORT => {
+                    self.waiting_for = None;
+                    let path = closure
+                        .function()
+                        .string_constant(instruction.operand() as usize)?;
+                    let importer = cx
+                        .state()
+                        .source_name(closure.function().debug_info().source())
+                        .unwrap_or_default();
+                    let resolved = cx
+                        .state()
+                        .resolve_module(path.as_str(), &importer)
+                        .map_err(|error| {
+                            ErrorValue::new_message_ptr(
+                                cx,
+                                format!("could not load module {}: {}", path, error),
+                            )
+                        })?;
+                    let Some(module) = cx.state().module(&resolved) else {
+                        closure = self.load_module(cx, this, &resolved)?;
+                        continue;
+                    };
+                    let loader = match *module.borrow().status() {
+                        ModuleStatus::Loaded => None,
+                        ModuleStatus::Loading(loader) => Some(loader),
+                    };
+                    match loader {
+                        None => self.stack.push(module.into()),
+                        Some(loader) if Self::waits_for(loader, this) => {
+                            return Err(ErrorValue::new_message_ptr(
+                                cx,
+                                format!("import cycle detected: {} is still being loaded", path),
+                            )
+                            .into());
+                        }
+                        Some(_) => {
+                            // Run this instruction again once the other fiber has loaded it.
+                            self.current_frame.pc -= 1;
+                            self.waiting_for = Some(module);
+                            return Ok(Step::WaitModule(module));
+                        }
+                    }
+                }
+                opcode::GET_EXPORT => {
+                    let name = closure
+                        .function()
+                        .string_constant(instruction.operand() as usize)?;
+                    let module: ModulePtr = self.pop()?.try_into(cx)?;
+                    let module = module.borrow();
+                    let value = module.get_export(name.as_str()).ok_or_else(|| {
+                        ErrorValue::new_message_ptr(
+                            cx,
+                            format!("module {} has no export named `{}`", module.path(), name),
+                        )
+                    })?;
+                    self.stack.push(value);
+                }
+                opcode::EXPORT => {
+                    let name = closure
+                        .function()
+                        .string_constant(instruction.operand() as usize)?;
+                    let value = self.pop()?;
+                    // The script being evaluated isn't a module, so its exports go nowhere.
+                    if let Some(module) = self.current_frame.module {
+                        module.borrow_mut(cx.mutation()).export(name, value);
+                    }
+                }
+
+                opcode::LIST => {
+                    let len = self
+                        .stack
+                        .len()
+                        .checked_sub(instruction.operand() as usize)
+                        .ok_or(EngineError::StackUnderflow)?;
+                    let items = self.stack.split_off(len);
+                    self.stack.push(ListValue::new_ptr(cx, items).into());
+                }
+                opcode::MAP => {
+                    let len = self
+                        .stack
+                        .len()
+                        .checked_sub(2 * instruction.operand() as usize)
+                        .ok_or(EngineError::StackUnderflow)?;
+                    let entries = self
+                        .stack
+                        .split_off(len)
+                        .chunks(2)
+                        .map(|entry| Ok((HashKey::new(cx, entry[0])?, entry[1])))
+                        .collect::<Result<Vec<_>, ErrorPtr>>()?;
+                    self.stack.push(MapValue::new_ptr(cx, entries).into());
+                }
+                opcode::GET_INDEX => {
+                    let index = self.pop()?;
+                    let container = self.pop()?;
+                    self.stack.push(Self::get_index(cx, container, index)?);
+                }
+                opcode::SET_INDEX => {
+                    let value = self.pop()?;
+                    let index = self.pop()?;
+                    let container = self.pop()?;
+                    Self::set_index(cx, container, index, value)?;
+                    self.stack.push(value);
+                }
+                opcode::IN => {
+                    let container = self.pop()?;
+                    let value = self.pop()?;
+                    let result = if let Ok(map) = container.try_into::<MapPtr>(cx) {
+                        // Floats can never be keys, so they are never in a map.
+                        HashKey::try_new(value).is_some_and(|key| map.borrow().contains(&key))
+                    } else if let Ok(list) = container.try_into::<ListPtr>(cx) {
+                        list.borrow().items().iter().any(|item| item.equals(&value))
+                    } else {
+                        return Err(ErrorValue::new_message_ptr(
+                            cx,
+                            format!(
+                                "cannot check membership in a value of type {}",
+                                container.ty()
+                            ),
+                        )
+                        .into());
+                    };
+                    self.stack.push(result.into());
+                }
+                opcode::RECORD => {
+                    let len = self
+                        .stack
+                        .len()
+                        .checked_sub(2 * instruction.operand() as usize)
+                        .ok_or(EngineError::StackUnderflow)?;
+                    let fields = self
+                        .stack
+                        .split_off(len)
+                        .chunks(2)
+                        .map(|field| Ok((field[0].try_into::<StringPtr>(cx)?, field[1])))
+                        .collect::<Result<Vec<_>, ErrorPtr>>()?;
+                    let ty = self.pop()?;
+                    let ty = ty.try_into::<RecordTypePtr>(cx).map_err(|_| {
+                        ErrorValue::new_message_ptr(
+                            cx,
+                            format!("cannot create a record from a value of type {}", ty.ty()),
+                        )
+                    })?;
+                    self.stack
+                        .push(RecordValue::new_ptr(cx, ty, fields)?.into());
+                }
+                opcode::GET_FIELD => {
+                    let index = instruction.operand() as usize;
+                    let name = closure.function().string_constant(index)?;
+                    let object = self.pop()?;
+                    let value = if let Ok(ty) = object.try_into::<EnumTypePtr>(cx) {
+                        // Variants without fields don't need to be called to be created.
+                        VariantValue::new_ptr(cx, ty, name.as_str(), Vec::new())?.into()
+                    } else if let Ok(variant) = object.try_into::<VariantPtr>(cx) {
+                        variant.field(name.as_str()).ok_or_else(|| {
+                            ErrorValue::new_message_ptr(
+                                cx,
+                                format!("{} has no field named `{}`", variant, name),
+                            )
+                        })?
+                    } else if let Ok(record) = object.try_into::<RecordPtr>(cx) {
+                        let record = record.borrow();
+                        let ty = record.ty();
+                        closure
+                            .function()
+                            .inline_cache(index)?
+                            .field_index(&ty, name.as_str())
+                            .and_then(|index| record.field(index))
+                            .ok_or_else(|| record::no_field(cx, &ty, name.as_str()))?
+                    } else if let Ok(map) = object.try_into::<MapPtr>(cx) {
+                        map.borrow().get_str(name.as_str()).ok_or_else(|| {
+                            ErrorValue::new_message_ptr(
+                                cx,
+                                format!("key {} is not in the map", name),
+                            )
+                        })?
+                    } else if let Ok(error) = object.try_into::<ErrorPtr>(cx) {
+                        error.field(name.as_str()).ok_or_else(|| {
+                            ErrorValue::new_message_ptr(
+                                cx,
+                                format!("error has no field named `{}`", name),
+                            )
+                        })?
+                    } else {
+                        return Err(Self::no_fields(cx, object, name).into());
+                    };
+                    self.stack.push(value);
+                }
+                opcode::SET_FIELD => {
+                    let index = instruction.operand() as usize;
+                    let name = closure.function().string_constant(index)?;
+                    let value = self.pop()?;
+                    let object = self.pop()?;
+                    if let Ok(record) = object.try_into::<RecordPtr>(cx) {
+                        let ty = record.borrow().ty();
+                        closure
+                            .function()
+                            .inline_cache(index)?
+                            .field_index(&ty, name.as_str())
+                            .and_then(|index| {
+                                record.borrow_mut(cx.mutation()).set_field(index, value)
+                            })
+                            .ok_or_else(|| record::no_field(cx, &ty, name.as_str()))?;
+                    } else if let Ok(map) = object.try_into::<MapPtr>(cx) {
+                        let key = HashKey::new(cx, name.into())?;
+                        map.borrow_mut(cx.mutation()).insert(key, value);
+                    } else if object.try_into::<VariantPtr>(cx).is_ok() {
+                        return Err(ErrorValue::new_message_ptr(
+                            cx,
+                            format!("cannot assign to field `{}` of a variant", name),
+                        )
+                        .into());
+                    } else {
+                        return Err(Self::no_fields(cx, object, name).into());
+                    }
+                    self.stack.push(value);
+                }
+                opcode::IS_VARIANT => {
+                    let name = closure
+                        .function()
+                        .string_constant(instruction.operand() as usize)?;
+                    let ty = self.pop()?;
+                    let ty = ty.try_into::<EnumTypePtr>(cx).map_err(|_| {
+                        ErrorValue::new_message_ptr(
+                            cx,
+                            format!("cannot match the variants of a value of type {}", ty.ty()),
+                        )
+                    })?;
+                    let index = ty
+                        .variant_index(name.as_str())
+                        .ok_or_else(|| variant::no_variant(cx, &ty, name.as_str()))?;
+                    let result = self
+                        .peek()?
+                        .try_into::<VariantPtr>(cx)
+                        .is_ok_and(|variant| {
+                            Gc::ptr_eq(variant.ty(), ty) && variant.index() == index
+                        });
+                    self.stack.push(result.into());
+                }
+                opcode::UNPACK => {
+                    let variant: VariantPtr = self.pop()?.try_into(cx)?;
+                    let count = instruction.operand() as usize;
+                    if variant.fields().len() != count {
+                        return Err(ErrorValue::new_message_ptr(
+                            cx,
+                            format!(
+                                "wrong number of fields in pattern for {}.{}: expected {}, got {}",
+                                variant.ty().name(),
+                                variant.variant().name(),
+                                variant.fields().len(),
+                                count
+                            ),
+                        )
+                        .into());
+                    }
+                    self.stack.extend_from_slice(variant.fields());
+                }
+
+                op => return Err(EngineError::InvalidOpcode(op).into()),
             }
         }
     }
@@ -122,56 +691,395 @@ impl<'gc> FiberValue<'gc> {
         cx: &Context<'gc>,
         int_op: I,
         float_op: F,
-    ) -> Result<(), ErrorPtr<'gc>>
+    ) -> Result<(), Interrupt<'gc>>
     where
-        I: Fn(i64, i64) -> i64,
+        I: Fn(i64, i64) -> Option<i64>,
         F: Fn(f64, f64) -> f64,
     {
-        let b = self.pop();
-        let a = self.pop();
-        let result = if let (Ok(a), Ok(b)) = (a.try_into::<f64>(cx), b.try_into::<f64>(cx)) {
-            float_op(a, b).into()
-        } else if let (Ok(a), Ok(b)) = (a.try_into::<f64>(cx), b.try_into::<i64>(cx)) {
-            float_op(a, b as f64).into()
-        } else if let (Ok(a), Ok(b)) = (a.try_into::<i64>(cx), b.try_into::<f64>(cx)) {
-            float_op(a as f64, b).into()
+        let b = self.pop()?;
+        let a = self.pop()?;
+        // Ints are only widened to floats if the other operand is a float.
+        let result = if a.as_float().is_some() || b.as_float().is_some() {
+            float_op(a.try_into(cx)?, b.try_into(cx)?).into()
         } else {
-            int_op(a.try_into(cx)?, b.try_into(cx)?).into()
+            checked_int_op(cx, a.try_into(cx)?, b.try_into(cx)?, int_op)?.into()
         };
         self.stack.push(result);
         Ok(())
     }
 
-    fn try_int_op<F>(&mut self, cx: &Context<'gc>, op: F) -> Result<(), ErrorPtr<'gc>>
+    fn try_int_op<F>(&mut self, cx: &Context<'gc>, op: F) -> Result<(), Interrupt<'gc>>
     where
-        F: Fn(i64, i64) -> i64,
+        F: Fn(i64, i64) -> Option<i64>,
     {
-        let b = self.pop();
-        let a = self.pop();
-        self.stack.push(op(a.try_into(cx)?, b.try_into(cx)?).into());
+        let b = self.pop()?;
+        let a = self.pop()?;
+        let result = checked_int_op(cx, a.try_into(cx)?, b.try_into(cx)?, op)?;
+        self.stack.push(result.into());
+        Ok(())
+    }
+
+    fn try_compare_op<F>(&mut self, cx: &Context<'gc>, op: F) -> Result<(), Interrupt<'gc>>
+    where
+        F: Fn(Ordering) -> bool,
+    {
+        let b = self.pop()?;
+        let a = self.pop()?;
+        if !a.is_comparable(&b) {
+            return Err(ErrorValue::new_message_ptr(
+                cx,
+                format!("cannot compare {} with {}", a.ty(), b.ty()),
+            )
+            .into());
+        }
+        // Comparisons involving NaN are always false.
+        let result = a.compare(&b).is_some_and(op);
+        self.stack.push(result.into());
+        Ok(())
+    }
+
+    /// Calls the built-in method named by the value beneath the given number of arguments at the
+    /// top of the stack, on the receiver beneath that.
+    fn invoke(&mut self, cx: &Context<'gc>, arg_count: usize) -> Result<(), Interrupt<'gc>> {
+        let receiver_slot = self
+            .stack
+            .len()
+            .checked_sub(arg_count + 2)
+            .ok_or(EngineError::StackUnderflow)?;
+        let receiver = self.stack[receiver_slot];
+        let name: StringPtr = self.stack[receiver_slot + 1].try_into(cx)?;
+        let name = name.as_str();
+        let args = &self.stack[receiver_slot + 2..];
+
+        let value = if let Ok(list) = receiver.try_into::<ListPtr>(cx) {
+            ListValue::call_method(list, cx, name, args)?
+        } else if let Ok(map) = receiver.try_into::<MapPtr>(cx) {
+            MapValue::call_method(map, cx, name, args)?
+        } else if let Ok(ty) = receiver.try_into::<EnumTypePtr>(cx) {
+            // Calling a variant on its enum creates a value of it, as in `Shape.Circle(1)`.
+            VariantValue::new_ptr(cx, ty, name, args.to_vec())?.into()
+        } else {
+            return Err(ErrorValue::new_message_ptr(
+                cx,
+                format!("{} has no method named `{}`", receiver.ty(), name),
+            )
+            .into());
+        };
+        self.truncate(cx, receiver_slot);
+        self.stack.push(value);
+        Ok(())
+    }
+
+    /// Gets the item at the index of a list, or the value of the key in a map.
+    fn get_index(
+        cx: &Context<'gc>,
+        container: Value<'gc>,
+        index: Value<'gc>,
+    ) -> Result<Value<'gc>, ErrorPtr<'gc>> {
+        if let Ok(map) = container.try_into::<MapPtr>(cx) {
+            let key = HashKey::new(cx, index)?;
+            return map.borrow().get(&key).ok_or_else(|| {
+                ErrorValue::new_ptr(
+                    cx,
+                    StringValue::new_ptr(cx, format!("key {} is not in the map", index)),
+                    index,
+                )
+            });
+        }
+        let (list, index) = Self::list_index(cx, container, index)?;
+        list.borrow().get(cx, index)
+    }
+
+    /// Sets the item at the index of a list, or the value of the key in a map.
+    fn set_index(
+        cx: &Context<'gc>,
+        container: Value<'gc>,
+        index: Value<'gc>,
+        value: Value<'gc>,
+    ) -> Result<(), ErrorPtr<'gc>> {
+        if let Ok(map) = container.try_into::<MapPtr>(cx) {
+            let key = HashKey::new(cx, index)?;
+            map.borrow_mut(cx.mutation()).insert(key, value);
+            return Ok(());
+        }
+        let (list, index) = Self::list_index(cx, container, index)?;
+        list.borrow_mut(cx.mutation()).set(cx, index, value)
+    }
+
+    fn no_fields(cx: &Context<'gc>, object: Value<'gc>, name: StringPtr<'gc>) -> ErrorPtr<'gc> {
+        ErrorValue::new_message_ptr(
+            cx,
+            format!(
+                "cannot access field `{}` on a value of type {}",
+                name,
+                object.ty()
+            ),
+        )
+    }
+
+    /// Checks that the value being indexed is a list and that the index is an int.
+    fn list_index(
+        cx: &Context<'gc>,
+        list: Value<'gc>,
+        index: Value<'gc>,
+    ) -> Result<(ListPtr<'gc>, i64), ErrorPtr<'gc>> {
+        let list = list.try_into::<ListPtr>(cx).map_err(|_| {
+            ErrorValue::new_message_ptr(
+                cx,
+                format!("cannot index into a value of type {}", list.ty()),
+            )
+        })?;
+        let index = index.try_into::<i64>(cx).map_err(|_| {
+            ErrorValue::new_message_ptr(
+                cx,
+                format!("list indices must be ints, not {}", index.ty()),
+            )
+        })?;
+        Ok((list, index))
+    }
+
+    /// Returns the value from the current frame to its caller. If this is the last frame, the
+    /// fiber is finished and the step to return is given back.
+    fn return_from_frame(
+        &mut self,
+        cx: &Context<'gc>,
+        value: Value<'gc>,
+    ) -> Result<Option<Step<'gc>>, EngineError> {
+        if self.call_stack.is_empty() {
+            self.truncate(cx, 0);
+            self.status = Status::Returned(value);
+            return Ok(Some(Step::Return(value)));
+        }
+
+        // Importing a module results in the module itself, rather than the value its script ends
+        // with.
+        let value = match self.current_frame.module {
+            Some(module) => {
+                cx.state().finish_module(cx, module);
+                module.into()
+            }
+            None => value,
+        };
+
+        // Discard the callee, its arguments and any of its remaining temporaries.
+        self.truncate(cx, self.current_frame.stack_bottom - 1);
+        self.pop_frame()?;
+        self.stack.push(value);
+
+        Ok(None)
+    }
+
+    /// Calls the callee beneath the given number of arguments at the top of the stack, pushing a
+    /// frame for it.
+    fn call(&mut self, cx: &Context<'gc>, arg_count: usize) -> Result<(), Interrupt<'gc>> {
+        let callee_slot = self
+            .stack
+            .len()
+            .checked_sub(arg_count + 1)
+            .ok_or(EngineError::StackUnderflow)?;
+        let callee = *self.slot(callee_slot)?;
+
+        // Natives check their own arguments.
+        if let Ok(native) = callee.try_into::<NativeFunctionPtr>(cx) {
+            self.push_frame(cx, Frame::new_native(native, callee_slot + 1))?;
+            return Ok(());
+        }
+
+        let closure: ClosurePtr = callee.try_into(cx).map_err(|_| {
+            ErrorValue::new_ptr(
+                cx,
+                StringValue::new_ptr(cx, format!("cannot call a value of type {}", callee.ty())),
+                callee,
+            )
+        })?;
+        closure.check_arity(cx, arg_count)?;
+        self.push_frame(cx, Frame::new_closure(closure, callee_slot + 1))?;
+
+        Ok(())
+    }
+
+    /// Makes the frame the current one, keeping the caller to return to.
+    fn push_frame(&mut self, cx: &Context<'gc>, frame: Frame<'gc>) -> Result<(), ErrorPtr<'gc>> {
+        if self.call_stack.len() >= MAX_FRAMES {
+            return Err(ErrorValue::new_message_ptr(cx, "stack overflow"));
+        }
+        let caller = mem::replace(&mut self.current_frame, frame);
+        self.call_stack.push(caller);
         Ok(())
     }
 
-    fn pop_frame(&mut self) {
+    /// Compiles the module and calls it in a new frame, which finishes loading the module once it
+    /// returns. Returns the closure to continue running.
+    fn load_module(
+        &mut self,
+        cx: &Context<'gc>,
+        this: FiberPtr<'gc>,
+        path: &str,
+    ) -> Result<ClosurePtr<'gc>, Interrupt<'gc>> {
+        let source = cx.state().load_module_source(path).map_err(|error| {
+            ErrorValue::new_message_ptr(cx, format!("could not load module {}: {}", path, error))
+        })?;
+        let closure = cx.compile_module(path, &source).map_err(|diagnostics| {
+            ErrorValue::new_message_ptr(
+                cx,
+                format!("could not compile module {}:\n{}", path, diagnostics),
+            )
+        })?;
+        let module = ModuleValue::new_ptr(cx, path.to_string(), this);
+
+        // The closure takes the place of the callee, as in any other call.
+        self.stack.push(closure.into());
+        self.push_frame(cx, Frame::new_module(closure, module, self.stack.len()))?;
+        cx.state().add_module(cx, module);
+
+        Ok(closure)
+    }
+
+    /// Whether the fiber loading a module is, or is waiting on, the given fiber, following the
+    /// chain of fibers waiting for modules loaded by other fibers. Waiting for the module would
+    /// then never end.
+    fn waits_for(loader: FiberPtr<'gc>, fiber: FiberPtr<'gc>) -> bool {
+        let mut loader = loader;
+        loop {
+            if Gc::ptr_eq(loader, fiber) {
+                return true;
+            }
+            let Some(module) = loader.borrow().waiting_for else {
+                return false;
+            };
+            match *module.borrow().status() {
+                ModuleStatus::Loading(next) => loader = next,
+                ModuleStatus::Loaded => return false,
+            }
+        }
+    }
+
+    /// Returns the open upvalue for the slot, creating it if the slot hasn't been captured yet, so
+    /// that all closures capturing the same variable share it.
+    fn capture_upvalue(
+        &mut self,
+        cx: &Context<'gc>,
+        this: FiberPtr<'gc>,
+        slot: usize,
+    ) -> UpvaluePtr<'gc> {
+        let existing = self.open_upvalues.iter().find(
+            |upvalue| matches!(*upvalue.borrow(), Upvalue::Open(_, open_slot) if open_slot == slot),
+        );
+        if let Some(upvalue) = existing {
+            return *upvalue;
+        }
+
+        let upvalue = Upvalue::new_open_ptr(cx, this, slot);
+        self.open_upvalues.push(upvalue);
+        upvalue
+    }
+
+    /// Truncates the stack to the given length, first closing any upvalues that refer to the
+    /// removed slots.
+    fn truncate(&mut self, cx: &Context<'gc>, len: usize) {
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            let slot = match *upvalue.borrow() {
+                Upvalue::Open(_, slot) => slot,
+                Upvalue::Closed(_) => unreachable!("closed upvalues are removed"),
+            };
+            if slot < len {
+                return true;
+            }
+            *upvalue.borrow_mut(cx.mutation()) = Upvalue::Closed(stack[slot]);
+            false
+        });
+        self.stack.truncate(len);
+    }
+
+    fn trace(&self, cx: &Context<'gc>) -> Trace {
+        std::iter::once(&self.current_frame)
+            .chain(self.call_stack.iter().rev())
+            .map(|frame| frame.trace_frame(cx))
+            .collect()
+    }
+
+    fn pop_frame(&mut self) -> Result<(), EngineError> {
         self.current_frame = self
             .call_stack
             .pop()
-            .ok_or_else(|| EngineError::CallStackUnderflow)
-            .unwrap();
+            .ok_or(EngineError::CallStackUnderflow)?;
+        Ok(())
     }
 
-    fn pop(&mut self) -> Value<'gc> {
+    fn slot(&mut self, slot: usize) -> Result<&mut Value<'gc>, EngineError> {
         self.stack
-            .pop()
-            .ok_or_else(|| EngineError::StackUnderflow)
-            .unwrap()
+            .get_mut(slot)
+            .ok_or(EngineError::InvalidStackSlot(slot))
     }
+
+    fn peek(&self) -> Result<Value<'gc>, EngineError> {
+        self.stack
+            .last()
+            .copied()
+            .ok_or(EngineError::StackUnderflow)
+    }
+
+    fn pop(&mut self) -> Result<Value<'gc>, EngineError> {
+        self.stack.pop().ok_or(EngineError::StackUnderflow)
+    }
+}
+
+/// Applies the checked integer operation, turning a failure into an error the script can catch.
+fn checked_int_op<'gc>(
+    cx: &Context<'gc>,
+    a: i64,
+    b: i64,
+    op: impl Fn(i64, i64) -> Option<i64>,
+) -> Result<i64, ErrorPtr<'gc>> {
+    // Only division and remainder can fail with a zero divisor, the rest only fail by overflowing.
+    op(a, b).ok_or_else(|| {
+        let message = if b == 0 {
+            "division by zero"
+        } else {
+            "integer overflow"
+        };
+        ErrorValue::new_message_ptr(cx, message)
+    })
 }
 
 pub enum Step<'gc> {
     Continue,
     Yield(Value<'gc>),
+    Join(FiberPtr<'gc>),
+    /// The module is being loaded by another fiber, which must finish first.
+    WaitModule(ModulePtr<'gc>),
     Return(Value<'gc>),
+    Error(ErrorPtr<'gc>),
+    /// The engine itself went wrong, so the fiber cannot carry on.
+    Fatal(EngineError),
+}
+
+/// Why the fiber stopped running instructions early.
+enum Interrupt<'gc> {
+    /// An error was thrown, which may be caught by the script.
+    Error(ErrorPtr<'gc>),
+    Fatal(EngineError),
+}
+
+impl<'gc> From<ErrorPtr<'gc>> for Interrupt<'gc> {
+    fn from(error: ErrorPtr<'gc>) -> Self {
+        Interrupt::Error(error)
+    }
+}
+
+impl<'gc> From<EngineError> for Interrupt<'gc> {
+    fn from(error: EngineError) -> Self {
+        Interrupt::Fatal(error)
+    }
+}
+
+#[derive(Collect, Debug)]
+#[collect(no_drop)]
+pub enum Status<'gc> {
+    Running,
+    Returned(Value<'gc>),
+    Failed(ErrorPtr<'gc>),
 }
 
 #[derive(Collect, Debug)]
@@ -181,6 +1089,8 @@ struct Frame<'gc> {
     pc: usize,
     stack_bottom: usize,
     current_try: Option<Try>,
+    // The module this frame is loading, if it is running the script of one.
+    module: Option<ModulePtr<'gc>>,
 }
 
 impl<'gc> Frame<'gc> {
@@ -190,6 +1100,43 @@ impl<'gc> Frame<'gc> {
             pc: 0,
             stack_bottom,
             current_try: None,
+            module: None,
+        }
+    }
+
+    pub fn new_native(native: NativeFunctionPtr<'gc>, stack_bottom: usize) -> Self {
+        Self {
+            steppable: Steppable::Native(native),
+            pc: 0,
+            stack_bottom,
+            current_try: None,
+            module: None,
+        }
+    }
+
+    pub fn new_module(
+        closure: ClosurePtr<'gc>,
+        module: ModulePtr<'gc>,
+        stack_bottom: usize,
+    ) -> Self {
+        Self {
+            module: Some(module),
+            ..Self::new_closure(closure, stack_bottom)
+        }
+    }
+
+    fn trace_frame(&self, cx: &Context<'gc>) -> TraceFrame {
+        // The pc has already moved past the instruction that was running.
+        let pc = self.pc.saturating_sub(1);
+        match self.steppable {
+            Steppable::Closure(closure) => {
+                let function = closure.function();
+                let name = function.name().map(|name| name.to_string());
+                let debug_info = function.debug_info();
+                let file = cx.state().source_name(debug_info.source());
+                TraceFrame::new(name, file, pc, debug_info.line(pc))
+            }
+            Steppable::Native(native) => TraceFrame::native(native.name().to_string()),
         }
     }
 }
@@ -198,6 +1145,7 @@ impl<'gc> Frame<'gc> {
 #[collect(no_drop)]
 enum Steppable<'gc> {
     Closure(ClosurePtr<'gc>),
+    Native(NativeFunctionPtr<'gc>),
 }
 
 #[derive(Collect, Debug)]
@@ -205,4 +1153,77 @@ enum Steppable<'gc> {
 struct Try {
     pc: usize,
     stack_len: usize,
+    // The try this one is nested in, within the same frame.
+    parent: Option<Box<Try>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Engine, Error};
+
+    fn runtime_error(source: &str) -> String {
+        match Engine::builder().build().evaluate_inline::<()>(source) {
+            Err(Error::Runtime { message, .. }) => message,
+            result => panic!(
+                "expected a runtime error, got {:?}",
+                result.map_err(|e| e.to_string())
+            ),
+        }
+    }
+
+    #[test]
+    fn integer_division_by_zero() {
+        assert_eq!(runtime_error("1 / 0"), "division by zero");
+        assert_eq!(runtime_error("5 % 0"), "division by zero");
+    }
+
+    #[test]
+    fn integer_overflow() {
+        assert_eq!(runtime_error("9223372036854775807 + 1"), "integer overflow");
+        assert_eq!(
+            runtime_error("-9223372036854775807 - 2"),
+            "integer overflow"
+        );
+        assert_eq!(runtime_error("4611686018427387904 * 2"), "integer overflow");
+        assert_eq!(
+            runtime_error("let min = -9223372036854775807 - 1; -min"),
+            "integer overflow"
+        );
+        assert_eq!(
+            runtime_error("let min = -9223372036854775807 - 1; min / -1"),
+            "integer overflow"
+        );
+    }
+
+    #[test]
+    fn arithmetic_errors_can_be_caught() {
+        let engine = Engine::builder().build();
+        let caught: bool = engine
+            .evaluate_inline("try { 1 / 0; false } catch { true }")
+            .unwrap();
+        assert!(caught);
+    }
+
+    #[test]
+    fn unbounded_recursion_overflows_the_stack() {
+        assert_eq!(runtime_error("fn f(n) { f(n + 1) } f(0)"), "stack overflow");
+    }
+
+    #[test]
+    fn stack_overflow_can_be_caught() {
+        let engine = Engine::builder().build();
+        let depth: i64 = engine
+            .evaluate_inline(
+                "let depth = 0; fn f() { depth = depth + 1; f() } try { f() } catch { depth }",
+            )
+            .unwrap();
+        assert!(depth > 100);
+    }
+
+    #[test]
+    fn float_division_by_zero() {
+        let engine = Engine::builder().build();
+        let result: f64 = engine.evaluate_inline("1.0 / 0").unwrap();
+        assert!(result.is_infinite());
+    }
 }
@@ -2,7 +2,15 @@ use core::fmt::{self, Display, Formatter};
 
 use gc_arena::{Collect, Gc};
 
-use crate::{context::Context, error::EngineError, string::StringPtr, value::Value};
+use crate::{
+    context::Context,
+    error::EngineError,
+    record::{InlineCache, RecordTypePtr},
+    source::SourceId,
+    string::StringPtr,
+    value::Value,
+    variant::EnumTypePtr,
+};
 
 pub type FunctionPtr<'gc> = Gc<'gc, Function<'gc>>;
 
@@ -13,9 +21,13 @@ pub const NO_OPERAND: u32 = 0;
 pub struct Function<'gc> {
     name: Option<StringPtr<'gc>>,
     arity: usize,
+    captures: Box<[Capture]>,
     functions: Box<[FunctionPtr<'gc>]>,
     constants: Box<[Constant<'gc>]>,
+    // One for each constant, used by the field instructions whose operand is that constant.
+    inline_caches: Box<[InlineCache]>,
     code: Box<[Instruction]>,
+    debug_info: DebugInfo,
 }
 
 impl<'gc> Function<'gc> {
@@ -23,41 +35,142 @@ impl<'gc> Function<'gc> {
         FunctionBuilder::default()
     }
 
+    pub fn name(&self) -> Option<StringPtr<'gc>> {
+        self.name
+    }
+
     pub fn arity(&self) -> usize {
         self.arity
     }
 
-    pub fn function(&self, index: usize) -> FunctionPtr<'gc> {
+    /// Where each upvalue of a closure of this function is captured from when it is created.
+    pub fn captures(&self) -> &[Capture] {
+        &self.captures
+    }
+
+    pub fn function(&self, index: usize) -> Result<FunctionPtr<'gc>, EngineError> {
         self.functions
             .get(index)
-            .ok_or_else(|| EngineError::InvalidFunctionIndex(index))
-            .unwrap()
-            .clone()
+            .copied()
+            .ok_or(EngineError::InvalidFunctionIndex(index))
     }
 
-    pub fn constant(&self, index: usize) -> Constant<'gc> {
+    pub fn constant(&self, index: usize) -> Result<Constant<'gc>, EngineError> {
         self.constants
             .get(index)
-            .ok_or_else(|| EngineError::InvalidConstantIndex(index))
-            .unwrap()
-            .clone()
+            .cloned()
+            .ok_or(EngineError::InvalidConstantIndex(index))
     }
 
-    pub fn instruction(&self, offset: usize) -> Instruction {
-        *self
-            .code
+    /// The constant at the index, which the compiler guarantees is a string, such as the name of
+    /// an export.
+    pub fn string_constant(&self, index: usize) -> Result<StringPtr<'gc>, EngineError> {
+        match self.constant(index)? {
+            Constant::String(string) => Ok(string),
+            _ => Err(EngineError::ExpectedStringConstant(index)),
+        }
+    }
+
+    /// The cache of the field instruction whose operand is the constant at the index.
+    pub fn inline_cache(&self, index: usize) -> Result<&InlineCache, EngineError> {
+        self.inline_caches
+            .get(index)
+            .ok_or(EngineError::InvalidConstantIndex(index))
+    }
+
+    pub fn instruction(&self, offset: usize) -> Result<Instruction, EngineError> {
+        self.code
             .get(offset)
-            .ok_or_else(|| EngineError::InvalidInstructionOffset(offset))
-            .unwrap()
+            .copied()
+            .ok_or(EngineError::InvalidInstructionOffset(offset))
+    }
+
+    pub fn debug_info(&self) -> &DebugInfo {
+        &self.debug_info
+    }
+}
+
+/// Maps instructions back to the source they were compiled from.
+#[derive(Collect, Debug, Default)]
+#[collect(require_static)]
+pub struct DebugInfo {
+    source: SourceId,
+    // Runs of instructions compiled from the same line, as the offset of the first instruction
+    // of the run along with the line, in order of offset.
+    lines: Vec<(usize, u32)>,
+    locals: Vec<LocalInfo>,
+}
+
+impl DebugInfo {
+    pub fn source(&self) -> SourceId {
+        self.source
+    }
+
+    /// The source line the instruction at the offset was compiled from.
+    pub fn line(&self, offset: usize) -> Option<u32> {
+        let run = self.lines.partition_point(|(start, _)| *start <= offset);
+        run.checked_sub(1).map(|run| self.lines[run].1)
+    }
+
+    /// The local variables that are in scope at the offset.
+    pub fn locals(&self, offset: usize) -> impl Iterator<Item = &LocalInfo> {
+        self.locals
+            .iter()
+            .filter(move |local| local.start <= offset && offset < local.end)
+    }
+
+    /// The name of the local variable in the slot at the offset, if there is one.
+    pub fn local_name(&self, slot: usize, offset: usize) -> Option<&str> {
+        self.locals(offset)
+            .find(|local| local.slot == slot)
+            .map(LocalInfo::name)
+    }
+}
+
+#[derive(Debug)]
+pub struct LocalInfo {
+    name: String,
+    slot: usize,
+    // The range of instruction offsets in which the local is in scope.
+    start: usize,
+    end: usize,
+}
+
+impl LocalInfo {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn slot(&self) -> usize {
+        self.slot
+    }
+
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn end(&self) -> usize {
+        self.end
     }
 }
 
+#[derive(Clone, Collect, Copy, Debug, Eq, PartialEq)]
+#[collect(require_static)]
+pub enum Capture {
+    /// A local in the frame of the enclosing function, at the given slot.
+    Local(usize),
+    /// An upvalue of the enclosing closure, at the given index.
+    Upvalue(usize),
+}
+
 #[derive(Clone, Collect, Debug)]
 #[collect(no_drop)]
 pub enum Constant<'gc> {
     Int(i64),
     Float(f64),
     String(StringPtr<'gc>),
+    RecordType(RecordTypePtr<'gc>),
+    EnumType(EnumTypePtr<'gc>),
 }
 
 impl<'gc> From<Constant<'gc>> for Value<'gc> {
@@ -66,6 +179,8 @@ impl<'gc> From<Constant<'gc>> for Value<'gc> {
             Constant::Int(int) => int.into(),
             Constant::Float(float) => float.into(),
             Constant::String(string) => string.into(),
+            Constant::RecordType(ty) => ty.into(),
+            Constant::EnumType(ty) => ty.into(),
         }
     }
 }
@@ -93,6 +208,8 @@ impl Display for Instruction {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self.opcode() {
             opcode::NO_OP => write!(f, "NO_OP"),
+            opcode::POP => write!(f, "POP"),
+            opcode::DROP => write!(f, "DROP {}", self.operand()),
             opcode::NIL => write!(f, "NIL"),
             opcode::TRUE => write!(f, "TRUE"),
             opcode::FALSE => write!(f, "FALSE"),
@@ -104,9 +221,48 @@ impl Display for Instruction {
             opcode::MUL => write!(f, "MUL"),
             opcode::DIV => write!(f, "DIV"),
             opcode::MOD => write!(f, "MOD"),
+            opcode::NEG => write!(f, "NEG"),
+            opcode::EQ => write!(f, "EQ"),
+            opcode::NE => write!(f, "NE"),
+            opcode::LT => write!(f, "LT"),
+            opcode::LE => write!(f, "LE"),
+            opcode::GT => write!(f, "GT"),
+            opcode::GE => write!(f, "GE"),
+            opcode::NOT => write!(f, "NOT"),
             opcode::RETURN => write!(f, "RETURN"),
+            opcode::CALL => write!(f, "CALL {}", self.operand()),
+            opcode::INVOKE => write!(f, "INVOKE {}", self.operand()),
+            opcode::SWITCH => write!(f, "SWITCH {}", self.operand()),
+            opcode::JUMP => write!(f, "JUMP {}", self.operand()),
+            opcode::JUMP_IF_FALSE => write!(f, "JUMP_IF_FALSE {}", self.operand()),
+            opcode::JUMP_IF_FALSE_OR_POP => write!(f, "JUMP_IF_FALSE_OR_POP {}", self.operand()),
+            opcode::JUMP_IF_TRUE_OR_POP => write!(f, "JUMP_IF_TRUE_OR_POP {}", self.operand()),
             opcode::SPAWN => write!(f, "SPAWN"),
             opcode::YIELD => write!(f, "YIELD"),
+            opcode::JOIN => write!(f, "JOIN"),
+            opcode::GET_LOCAL => write!(f, "GET_LOCAL {}", self.operand()),
+            opcode::SET_LOCAL => write!(f, "SET_LOCAL {}", self.operand()),
+            opcode::GET_UPVALUE => write!(f, "GET_UPVALUE {}", self.operand()),
+            opcode::SET_UPVALUE => write!(f, "SET_UPVALUE {}", self.operand()),
+            opcode::GET_GLOBAL => write!(f, "GET_GLOBAL {}", self.operand()),
+            opcode::SET_GLOBAL => write!(f, "SET_GLOBAL {}", self.operand()),
+            opcode::TRY_BEGIN => write!(f, "TRY_BEGIN {}", self.operand()),
+            opcode::TRY_END => write!(f, "TRY_END"),
+            opcode::THROW => write!(f, "THROW"),
+            opcode::NO_MATCH => write!(f, "NO_MATCH"),
+            opcode::IMPORT => write!(f, "IMPORT {}", self.operand()),
+            opcode::GET_EXPORT => write!(f, "GET_EXPORT {}", self.operand()),
+            opcode::EXPORT => write!(f, "EXPORT {}", self.operand()),
+            opcode::LIST => write!(f, "LIST {}", self.operand()),
+            opcode::GET_INDEX => write!(f, "GET_INDEX"),
+            opcode::SET_INDEX => write!(f, "SET_INDEX"),
+            opcode::MAP => write!(f, "MAP {}", self.operand()),
+            opcode::IN => write!(f, "IN"),
+            opcode::RECORD => write!(f, "RECORD {}", self.operand()),
+            opcode::GET_FIELD => write!(f, "GET_FIELD {}", self.operand()),
+            opcode::SET_FIELD => write!(f, "SET_FIELD {}", self.operand()),
+            opcode::IS_VARIANT => write!(f, "IS_VARIANT {}", self.operand()),
+            opcode::UNPACK => write!(f, "UNPACK {}", self.operand()),
             _ => write!(f, "UNKNOWN"),
         }
     }
@@ -114,6 +270,8 @@ impl Display for Instruction {
 
 pub mod opcode {
     pub const NO_OP: u8 = 0x00;
+    pub const POP: u8 = 0x01;
+    pub const DROP: u8 = 0x02;
 
     pub const NIL: u8 = 0x10;
     pub const TRUE: u8 = 0x11;
@@ -127,20 +285,65 @@ pub mod opcode {
     pub const MUL: u8 = 0x22;
     pub const DIV: u8 = 0x23;
     pub const MOD: u8 = 0x24;
+    pub const NEG: u8 = 0x25;
+    pub const LT: u8 = 0x26;
+    pub const LE: u8 = 0x27;
+    pub const GT: u8 = 0x28;
+    pub const GE: u8 = 0x29;
+    pub const NOT: u8 = 0x2A;
+    pub const EQ: u8 = 0x2B;
+    pub const NE: u8 = 0x2C;
 
     pub const RETURN: u8 = 0x30;
+    pub const CALL: u8 = 0x31;
+    pub const JUMP: u8 = 0x32;
+    pub const JUMP_IF_FALSE: u8 = 0x33;
+    pub const JUMP_IF_FALSE_OR_POP: u8 = 0x34;
+    pub const JUMP_IF_TRUE_OR_POP: u8 = 0x35;
+    pub const INVOKE: u8 = 0x36;
+    pub const SWITCH: u8 = 0x37;
 
     pub const SPAWN: u8 = 0x40;
     pub const YIELD: u8 = 0x41;
+    pub const JOIN: u8 = 0x42;
+
+    pub const GET_LOCAL: u8 = 0x50;
+    pub const SET_LOCAL: u8 = 0x51;
+    pub const GET_UPVALUE: u8 = 0x52;
+    pub const SET_UPVALUE: u8 = 0x53;
+    pub const GET_GLOBAL: u8 = 0x54;
+    pub const SET_GLOBAL: u8 = 0x55;
+
+    pub const TRY_BEGIN: u8 = 0x60;
+    pub const TRY_END: u8 = 0x61;
+    pub const THROW: u8 = 0x62;
+    pub const NO_MATCH: u8 = 0x63;
+
+    pub const IMPORT: u8 = 0x70;
+    pub const GET_EXPORT: u8 = 0x71;
+    pub const EXPORT: u8 = 0x72;
+
+    pub const LIST: u8 = 0x80;
+    pub const GET_INDEX: u8 = 0x81;
+    pub const SET_INDEX: u8 = 0x82;
+    pub const MAP: u8 = 0x83;
+    pub const IN: u8 = 0x84;
+    pub const RECORD: u8 = 0x85;
+    pub const GET_FIELD: u8 = 0x86;
+    pub const SET_FIELD: u8 = 0x87;
+    pub const IS_VARIANT: u8 = 0x88;
+    pub const UNPACK: u8 = 0x89;
 }
 
 #[derive(Default)]
 pub struct FunctionBuilder<'gc> {
     name: Option<StringPtr<'gc>>,
     arity: Option<usize>,
+    captures: Vec<Capture>,
     functions: Vec<FunctionPtr<'gc>>,
     constants: Vec<Constant<'gc>>,
     code: Vec<Instruction>,
+    debug_info: DebugInfo,
 }
 
 impl<'gc> FunctionBuilder<'gc> {
@@ -153,6 +356,25 @@ impl<'gc> FunctionBuilder<'gc> {
         self.arity = Some(arity);
     }
 
+    pub fn source(&mut self, source: SourceId) {
+        self.debug_info.source = source;
+    }
+
+    /// Records a local variable which is in scope for the instructions in `start..end`.
+    pub fn local(&mut self, name: String, slot: usize, start: usize, end: usize) {
+        self.debug_info.locals.push(LocalInfo {
+            name,
+            slot,
+            start,
+            end,
+        });
+    }
+
+    pub fn capture(&mut self, capture: Capture) -> usize {
+        self.captures.push(capture);
+        self.captures.len() - 1
+    }
+
     pub fn function(&mut self, function: FunctionPtr<'gc>) -> usize {
         self.functions.push(function);
         self.functions.len() - 1
@@ -163,18 +385,43 @@ impl<'gc> FunctionBuilder<'gc> {
         self.constants.len() - 1
     }
 
-    pub fn instruction(&mut self, op: u8, operand: u32) -> usize {
+    pub fn instruction(&mut self, op: u8, operand: u32, line: u32) -> usize {
+        let offset = self.code.len();
         self.code.push(Instruction::new(op, operand));
-        self.code.len() - 1
+        let lines = &mut self.debug_info.lines;
+        if lines.last().is_none_or(|(_, last_line)| *last_line != line) {
+            lines.push((offset, line));
+        }
+        offset
+    }
+
+    /// Replaces the operand of an instruction that has already been added, which is used to fill
+    /// in the target of a forward jump once it is known.
+    pub fn patch(&mut self, offset: usize, operand: u32) {
+        let op = self.code[offset].opcode();
+        self.code[offset] = Instruction::new(op, operand);
+    }
+
+    /// The offset of the next instruction to be added.
+    pub fn offset(&self) -> usize {
+        self.code.len()
     }
 
     pub fn build(self) -> Function<'gc> {
+        let inline_caches = self
+            .constants
+            .iter()
+            .map(|_| InlineCache::default())
+            .collect();
         Function {
             name: self.name,
             arity: self.arity.expect("arity is required"),
+            captures: self.captures.into_boxed_slice(),
             functions: self.functions.into_boxed_slice(),
             constants: self.constants.into_boxed_slice(),
+            inline_caches,
             code: self.code.into_boxed_slice(),
+            debug_info: self.debug_info,
         }
     }
 
@@ -182,3 +429,101 @@ impl<'gc> FunctionBuilder<'gc> {
         Gc::new(cx.mutation(), self.build())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Engine;
+
+    #[test]
+    fn line_table() {
+        let mut builder = FunctionBuilder::default();
+        builder.arity(0);
+        for line in [1, 1, 2, 2, 2, 4, 1] {
+            builder.instruction(opcode::NIL, NO_OPERAND, line);
+        }
+        let function = builder.build();
+        let debug_info = function.debug_info();
+
+        // Consecutive instructions on the same line share a single entry.
+        assert_eq!(debug_info.lines, [(0, 1), (2, 2), (5, 4), (6, 1)]);
+        let lines = (0..7)
+            .map(|offset| debug_info.line(offset))
+            .collect::<Vec<_>>();
+        assert_eq!(lines, [1, 1, 2, 2, 2, 4, 1].map(Some));
+    }
+
+    #[test]
+    fn empty_line_table() {
+        let mut builder = FunctionBuilder::default();
+        builder.arity(0);
+        assert_eq!(builder.build().debug_info().line(0), None);
+    }
+
+    #[test]
+    fn local_ranges() {
+        let mut builder = FunctionBuilder::default();
+        builder.arity(0);
+        builder.local("a".into(), 0, 0, 6);
+        builder.local("b".into(), 1, 2, 4);
+        builder.local("c".into(), 1, 4, 6);
+        let function = builder.build();
+        let debug_info = function.debug_info();
+
+        let names = |offset| {
+            debug_info
+                .locals(offset)
+                .map(LocalInfo::name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(0), ["a"]);
+        assert_eq!(names(3), ["a", "b"]);
+        // Ranges are exclusive of their end, so `b` is no longer in scope once `c` is.
+        assert_eq!(names(4), ["a", "c"]);
+        assert!(names(6).is_empty());
+
+        // Slots are reused by locals whose ranges don't overlap.
+        assert_eq!(debug_info.local_name(1, 3), Some("b"));
+        assert_eq!(debug_info.local_name(1, 5), Some("c"));
+        assert_eq!(debug_info.local_name(1, 1), None);
+        assert_eq!(debug_info.local_name(2, 3), None);
+    }
+
+    #[test]
+    fn compiled_debug_info() {
+        let engine = Engine::builder().build();
+        engine.enter(|cx| {
+            let closure = cx
+                .compile("<inline>", "let a = 1;\n{\n  let b = a;\n  b\n}")
+                .unwrap();
+            let function = closure.function();
+            let debug_info = function.debug_info();
+            let offsets = 0..function.code.len();
+
+            let lines = offsets
+                .clone()
+                .filter_map(|offset| debug_info.line(offset))
+                .collect::<Vec<_>>();
+            assert_eq!(lines.len(), function.code.len());
+            assert!(lines.contains(&1) && lines.contains(&3));
+            assert!(lines.iter().all(|line| (1..=5).contains(line)));
+
+            // `b` is only in scope within its block, while `a` is in scope for the rest of the
+            // script.
+            let a = debug_info
+                .locals
+                .iter()
+                .find(|local| local.name() == "a")
+                .unwrap();
+            let b = debug_info
+                .locals
+                .iter()
+                .find(|local| local.name() == "b")
+                .unwrap();
+            assert!(a.start() < b.start() && b.end() <= a.end());
+            assert_eq!(debug_info.local_name(b.slot(), b.start()), Some("b"));
+            assert_eq!(debug_info.local_name(b.slot(), b.end()), None);
+            assert!(offsets.contains(&b.end()));
+        });
+    }
+}
@@ -1,11 +1,21 @@
 pub use crate::{
     closure::ClosurePtr,
+    compile::{Diagnostic, Diagnostics, Severity, Span},
     context::Context,
-    engine::Engine,
-    error::Error,
-    value::{TryFromValue, Value},
+    engine::{Callee, Engine, EngineBuilder},
+    error::{EngineError, Error, ErrorPtr, ErrorValue, Trace, TraceFrame},
+    list::{ListPtr, ListValue},
+    map::{MapPtr, MapValue},
+    module::{FileModuleLoader, ModuleLoader},
+    record::{RecordPtr, RecordType, RecordTypePtr, RecordValue},
+    stash::{Fetchable, Stashable, StashedClosure, StashedValue},
+    value::{HashKey, IntoArgs, IntoValue, TryFromValue, Value},
+    variant::{EnumType, EnumTypePtr, Variant, VariantPtr, VariantValue},
 };
 
+#[cfg(feature = "derive")]
+pub use doji_derive::{IntoValue, TryFromValue};
+
 mod closure;
 mod compile;
 mod context;
@@ -14,6 +24,14 @@ mod engine;
 mod error;
 mod fiber;
 mod function;
+mod list;
+mod map;
+mod module;
+mod native;
+mod record;
+mod source;
+mod stash;
 mod state;
 mod string;
 mod value;
+mod variant;
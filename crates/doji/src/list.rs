@@ -0,0 +1,283 @@
+use core::fmt::{self, Display, Formatter};
+
+use gc_arena::{
+    Collect, Gc,
+    lock::{GcRefLock, RefLock},
+};
+
+use crate::{
+    context::Context,
+    error::{ErrorPtr, ErrorValue},
+    string::StringValue,
+    value::{TryFromValue, Value, fmt_container},
+};
+
+pub type ListPtr<'gc> = GcRefLock<'gc, ListValue<'gc>>;
+
+/// A growable sequence of values, which scripts create with `[a, b, c]` literals.
+#[derive(Collect, Debug, Default)]
+#[collect(no_drop)]
+pub struct ListValue<'gc> {
+    items: Vec<Value<'gc>>,
+}
+
+impl<'gc> ListValue<'gc> {
+    pub fn new_ptr(cx: &Context<'gc>, items: Vec<Value<'gc>>) -> ListPtr<'gc> {
+        Gc::new(cx.mutation(), RefLock::new(Self { items }))
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn items(&self) -> &[Value<'gc>] {
+        &self.items
+    }
+
+    pub fn push(&mut self, value: Value<'gc>) {
+        self.items.push(value);
+    }
+
+    pub fn pop(&mut self) -> Option<Value<'gc>> {
+        self.items.pop()
+    }
+
+    /// Gets the item at the index, where negative indices count back from the end.
+    pub fn get(&self, cx: &Context<'gc>, index: i64) -> Result<Value<'gc>, ErrorPtr<'gc>> {
+        let index = self.index(cx, index)?;
+        Ok(self.items[index])
+    }
+
+    /// Replaces the item at the index, where negative indices count back from the end.
+    pub fn set(
+        &mut self,
+        cx: &Context<'gc>,
+        index: i64,
+        value: Value<'gc>,
+    ) -> Result<(), ErrorPtr<'gc>> {
+        let index = self.index(cx, index)?;
+        self.items[index] = value;
+        Ok(())
+    }
+
+    /// Copies the items from `start` up to, but not including, `end` into a new list. Both ends
+    /// may be negative, and `end` may be the length of the list.
+    pub fn slice(
+        &self,
+        cx: &Context<'gc>,
+        start: i64,
+        end: i64,
+    ) -> Result<ListPtr<'gc>, ErrorPtr<'gc>> {
+        let bound = |index: i64| {
+            let resolved = if index < 0 {
+                index + self.items.len() as i64
+            } else {
+                index
+            };
+            if (0..=self.items.len() as i64).contains(&resolved) {
+                Ok(resolved as usize)
+            } else {
+                Err(self.out_of_bounds(cx, index))
+            }
+        };
+        let (start, end) = (bound(start)?, bound(end)?);
+        if start > end {
+            return Err(ErrorValue::new_message_ptr(
+                cx,
+                format!("slice starts at {} but ends at {}", start, end),
+            ));
+        }
+        Ok(Self::new_ptr(cx, self.items[start..end].to_vec()))
+    }
+
+    /// Calls the built-in method with the name on the list.
+    pub fn call_method(
+        list: ListPtr<'gc>,
+        cx: &Context<'gc>,
+        name: &str,
+        args: &[Value<'gc>],
+    ) -> Result<Value<'gc>, ErrorPtr<'gc>> {
+        match (name, args) {
+            ("push", &[value]) => {
+                list.borrow_mut(cx.mutation()).push(value);
+                Ok(Value::NIL)
+            }
+            ("pop", &[]) => list
+                .borrow_mut(cx.mutation())
+                .pop()
+                .ok_or_else(|| ErrorValue::new_message_ptr(cx, "cannot pop from an empty list")),
+            ("len", &[]) => Ok((list.borrow().len() as i64).into()),
+            ("slice", &[start]) => {
+                let list = list.borrow();
+                Ok(list
+                    .slice(cx, start.try_into(cx)?, list.len() as i64)?
+                    .into())
+            }
+            ("slice", &[start, end]) => Ok(list
+                .borrow()
+                .slice(cx, start.try_into(cx)?, end.try_into(cx)?)?
+                .into()),
+            ("push" | "pop" | "len" | "slice", _) => Err(ErrorValue::new_message_ptr(
+                cx,
+                format!(
+                    "wrong number of arguments to list method `{}`: got {}",
+                    name,
+                    args.len()
+                ),
+            )),
+            _ => Err(ErrorValue::new_message_ptr(
+                cx,
+                format!("list has no method named `{}`", name),
+            )),
+        }
+    }
+
+    /// Converts every item, failing on the first one that cannot be converted.
+    pub fn convert<T>(&self, cx: &Context<'gc>) -> Result<Vec<T>, ErrorPtr<'gc>>
+    where
+        T: TryFromValue<'gc>,
+    {
+        (0..self.items.len())
+            .map(|index| self.convert_item(cx, index))
+            .collect()
+    }
+
+    /// Converts the item at the index, which must be in bounds, saying where the item was if it
+    /// cannot be converted.
+    pub fn convert_item<T>(&self, cx: &Context<'gc>, index: usize) -> Result<T, ErrorPtr<'gc>>
+    where
+        T: TryFromValue<'gc>,
+    {
+        let item = self.items[index];
+        item.try_into(cx).map_err(|error| {
+            ErrorValue::new_ptr(
+                cx,
+                StringValue::new_ptr(cx, format!("{} at index {}", error, index)),
+                item,
+            )
+        })
+    }
+
+    fn index(&self, cx: &Context<'gc>, index: i64) -> Result<usize, ErrorPtr<'gc>> {
+        let resolved = if index < 0 {
+            index + self.items.len() as i64
+        } else {
+            index
+        };
+        if (0..self.items.len() as i64).contains(&resolved) {
+            Ok(resolved as usize)
+        } else {
+            Err(self.out_of_bounds(cx, index))
+        }
+    }
+
+    fn out_of_bounds(&self, cx: &Context<'gc>, index: i64) -> ErrorPtr<'gc> {
+        ErrorValue::new_message_ptr(
+            cx,
+            format!(
+                "index {} is out of bounds for a list of length {}",
+                index,
+                self.items.len()
+            ),
+        )
+    }
+}
+
+impl<'gc> Display for ListValue<'gc> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        fmt_container(f, self, "[...]", |f| {
+            write!(f, "[")?;
+            for (i, item) in self.items.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}", item)?;
+            }
+            write!(f, "]")
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Engine, Error};
+
+    fn evaluate(source: &str) -> Vec<i64> {
+        Engine::builder().build().evaluate_inline(source).unwrap()
+    }
+
+    fn thrown(source: &str) -> String {
+        match Engine::builder().build().evaluate_inline::<()>(source) {
+            Err(Error::Runtime { message, .. }) => message,
+            result => panic!(
+                "expected a runtime error, got {:?}",
+                result.map_err(|e| e.to_string())
+            ),
+        }
+    }
+
+    #[test]
+    fn methods() {
+        assert_eq!(evaluate("let a = [1, 2]; a.push(3); a"), [1, 2, 3]);
+        assert_eq!(evaluate("let a = [1, 2]; [a.pop(), a.len()]"), [2, 1]);
+        assert_eq!(evaluate("[1, 2, 3, 4].slice(1, 3)"), [2, 3]);
+        assert_eq!(evaluate("[1, 2, 3, 4].slice(2)"), [3, 4]);
+        assert_eq!(evaluate("[1, 2, 3].slice(3)"), Vec::<i64>::new());
+        assert_eq!(thrown("[].pop();"), "cannot pop from an empty list");
+        assert_eq!(
+            thrown("[1].push();"),
+            "wrong number of arguments to list method `push`: got 0"
+        );
+        assert_eq!(thrown("[1].sort();"), "list has no method named `sort`");
+    }
+
+    #[test]
+    fn indexing() {
+        assert_eq!(evaluate("let a = [1, 2, 3]; a[0] = 4; a"), [4, 2, 3]);
+        assert_eq!(evaluate("let a = [1, 2, 3]; [a[-1], a[-3]]"), [3, 1]);
+        assert_eq!(evaluate("let a = [1, 2, 3]; a[-1] = 4; a"), [1, 2, 4]);
+        assert_eq!(evaluate("[1, 2, 3, 4].slice(-3, -1)"), [2, 3]);
+    }
+
+    #[test]
+    fn out_of_bounds() {
+        assert_eq!(
+            thrown("[1, 2][2];"),
+            "index 2 is out of bounds for a list of length 2"
+        );
+        assert_eq!(
+            thrown("[1, 2][-3];"),
+            "index -3 is out of bounds for a list of length 2"
+        );
+        assert_eq!(
+            thrown("let a = []; a[0] = 1;"),
+            "index 0 is out of bounds for a list of length 0"
+        );
+        assert_eq!(
+            thrown("[1, 2].slice(0, 3);"),
+            "index 3 is out of bounds for a list of length 2"
+        );
+        assert_eq!(
+            thrown("[1, 2].slice(2, 1);"),
+            "slice starts at 2 but ends at 1"
+        );
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(thrown(r#"throw [1, "a", [nil]];"#), "[1, a, [nil]]");
+    }
+
+    #[test]
+    fn display_cycles() {
+        assert_eq!(thrown("let a = [1]; a.push(a); throw a;"), "[1, [...]]");
+        assert_eq!(
+            thrown("let a = [1]; let b = [a, a]; a.push(b); throw b;"),
+            "[[1, [...]], [1, [...]]]"
+        );
+    }
+}
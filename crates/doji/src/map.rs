@@ -0,0 +1,312 @@
+use core::fmt::{self, Display, Formatter};
+
+use std::collections::HashMap;
+
+use gc_arena::{
+    Collect, Gc,
+    lock::{GcRefLock, RefLock},
+};
+
+use crate::{
+    context::Context,
+    error::{ErrorPtr, ErrorValue},
+    list::ListValue,
+    string::StringValue,
+    value::{HashKey, TryFromValue, Value, fmt_container},
+};
+
+pub type MapPtr<'gc> = GcRefLock<'gc, MapValue<'gc>>;
+
+/// Key-value pairs, kept in the order they were inserted so that iterating over them is
+/// deterministic. Rust types with named fields are converted to maps keyed by their field names.
+#[derive(Collect, Debug, Default)]
+#[collect(no_drop)]
+pub struct MapValue<'gc> {
+    entries: Vec<(HashKey<'gc>, Value<'gc>)>,
+    // The position of each key in the entries.
+    indices: HashMap<HashKey<'gc>, usize>,
+}
+
+impl<'gc> MapValue<'gc> {
+    pub fn new_ptr(
+        cx: &Context<'gc>,
+        entries: impl IntoIterator<Item = (HashKey<'gc>, Value<'gc>)>,
+    ) -> MapPtr<'gc> {
+        let mut map = Self::default();
+        for (key, value) in entries {
+            map.insert(key, value);
+        }
+        Gc::new(cx.mutation(), RefLock::new(map))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &HashKey<'gc>) -> Option<Value<'gc>> {
+        self.indices.get(key).map(|&index| self.entries[index].1)
+    }
+
+    /// Gets the value of a string key, such as the name of a field. Unlike [`MapValue::get`],
+    /// this searches through every entry, which is fine for the handful of fields of a record.
+    pub fn get_str(&self, key: &str) -> Option<Value<'gc>> {
+        self.entries
+            .iter()
+            .find(|(k, _)| k.value().as_str() == Some(key))
+            .map(|(_, value)| *value)
+    }
+
+    pub fn contains(&self, key: &HashKey<'gc>) -> bool {
+        self.indices.contains_key(key)
+    }
+
+    /// Sets the value of the key, returning its previous value. New keys are added at the end.
+    pub fn insert(&mut self, key: HashKey<'gc>, value: Value<'gc>) -> Option<Value<'gc>> {
+        match self.indices.get(&key) {
+            Some(&index) => Some(std::mem::replace(&mut self.entries[index].1, value)),
+            None => {
+                self.indices.insert(key, self.entries.len());
+                self.entries.push((key, value));
+                None
+            }
+        }
+    }
+
+    /// Removes the key, returning its value. The remaining entries keep their order.
+    pub fn remove(&mut self, key: &HashKey<'gc>) -> Option<Value<'gc>> {
+        let index = self.indices.remove(key)?;
+        let (_, value) = self.entries.remove(index);
+        for (key, _) in &self.entries[index..] {
+            *self.indices.get_mut(key).expect("every key is indexed") -= 1;
+        }
+        Some(value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Value<'gc>, Value<'gc>)> + '_ {
+        self.entries
+            .iter()
+            .map(|(key, value)| (key.value(), *value))
+    }
+
+    /// Calls the built-in method with the name on the map.
+    pub fn call_method(
+        map: MapPtr<'gc>,
+        cx: &Context<'gc>,
+        name: &str,
+        args: &[Value<'gc>],
+    ) -> Result<Value<'gc>, ErrorPtr<'gc>> {
+        match (name, args) {
+            ("len", &[]) => Ok((map.borrow().len() as i64).into()),
+            ("get", &[key]) => {
+                let key = HashKey::new(cx, key)?;
+                Ok(map.borrow().get(&key).unwrap_or_default())
+            }
+            ("remove", &[key]) => {
+                let key = HashKey::new(cx, key)?;
+                Ok(map
+                    .borrow_mut(cx.mutation())
+                    .remove(&key)
+                    .unwrap_or_default())
+            }
+            ("keys", &[]) => {
+                let keys = map.borrow().iter().map(|(key, _)| key).collect();
+                Ok(ListValue::new_ptr(cx, keys).into())
+            }
+            ("values", &[]) => {
+                let values = map.borrow().iter().map(|(_, value)| value).collect();
+                Ok(ListValue::new_ptr(cx, values).into())
+            }
+            ("entries", &[]) => {
+                let entries = map
+                    .borrow()
+                    .iter()
+                    .map(|(key, value)| ListValue::new_ptr(cx, vec![key, value]).into())
+                    .collect();
+                Ok(ListValue::new_ptr(cx, entries).into())
+            }
+            ("len" | "get" | "remove" | "keys" | "values" | "entries", _) => {
+                Err(ErrorValue::new_message_ptr(
+                    cx,
+                    format!(
+                        "wrong number of arguments to map method `{}`: got {}",
+                        name,
+                        args.len()
+                    ),
+                ))
+            }
+            _ => Err(ErrorValue::new_message_ptr(
+                cx,
+                format!("map has no method named `{}`", name),
+            )),
+        }
+    }
+
+    /// Converts the value of the field, failing if it is missing.
+    pub fn field<T>(&self, cx: &Context<'gc>, name: &str) -> Result<T, ErrorPtr<'gc>>
+    where
+        T: TryFromValue<'gc>,
+    {
+        match self.get_str(name) {
+            Some(value) => convert_field(cx, name, value),
+            None => Err(ErrorValue::new_message_ptr(
+                cx,
+                format!("missing field `{}`", name),
+            )),
+        }
+    }
+
+    /// Converts the value of the field, falling back to the default if it is missing.
+    pub fn field_or_default<T>(&self, cx: &Context<'gc>, name: &str) -> Result<T, ErrorPtr<'gc>>
+    where
+        T: TryFromValue<'gc> + Default,
+    {
+        match self.get_str(name) {
+            Some(value) => convert_field(cx, name, value),
+            None => Ok(T::default()),
+        }
+    }
+}
+
+pub(crate) fn convert_field<'gc, T>(
+    cx: &Context<'gc>,
+    name: &str,
+    value: Value<'gc>,
+) -> Result<T, ErrorPtr<'gc>>
+where
+    T: TryFromValue<'gc>,
+{
+    value.try_into(cx).map_err(|error| {
+        ErrorValue::new_ptr(
+            cx,
+            StringValue::new_ptr(cx, format!("{} at field `{}`", error, name)),
+            value,
+        )
+    })
+}
+
+impl<'gc> Display for MapValue<'gc> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        fmt_container(f, self, "{...}", |f| {
+            write!(f, "{{")?;
+            for (i, (key, value)) in self.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}: {}", key, value)?;
+            }
+            write!(f, "}}")
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{Engine, Error, TryFromValue};
+
+    fn evaluate<T>(source: &str) -> T
+    where
+        T: for<'gc> TryFromValue<'gc>,
+    {
+        Engine::builder().build().evaluate_inline(source).unwrap()
+    }
+
+    fn thrown(source: &str) -> String {
+        match Engine::builder().build().evaluate_inline::<()>(source) {
+            Err(Error::Runtime { message, .. }) => message,
+            result => panic!(
+                "expected a runtime error, got {:?}",
+                result.map_err(|e| e.to_string())
+            ),
+        }
+    }
+
+    #[test]
+    fn literals_and_indexing() {
+        let map: HashMap<String, i64> = evaluate(r#"{ a: 1, "b c": 2 }"#);
+        assert_eq!(map, HashMap::from([("a".into(), 1), ("b c".into(), 2)]));
+        assert_eq!(
+            evaluate::<Vec<i64>>(r#"let m = { 1: 2, true: 3, nil: 4 }; [m[1], m[true], m[nil]]"#),
+            [2, 3, 4]
+        );
+        assert_eq!(
+            evaluate::<Vec<i64>>(
+                r#"let m = { a: 1 }; m["a"] = 2; m.b = 3; m[4] = 5; [m.a, m["b"], m[4]]"#
+            ),
+            [2, 3, 5]
+        );
+        assert_eq!(
+            evaluate::<Vec<bool>>(
+                r#"let m = { a: 1, 2: nil }; ["a" in m, 2 in m, "b" in m, 1.5 in m]"#
+            ),
+            [true, true, false, false]
+        );
+    }
+
+    #[test]
+    fn insertion_order() {
+        assert_eq!(
+            evaluate::<Vec<String>>(r#"let m = { b: 1, a: 2 }; m.c = 3; m.b = 4; m.keys()"#),
+            ["b", "a", "c"]
+        );
+        assert_eq!(
+            evaluate::<Vec<String>>(
+                r#"let m = { b: 1, a: 2, c: 3 }; m.remove("b"); m.b = 4; m.keys()"#
+            ),
+            ["a", "c", "b"]
+        );
+        assert_eq!(
+            evaluate::<Vec<i64>>("let m = { b: 1, a: 2 }; m.values()"),
+            [1, 2]
+        );
+        assert_eq!(
+            evaluate::<Vec<(String, i64)>>("let m = { b: 1, a: 2 }; m.entries()"),
+            [("b".to_string(), 1), ("a".to_string(), 2)]
+        );
+    }
+
+    #[test]
+    fn missing_keys() {
+        assert_eq!(thrown("{ a: 1 }.b;"), "key b is not in the map");
+        assert_eq!(thrown("{ a: 1 }[2];"), "key 2 is not in the map");
+        assert_eq!(evaluate::<Option<i64>>("{ a: 1 }.get(\"b\")"), None);
+        assert_eq!(evaluate::<Option<i64>>("{ a: 1 }.remove(\"b\")"), None);
+        assert_eq!(
+            evaluate::<i64>("let m = { a: 1 }; m.remove(\"a\"); m.len()"),
+            0
+        );
+    }
+
+    #[test]
+    fn float_keys() {
+        assert_eq!(
+            thrown("let m = {}; m[1.5] = 1;"),
+            "values of type float cannot be used as map keys"
+        );
+        assert_eq!(
+            thrown("{}[0.0];"),
+            "values of type float cannot be used as map keys"
+        );
+        assert_eq!(
+            thrown("{}.get(1.5);"),
+            "values of type float cannot be used as map keys"
+        );
+    }
+
+    #[test]
+    fn display_cycles() {
+        assert_eq!(
+            thrown("let m = { a: 1 }; m.b = m; throw m;"),
+            "{a: 1, b: {...}}"
+        );
+        assert_eq!(
+            thrown("let m = {}; let l = [m]; m.l = l; throw l;"),
+            "[{l: [...]}]"
+        );
+    }
+}
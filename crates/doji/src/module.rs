@@ -0,0 +1,301 @@
+use std::{
+    collections::HashMap,
+    fs, io, mem,
+    path::{Path, PathBuf},
+};
+
+use gc_arena::{
+    Collect, Gc,
+    lock::{GcRefLock, RefLock},
+};
+
+use crate::{context::Context, fiber::FiberPtr, string::StringPtr, value::Value};
+
+/// Provides the source of the modules imported by scripts.
+pub trait ModuleLoader {
+    /// Turns the path written in an import into the path the module is loaded and cached under,
+    /// so that different ways of writing the same path share a module. The importer is the name
+    /// of the source the import is in. By default, the path is used exactly as it was written.
+    fn resolve(&self, path: &str, _importer: &str) -> io::Result<String> {
+        Ok(path.to_string())
+    }
+
+    /// Returns the source of the module, given its resolved path.
+    fn load(&self, path: &str) -> io::Result<String>;
+}
+
+impl<F> ModuleLoader for F
+where
+    F: Fn(&str) -> io::Result<String>,
+{
+    fn load(&self, path: &str) -> io::Result<String> {
+        self(path)
+    }
+}
+
+/// Serves modules embedded in the host, keyed by their path.
+impl ModuleLoader for HashMap<String, String> {
+    fn load(&self, path: &str) -> io::Result<String> {
+        self.get(path).cloned().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("no module at {}", path))
+        })
+    }
+}
+
+/// Reads modules from files. Relative paths are resolved against the directory of the file doing
+/// the import, or against a root directory if the import isn't in a file, such as in inline
+/// source.
+#[derive(Debug)]
+pub struct FileModuleLoader {
+    root: PathBuf,
+}
+
+impl FileModuleLoader {
+    pub fn new(root: impl AsRef<Path>) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+}
+
+impl Default for FileModuleLoader {
+    /// Uses the current working directory as the root.
+    fn default() -> Self {
+        Self::new(".")
+    }
+}
+
+impl ModuleLoader for FileModuleLoader {
+    /// Resolves the path to its canonical form, with all links and `.` and `..` components
+    /// removed.
+    fn resolve(&self, path: &str, importer: &str) -> io::Result<String> {
+        let importer = Path::new(importer);
+        let dir = match importer.parent() {
+            Some(dir) if importer.is_file() => dir,
+            _ => &self.root,
+        };
+        let path = fs::canonicalize(dir.join(path))?;
+        Ok(path.to_string_lossy().into_owned())
+    }
+
+    fn load(&self, path: &str) -> io::Result<String> {
+        fs::read_to_string(path)
+    }
+}
+
+pub type ModulePtr<'gc> = GcRefLock<'gc, ModuleValue<'gc>>;
+
+#[derive(Collect, Debug)]
+#[collect(no_drop)]
+pub struct ModuleValue<'gc> {
+    #[collect(require_static)]
+    path: String,
+    status: ModuleStatus<'gc>,
+    exports: Vec<(StringPtr<'gc>, Value<'gc>)>,
+    // The fibers waiting for another fiber to finish loading this module.
+    waiters: Vec<FiberPtr<'gc>>,
+}
+
+impl<'gc> ModuleValue<'gc> {
+    /// Creates a module which is being loaded by the given fiber.
+    pub fn new_ptr(cx: &Context<'gc>, path: String, loader: FiberPtr<'gc>) -> ModulePtr<'gc> {
+        Gc::new(
+            cx.mutation(),
+            RefLock::new(Self {
+                path,
+                status: ModuleStatus::Loading(loader),
+                exports: Vec::new(),
+                waiters: Vec::new(),
+            }),
+        )
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn status(&self) -> &ModuleStatus<'gc> {
+        &self.status
+    }
+
+    pub fn export(&mut self, name: StringPtr<'gc>, value: Value<'gc>) {
+        match self
+            .exports
+            .iter_mut()
+            .find(|(export, _)| export.as_str() == name.as_str())
+        {
+            Some((_, export)) => *export = value,
+            None => self.exports.push((name, value)),
+        }
+    }
+
+    pub fn get_export(&self, name: &str) -> Option<Value<'gc>> {
+        self.exports
+            .iter()
+            .find(|(export, _)| export.as_str() == name)
+            .map(|(_, value)| *value)
+    }
+
+    /// Marks the module as loaded, returning the fibers that were waiting for it.
+    pub fn finish_loading(&mut self) -> Vec<FiberPtr<'gc>> {
+        self.status = ModuleStatus::Loaded;
+        mem::take(&mut self.waiters)
+    }
+
+    /// Makes the fiber wake up once the module has either loaded or failed to load.
+    pub fn add_waiter(&mut self, waiter: FiberPtr<'gc>) {
+        self.waiters.push(waiter);
+    }
+
+    pub fn take_waiters(&mut self) -> Vec<FiberPtr<'gc>> {
+        mem::take(&mut self.waiters)
+    }
+}
+
+#[derive(Collect, Debug)]
+#[collect(no_drop)]
+pub enum ModuleStatus<'gc> {
+    /// The module is still being evaluated by the fiber.
+    Loading(FiberPtr<'gc>),
+    Loaded,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, env, fs, path::PathBuf, process};
+
+    use crate::{Engine, Error, FileModuleLoader};
+
+    fn engine(modules: &[(&str, &str)]) -> Engine {
+        let modules = modules
+            .iter()
+            .map(|(path, source)| (path.to_string(), source.to_string()))
+            .collect::<HashMap<_, _>>();
+        Engine::builder().module_loader(modules).build()
+    }
+
+    fn runtime_error(result: Result<i64, Error>) -> String {
+        match result {
+            Err(Error::Runtime { message, .. }) => message,
+            result => panic!(
+                "expected a runtime error, got {:?}",
+                result.map_err(|e| e.to_string())
+            ),
+        }
+    }
+
+    /// Creates an empty directory for the test to write modules into.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("doji-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn import_exports() {
+        let engine = engine(&[
+            (
+                "math",
+                "export let two = 2; export fn double(n) { n * two }",
+            ),
+            ("unused", "export let value = 1;"),
+        ]);
+        let value: i64 = engine
+            .evaluate_inline(r#"import { two, double } from "math"; double(two) + 1"#)
+            .unwrap();
+        assert_eq!(value, 5);
+    }
+
+    #[test]
+    fn modules_are_evaluated_once() {
+        let engine = engine(&[
+            ("counter", "export let counts = [];"),
+            ("a", r#"import { counts } from "counter"; counts.push(1);"#),
+        ]);
+        let value: i64 = engine
+            .evaluate_inline(
+                r#"import "a"; import "a"; import { counts } from "counter"; counts.len()"#,
+            )
+            .unwrap();
+        assert_eq!(value, 1);
+    }
+
+    #[test]
+    fn missing_export() {
+        let engine = engine(&[("m", "export let a = 1;")]);
+        let result = engine.evaluate_inline(r#"import { b } from "m"; b"#);
+        assert_eq!(runtime_error(result), "module m has no export named `b`");
+    }
+
+    #[test]
+    fn import_cycle() {
+        let engine = engine(&[
+            ("a", r#"import "b"; export let a = 1;"#),
+            ("b", r#"import "a"; export let b = 1;"#),
+        ]);
+        let result = engine.evaluate_inline(r#"import { a } from "a"; a"#);
+        assert_eq!(
+            runtime_error(result),
+            "import cycle detected: a is still being loaded"
+        );
+    }
+
+    #[test]
+    fn import_cycle_across_fibers() {
+        // Each module yields before importing the other, so that one fiber starts loading `a`
+        // and the other `b` before either of them gets to the second import.
+        let engine = engine(&[
+            ("a", r#"yield 0; import "b"; export let a = 1;"#),
+            ("b", r#"yield 0; import "a"; export let b = 1;"#),
+        ]);
+        let result =
+            engine.evaluate_inline(r#"spawn fn() { import "a"; }; import { b } from "b"; b"#);
+        assert!(runtime_error(result).starts_with("import cycle detected"));
+    }
+
+    #[test]
+    fn files_resolve_relative_to_the_importer() {
+        let dir = temp_dir("relative-imports");
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(
+            dir.join("main.doji"),
+            r#"import { value } from "./lib/a.doji"; value"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.join("lib/a.doji"),
+            r#"import { b } from "b.doji"; export let value = b + 1;"#,
+        )
+        .unwrap();
+        fs::write(dir.join("lib/b.doji"), "export let b = 1;").unwrap();
+
+        let engine = Engine::builder().build();
+        let value: i64 = engine.evaluate_file(dir.join("main.doji")).unwrap();
+        assert_eq!(value, 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn files_are_cached_by_their_canonical_path() {
+        let dir = temp_dir("canonical-imports");
+        fs::write(dir.join("counter.doji"), "export let counts = [];").unwrap();
+        fs::write(
+            dir.join("a.doji"),
+            r#"import { counts } from "./counter.doji"; counts.push(1);"#,
+        )
+        .unwrap();
+
+        let engine = Engine::builder()
+            .module_loader(FileModuleLoader::new(&dir))
+            .build();
+        let value: i64 = engine
+            .evaluate_inline(
+                r#"import "a.doji"; import "./a.doji"; import { counts } from "counter.doji";
+                counts.len()"#,
+            )
+            .unwrap();
+        assert_eq!(value, 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,121 @@
+use core::fmt::{self, Debug, Formatter};
+
+use gc_arena::{Collect, Gc};
+
+use crate::{context::Context, error::ErrorPtr, value::Value};
+
+pub type NativeFunctionPtr<'gc> = Gc<'gc, NativeFunction>;
+
+/// The signature of the Rust closures that can be called from scripts. They are given all the
+/// arguments of the call, and either return a value or raise an error.
+pub type NativeFn =
+    dyn for<'gc> Fn(&Context<'gc>, &[Value<'gc>]) -> Result<Value<'gc>, ErrorPtr<'gc>>;
+
+/// A function implemented in Rust by the host.
+#[derive(Collect)]
+#[collect(require_static)]
+pub struct NativeFunction {
+    name: String,
+    function: Box<NativeFn>,
+}
+
+impl NativeFunction {
+    pub fn new_ptr<'gc>(
+        cx: &Context<'gc>,
+        name: String,
+        function: Box<NativeFn>,
+    ) -> NativeFunctionPtr<'gc> {
+        Gc::new(cx.mutation(), Self { name, function })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn call<'gc>(
+        &self,
+        cx: &Context<'gc>,
+        args: &[Value<'gc>],
+    ) -> Result<Value<'gc>, ErrorPtr<'gc>> {
+        (self.function)(cx, args)
+    }
+}
+
+impl Debug for NativeFunction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NativeFunction")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Engine, Error, ErrorValue, IntoValue, TraceFrame};
+
+    /// An engine with `add`, which adds two integers, and `fail`, which always raises an error.
+    fn engine() -> Engine {
+        Engine::builder()
+            .function("add", |cx, args| match args {
+                &[a, b] => {
+                    let (a, b): (i64, i64) = (a.try_into(cx)?, b.try_into(cx)?);
+                    (a + b).into_value(cx)
+                }
+                _ => Err(ErrorValue::new_message_ptr(cx, "add takes two arguments")),
+            })
+            .function("fail", |cx, _| {
+                Err(ErrorValue::new_message_ptr(cx, "native failure"))
+            })
+            .build()
+    }
+
+    fn runtime_error(source: &str) -> (String, Vec<TraceFrame>) {
+        match engine().evaluate_inline::<i64>(source) {
+            Err(Error::Runtime { message, trace }) => (message, trace.frames().to_vec()),
+            result => panic!(
+                "expected a runtime error, got {:?}",
+                result.map_err(|e| e.to_string())
+            ),
+        }
+    }
+
+    #[test]
+    fn call_natives() {
+        let engine = engine();
+        assert_eq!(engine.evaluate_inline::<i64>("add(1, 2)").unwrap(), 3);
+        assert_eq!(
+            engine
+                .evaluate_inline::<i64>("fn twice(f, x) { f(f(x, x), x) } twice(add, 2)")
+                .unwrap(),
+            6
+        );
+        assert_eq!(runtime_error("add(1)").0, "add takes two arguments");
+        assert_eq!(
+            runtime_error(r#"add(1, "2")"#).0,
+            "expected int, found string"
+        );
+    }
+
+    #[test]
+    fn catch_native_errors() {
+        let value: i64 = engine()
+            .evaluate_inline("let caught = try { fail(); 1 } catch { 2 }; caught")
+            .unwrap();
+        assert_eq!(value, 2);
+
+        let (message, frames) = runtime_error("fn outer() {\n  fail()\n}\nouter()");
+        assert_eq!(message, "native failure");
+        let frames = frames
+            .iter()
+            .map(|frame| (frame.function(), frame.is_native(), frame.line()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            frames,
+            [
+                (Some("fail"), true, None),
+                (Some("outer"), false, Some(2)),
+                (Some("<script>"), false, Some(4)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,296 @@
+use core::{
+    cell::Cell,
+    fmt::{self, Display, Formatter},
+};
+
+use gc_arena::{
+    Collect, Gc,
+    lock::{GcRefLock, RefLock},
+};
+
+use crate::{
+    context::Context,
+    error::{ErrorPtr, ErrorValue},
+    map::convert_field,
+    string::StringPtr,
+    value::{TryFromValue, Value, fmt_container},
+};
+
+pub type RecordTypePtr<'gc> = Gc<'gc, RecordType>;
+
+pub type RecordPtr<'gc> = GcRefLock<'gc, RecordValue<'gc>>;
+
+/// Identifies a record type, so that inline caches can tell types apart without holding on to
+/// them.
+#[derive(Clone, Collect, Copy, Debug, Eq, PartialEq)]
+#[collect(require_static)]
+pub struct RecordTypeId(u32);
+
+impl RecordTypeId {
+    /// Returns the current id, and moves the counter on to the next one.
+    pub fn take_next(next: &Cell<u32>) -> Self {
+        let id = next.get();
+        next.set(id + 1);
+        Self(id)
+    }
+}
+
+/// A type declared with `struct`, which has a fixed set of named fields.
+#[derive(Collect, Debug)]
+#[collect(require_static)]
+pub struct RecordType {
+    id: RecordTypeId,
+    name: String,
+    fields: Box<[String]>,
+}
+
+impl RecordType {
+    pub fn new_ptr<'gc>(
+        cx: &Context<'gc>,
+        name: String,
+        fields: Box<[String]>,
+    ) -> RecordTypePtr<'gc> {
+        let id = cx.state().next_record_type_id();
+        Gc::new(cx.mutation(), Self { id, name, fields })
+    }
+
+    pub fn id(&self) -> RecordTypeId {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn fields(&self) -> &[String] {
+        &self.fields
+    }
+
+    /// The position of the field in the records of this type.
+    pub fn field_index(&self, name: &str) -> Option<usize> {
+        self.fields.iter().position(|field| field == name)
+    }
+}
+
+/// An instance of a record type, holding a value for each of its fields.
+#[derive(Collect, Debug)]
+#[collect(no_drop)]
+pub struct RecordValue<'gc> {
+    ty: RecordTypePtr<'gc>,
+    fields: Box<[Value<'gc>]>,
+}
+
+impl<'gc> RecordValue<'gc> {
+    /// Creates a record from the fields given by name, which must be exactly the fields of the
+    /// type.
+    pub fn new_ptr(
+        cx: &Context<'gc>,
+        ty: RecordTypePtr<'gc>,
+        given: impl IntoIterator<Item = (StringPtr<'gc>, Value<'gc>)>,
+    ) -> Result<RecordPtr<'gc>, ErrorPtr<'gc>> {
+        let mut fields: Box<[Option<Value<'gc>>]> = vec![None; ty.fields().len()].into();
+        for (name, value) in given {
+            let index = ty
+                .field_index(name.as_str())
+                .ok_or_else(|| no_field(cx, &ty, name.as_str()))?;
+            fields[index] = Some(value);
+        }
+        let fields = fields
+            .iter()
+            .zip(ty.fields())
+            .map(|(value, name)| {
+                value.ok_or_else(|| {
+                    ErrorValue::new_message_ptr(
+                        cx,
+                        format!("missing field `{}` in {}", name, ty.name()),
+                    )
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Gc::new(cx.mutation(), RefLock::new(Self { ty, fields })))
+    }
+
+    pub fn ty(&self) -> RecordTypePtr<'gc> {
+        self.ty
+    }
+
+    /// The value of the field at the position given by [`RecordType::field_index`].
+    pub fn field(&self, index: usize) -> Option<Value<'gc>> {
+        self.fields.get(index).copied()
+    }
+
+    pub fn set_field(&mut self, index: usize, value: Value<'gc>) -> Option<()> {
+        *self.fields.get_mut(index)? = value;
+        Some(())
+    }
+
+    /// Converts the value of the field with the name, failing if the type has no such field.
+    pub fn convert_field<T>(&self, cx: &Context<'gc>, name: &str) -> Result<T, ErrorPtr<'gc>>
+    where
+        T: TryFromValue<'gc>,
+    {
+        match self.named_field(name) {
+            Some(value) => convert_field(cx, name, value),
+            None => Err(ErrorValue::new_message_ptr(
+                cx,
+                format!("missing field `{}` in {}", name, self.ty.name()),
+            )),
+        }
+    }
+
+    /// Converts the value of the field with the name, falling back to the default if the type has
+    /// no such field.
+    pub fn convert_field_or_default<T>(
+        &self,
+        cx: &Context<'gc>,
+        name: &str,
+    ) -> Result<T, ErrorPtr<'gc>>
+    where
+        T: TryFromValue<'gc> + Default,
+    {
+        match self.named_field(name) {
+            Some(value) => convert_field(cx, name, value),
+            None => Ok(T::default()),
+        }
+    }
+
+    fn named_field(&self, name: &str) -> Option<Value<'gc>> {
+        self.field(self.ty.field_index(name)?)
+    }
+}
+
+/// Remembers where a field was found the last time an instruction accessed it, which is most
+/// likely where it will be found the next time.
+#[derive(Collect, Debug, Default)]
+#[collect(require_static)]
+pub struct InlineCache(Cell<Option<(RecordTypeId, usize)>>);
+
+impl InlineCache {
+    /// Finds the position of the field in records of the type, using the cache if it was last
+    /// filled in for the same type.
+    pub fn field_index(&self, ty: &RecordType, name: &str) -> Option<usize> {
+        if let Some((id, index)) = self.0.get()
+            && id == ty.id()
+        {
+            return Some(index);
+        }
+        let index = ty.field_index(name)?;
+        self.0.set(Some((ty.id(), index)));
+        Some(index)
+    }
+}
+
+pub fn no_field<'gc>(cx: &Context<'gc>, ty: &RecordType, name: &str) -> ErrorPtr<'gc> {
+    ErrorValue::new_message_ptr(cx, format!("{} has no field named `{}`", ty.name(), name))
+}
+
+impl Display for RecordType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "<struct {}>", self.name)
+    }
+}
+
+impl<'gc> Display for RecordValue<'gc> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ", self.ty.name())?;
+        fmt_container(f, self, "{...}", |f| {
+            write!(f, "{{ ")?;
+            for (i, (name, value)) in self.ty.fields().iter().zip(&self.fields).enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}: {}", name, value)?;
+            }
+            write!(f, " }}")
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Engine, Error};
+
+    fn evaluate(source: &str) -> Vec<i64> {
+        Engine::builder().build().evaluate_inline(source).unwrap()
+    }
+
+    fn thrown(source: &str) -> String {
+        match Engine::builder().build().evaluate_inline::<()>(source) {
+            Err(Error::Runtime { message, .. }) => message,
+            result => panic!(
+                "expected a runtime error, got {:?}",
+                result.map_err(|e| e.to_string())
+            ),
+        }
+    }
+
+    #[test]
+    fn fields() {
+        assert_eq!(
+            evaluate("struct Point { x, y } let p = Point { y: 2, x: 1 }; [p.x, p.y]"),
+            [1, 2]
+        );
+        assert_eq!(
+            evaluate("struct Point { x, y } let p = Point { x: 1, y: 2 }; p.x = 3; [p.x, p.y]"),
+            [3, 2]
+        );
+    }
+
+    #[test]
+    fn unknown_and_missing_fields() {
+        assert_eq!(
+            thrown("struct Point { x, y } Point { x: 1, y: 2, z: 3 };"),
+            "Point has no field named `z`"
+        );
+        assert_eq!(
+            thrown("struct Point { x, y } Point { x: 1 };"),
+            "missing field `y` in Point"
+        );
+        assert_eq!(
+            thrown("struct Point { x, y } Point { x: 1, y: 2 }.z;"),
+            "Point has no field named `z`"
+        );
+        assert_eq!(
+            thrown("struct Point { x, y } let p = Point { x: 1, y: 2 }; p.z = 3;"),
+            "Point has no field named `z`"
+        );
+    }
+
+    #[test]
+    fn inline_cache_with_several_types() {
+        // The same instructions read and write `b`, which is at a different position in each type.
+        let source = "
+            struct A { a, b }
+            struct B { b }
+            fn bump(r) { r.b = r.b + 1; r.b }
+            let a = A { a: 0, b: 10 };
+            let b = B { b: 20 };
+            [bump(a), bump(b), bump(a), bump(b), a.a]
+        ";
+        assert_eq!(evaluate(source), [11, 21, 12, 22, 0]);
+        let source = "
+            struct A { a, b }
+            struct B { a }
+            fn get(r) { r.b }
+            get(A { a: 1, b: 2 });
+            get(B { a: 1 });
+        ";
+        assert_eq!(thrown(source), "B has no field named `b`");
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(
+            thrown("struct Point { x, y } throw Point { x: 1, y: 2 };"),
+            "Point { x: 1, y: 2 }"
+        );
+    }
+
+    #[test]
+    fn display_cycles() {
+        assert_eq!(
+            thrown("struct Node { next } let n = Node { next: nil }; n.next = n; throw n;"),
+            "Node { next: Node {...} }"
+        );
+    }
+}
@@ -0,0 +1,54 @@
+use std::{collections::HashMap, sync::Arc};
+
+use gc_arena::Collect;
+
+/// Identifies the source a function was compiled from.
+#[derive(Clone, Collect, Copy, Debug, Default, Eq, Hash, PartialEq)]
+#[collect(require_static)]
+pub struct SourceId(u32);
+
+/// The names of all the sources compiled by the engine, such as file paths, so that anything
+/// holding a [`SourceId`] can point back to where it came from.
+#[derive(Collect, Debug, Default)]
+#[collect(require_static)]
+pub struct SourceMap {
+    names: Vec<Arc<str>>,
+    ids: HashMap<Arc<str>, SourceId>,
+}
+
+impl SourceMap {
+    /// Returns the id of the name, which is shared by every source compiled under it, so that
+    /// compiling again doesn't grow the map.
+    pub fn add(&mut self, name: &str) -> SourceId {
+        if let Some(id) = self.ids.get(name) {
+            return *id;
+        }
+        let name: Arc<str> = name.into();
+        let id = SourceId(self.names.len() as u32);
+        self.names.push(name.clone());
+        self.ids.insert(name, id);
+        id
+    }
+
+    pub fn name(&self, id: SourceId) -> Option<Arc<str>> {
+        self.names.get(id.0 as usize).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sources_with_the_same_name_share_an_id() {
+        let mut sources = SourceMap::default();
+        let inline = sources.add("<inline>");
+        let file = sources.add("main.doji");
+        assert_ne!(inline, file);
+        for _ in 0..10 {
+            assert_eq!(sources.add("<inline>"), inline);
+        }
+        assert_eq!(sources.names.len(), 2);
+        assert_eq!(sources.name(file).as_deref(), Some("main.doji"));
+    }
+}
@@ -0,0 +1,130 @@
+use gc_arena::{DynamicRoot, Rootable};
+
+use crate::{closure::ClosurePtr, context::Context, engine::Callee, error::Error, value::Value};
+
+/// Values that can be stashed in the root set of the engine, to be kept alive outside of
+/// [`Engine::enter`](crate::Engine::enter).
+pub trait Stashable<'gc> {
+    type Stashed;
+
+    fn stash(self, cx: &Context<'gc>) -> Self::Stashed;
+}
+
+/// Handles to stashed values, which can be fetched back inside a later
+/// [`Engine::enter`](crate::Engine::enter).
+pub trait Fetchable {
+    type Fetched<'gc>;
+
+    /// # Panics
+    ///
+    /// Panics if the handle was stashed by a different engine.
+    fn fetch<'gc>(&self, cx: &Context<'gc>) -> Self::Fetched<'gc>;
+}
+
+/// A value stashed in the root set of the engine. The value is kept alive until every clone of
+/// the handle has been dropped.
+#[derive(Clone)]
+pub struct StashedValue(DynamicRoot<Rootable![Value<'_>]>);
+
+impl<'gc> Stashable<'gc> for Value<'gc> {
+    type Stashed = StashedValue;
+
+    fn stash(self, cx: &Context<'gc>) -> StashedValue {
+        StashedValue(cx.state().roots().stash(cx.mutation(), self))
+    }
+}
+
+impl Fetchable for StashedValue {
+    type Fetched<'gc> = Value<'gc>;
+
+    fn fetch<'gc>(&self, cx: &Context<'gc>) -> Value<'gc> {
+        *cx.state().roots().fetch(&self.0)
+    }
+}
+
+impl Callee for &StashedValue {
+    fn callee<'gc>(self, cx: &Context<'gc>) -> Result<Value<'gc>, Error> {
+        Ok(self.fetch(cx))
+    }
+}
+
+/// A closure stashed in the root set of the engine, such as a callback the host calls later. The
+/// closure is kept alive until every clone of the handle has been dropped.
+#[derive(Clone)]
+pub struct StashedClosure(DynamicRoot<Rootable![ClosurePtr<'_>]>);
+
+impl<'gc> Stashable<'gc> for ClosurePtr<'gc> {
+    type Stashed = StashedClosure;
+
+    fn stash(self, cx: &Context<'gc>) -> StashedClosure {
+        StashedClosure(cx.state().roots().stash(cx.mutation(), self))
+    }
+}
+
+impl Fetchable for StashedClosure {
+    type Fetched<'gc> = ClosurePtr<'gc>;
+
+    fn fetch<'gc>(&self, cx: &Context<'gc>) -> ClosurePtr<'gc> {
+        *cx.state().roots().fetch(&self.0)
+    }
+}
+
+impl Callee for &StashedClosure {
+    fn callee<'gc>(self, cx: &Context<'gc>) -> Result<Value<'gc>, Error> {
+        Ok(self.fetch(cx).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Engine, IntoValue, StashedValue};
+
+    fn allocated(engine: &Engine) -> usize {
+        engine.enter(|cx| cx.mutation().metrics().total_allocation())
+    }
+
+    fn stash_string(engine: &Engine, string: &str) -> StashedValue {
+        engine.enter(|cx| cx.stash(string.into_value(cx).unwrap()))
+    }
+
+    #[test]
+    fn stashed_values_survive_collection() {
+        let mut engine = Engine::builder().build();
+        let stashed = stash_string(&engine, "kept");
+        let _: i64 = engine.evaluate_inline("let s = \"garbage\"; 1").unwrap();
+        engine.collect_all();
+        let _: i64 = engine.evaluate_inline("2").unwrap();
+        engine.collect_all();
+        let fetched = engine.enter(|cx| cx.fetch(&stashed).as_str().map(str::to_string));
+        assert_eq!(fetched.as_deref(), Some("kept"));
+    }
+
+    #[test]
+    fn fetch_returns_the_same_value() {
+        let mut engine = Engine::builder().build();
+        let stashed = stash_string(&engine, "kept");
+        let first = engine.enter(|cx| cx.fetch(&stashed).as_str().unwrap().as_ptr());
+        engine.collect_all();
+        let clone = stashed.clone();
+        let second = engine.enter(|cx| cx.fetch(&clone).as_str().unwrap().as_ptr());
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn dropping_every_handle_releases_the_value() {
+        let mut engine = Engine::builder().build();
+        let stashed = stash_string(&engine, "released");
+        let clone = stashed.clone();
+        engine.collect_all();
+        let before = allocated(&engine);
+
+        // The value is still rooted by the clone.
+        drop(stashed);
+        engine.collect_all();
+        assert_eq!(allocated(&engine), before);
+
+        drop(clone);
+        engine.collect_all();
+        assert!(allocated(&engine) < before);
+    }
+}
@@ -1,17 +1,26 @@
-use std::collections::VecDeque;
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet, VecDeque},
+    io,
+    sync::Arc,
+};
 
 use gc_arena::{
-    Collect, Gc, Mutation,
+    Collect, DynamicRootSet, Gc, Mutation,
     lock::{GcRefLock, RefLock},
 };
 use generational_arena::Arena as GenArena;
 
 use crate::{
     closure::ClosurePtr,
+    compile::Diagnostics,
     context::Context,
     driver::Id,
-    error::EngineError,
+    error::{EngineError, ErrorPtr},
     fiber::{self, FiberPtr, FiberValue},
+    module::{ModuleLoader, ModulePtr},
+    record::RecordTypeId,
+    source::{SourceId, SourceMap},
     value::Value,
 };
 
@@ -21,24 +30,131 @@ pub struct State<'gc> {
     root_fiber: GcRefLock<'gc, Option<FiberPtr<'gc>>>,
     ready_queue: GcRefLock<'gc, VecDeque<FiberPtr<'gc>>>,
     pending_arena: GcRefLock<'gc, PendingArena<'gc>>,
+    sources: GcRefLock<'gc, SourceMap>,
+    // The modules that have been imported, or are still being loaded, by their path.
+    modules: GcRefLock<'gc, HashMap<String, ModulePtr<'gc>>>,
+    #[collect(require_static)]
+    module_loader: Box<dyn ModuleLoader>,
+    // The variables visible to every script, provided by the host or defined by earlier scripts.
+    globals: GcRefLock<'gc, HashMap<String, Value<'gc>>>,
+    // The names of the globals the host has set, which scripts don't define over.
+    #[collect(require_static)]
+    host_globals: RefCell<HashSet<String>>,
+    // Keeps the values stashed by the host alive.
+    roots: DynamicRootSet<'gc>,
+    #[collect(require_static)]
+    next_record_type_id: Cell<u32>,
+    // The warnings from every source compiled since they were last cleared.
+    #[collect(require_static)]
+    warnings: RefCell<Vec<Diagnostics>>,
 }
 
 impl<'gc> State<'gc> {
-    pub fn new(mutation: &'gc Mutation<'gc>) -> Self {
+    pub fn new(mutation: &'gc Mutation<'gc>, module_loader: Box<dyn ModuleLoader>) -> Self {
         Self {
             root_fiber: Gc::new(mutation, RefLock::default()),
             ready_queue: Gc::new(mutation, RefLock::default()),
             pending_arena: Gc::new(mutation, RefLock::default()),
+            sources: Gc::new(mutation, RefLock::default()),
+            modules: Gc::new(mutation, RefLock::default()),
+            module_loader,
+            globals: Gc::new(mutation, RefLock::default()),
+            host_globals: RefCell::default(),
+            roots: DynamicRootSet::new(mutation),
+            next_record_type_id: Cell::new(0),
+            warnings: RefCell::default(),
         }
     }
 
-    pub fn spawn(&self, cx: &Context<'gc>, closure: ClosurePtr<'gc>) -> FiberPtr<'gc> {
-        let fiber = FiberValue::new_ptr(cx, closure);
+    pub fn roots(&self) -> DynamicRootSet<'gc> {
+        self.roots
+    }
+
+    /// Registers a newly compiled source under the name it should be reported with, reusing the
+    /// id of any earlier source with the same name.
+    pub fn add_source(&self, cx: &Context<'gc>, name: &str) -> SourceId {
+        self.sources.borrow_mut(cx.mutation()).add(name)
+    }
+
+    pub fn source_name(&self, id: SourceId) -> Option<Arc<str>> {
+        self.sources.borrow().name(id)
+    }
 
-        // Set this fiber as the root fiber if there aren't any existing fibers.
-        if self.ready_queue.borrow().is_empty() && self.pending_arena.borrow().is_empty() {
-            let _ = self.root_fiber.borrow_mut(cx.mutation()).insert(fiber);
+    /// Keeps the warnings of a source that compiled successfully, until they are cleared.
+    pub fn add_warnings(&self, warnings: Diagnostics) {
+        if !warnings.is_empty() {
+            self.warnings.borrow_mut().push(warnings);
         }
+    }
+
+    pub fn warnings(&self) -> Vec<Diagnostics> {
+        self.warnings.borrow().clone()
+    }
+
+    pub fn clear_warnings(&self) {
+        self.warnings.borrow_mut().clear();
+    }
+
+    pub fn next_record_type_id(&self) -> RecordTypeId {
+        RecordTypeId::take_next(&self.next_record_type_id)
+    }
+
+    pub fn global(&self, name: &str) -> Option<Value<'gc>> {
+        self.globals.borrow().get(name).copied()
+    }
+
+    /// Sets the global, defining it if it doesn't exist yet.
+    pub fn set_global(&self, cx: &Context<'gc>, name: impl Into<String>, value: Value<'gc>) {
+        self.globals
+            .borrow_mut(cx.mutation())
+            .insert(name.into(), value);
+    }
+
+    /// Sets a global on behalf of the host, so that scripts can't define over it.
+    pub fn set_host_global(&self, cx: &Context<'gc>, name: impl Into<String>, value: Value<'gc>) {
+        let name = name.into();
+        self.host_globals.borrow_mut().insert(name.clone());
+        self.set_global(cx, name, value);
+    }
+
+    pub fn is_host_global(&self, name: &str) -> bool {
+        self.host_globals.borrow().contains(name)
+    }
+
+    pub fn module(&self, path: &str) -> Option<ModulePtr<'gc>> {
+        self.modules.borrow().get(path).copied()
+    }
+
+    pub fn resolve_module(&self, path: &str, importer: &str) -> io::Result<String> {
+        self.module_loader.resolve(path, importer)
+    }
+
+    pub fn load_module_source(&self, path: &str) -> io::Result<String> {
+        self.module_loader.load(path)
+    }
+
+    pub fn add_module(&self, cx: &Context<'gc>, module: ModulePtr<'gc>) {
+        let path = module.borrow().path().to_string();
+        self.modules.borrow_mut(cx.mutation()).insert(path, module);
+    }
+
+    /// Forgets a module that failed to load, so that importing it again tries again. Any fibers
+    /// waiting for it are woken up to do so.
+    pub fn remove_module(&self, cx: &Context<'gc>, module: ModulePtr<'gc>) {
+        let path = module.borrow().path().to_string();
+        self.modules.borrow_mut(cx.mutation()).remove(&path);
+        let waiters = module.borrow_mut(cx.mutation()).take_waiters();
+        self.ready_queue.borrow_mut(cx.mutation()).extend(waiters);
+    }
+
+    /// Marks the module as loaded, waking up any fibers waiting for it.
+    pub fn finish_module(&self, cx: &Context<'gc>, module: ModulePtr<'gc>) {
+        let waiters = module.borrow_mut(cx.mutation()).finish_loading();
+        self.ready_queue.borrow_mut(cx.mutation()).extend(waiters);
+    }
+
+    pub fn spawn(&self, cx: &Context<'gc>, closure: ClosurePtr<'gc>) -> FiberPtr<'gc> {
+        let fiber = FiberValue::new_ptr(cx, closure);
 
         // Enqueue the fiber for evaluation.
         self.ready_queue.borrow_mut(cx.mutation()).push_back(fiber);
@@ -46,6 +162,13 @@ impl<'gc> State<'gc> {
         fiber
     }
 
+    /// Spawns the fiber of a new evaluation, which is complete once this fiber finishes.
+    pub fn spawn_root(&self, cx: &Context<'gc>, closure: ClosurePtr<'gc>) -> FiberPtr<'gc> {
+        let fiber = self.spawn(cx, closure);
+        *self.root_fiber.borrow_mut(cx.mutation()) = Some(fiber);
+        fiber
+    }
+
     pub fn step(&self, cx: &Context<'gc>) -> Step<'gc> {
         // Dequeue the next fiber to be evaluated.
         let fiber = match self.ready_queue.borrow_mut(cx.mutation()).pop_front() {
@@ -65,19 +188,34 @@ impl<'gc> State<'gc> {
         let id = self.pending_arena.borrow_mut(cx.mutation()).insert(fiber);
 
         // We also want to know whether the fiber is the root fiber.
-        let is_root_fiber = Gc::ptr_eq(fiber, self.root_fiber.borrow().unwrap());
-
-        dbg!(is_root_fiber);
+        let is_root_fiber = self
+            .root_fiber
+            .borrow()
+            .is_some_and(|root_fiber| Gc::ptr_eq(fiber, root_fiber));
 
         // Run one step of the evaluation of the fiber.
-        match fiber.borrow_mut(cx.mutation()).step(cx) {
+        let step = fiber.borrow_mut(cx.mutation()).step(cx, fiber);
+        match step {
             fiber::Step::Continue => {
                 self.pending_arena.borrow_mut(cx.mutation()).remove(id);
+                self.ready_queue.borrow_mut(cx.mutation()).push_back(fiber);
                 Step::Continue
             }
             fiber::Step::Yield(value) => Step::Yield(id, value),
-            fiber::Step::Return(value) => {
+            fiber::Step::Join(target) => {
+                // The fiber is woken up again once the target finishes.
+                self.pending_arena.borrow_mut(cx.mutation()).remove(id);
+                target.borrow_mut(cx.mutation()).add_joiner(fiber);
+                Step::Continue
+            }
+            fiber::Step::WaitModule(module) => {
+                // The fiber is woken up again once the module is loaded by another fiber.
                 self.pending_arena.borrow_mut(cx.mutation()).remove(id);
+                module.borrow_mut(cx.mutation()).add_waiter(fiber);
+                Step::Continue
+            }
+            fiber::Step::Return(value) => {
+                self.finish(cx, id, fiber);
 
                 // If the root fiber returns, we're done with this evaluation, otherwise continue.
                 if is_root_fiber {
@@ -87,18 +225,39 @@ impl<'gc> State<'gc> {
                     Step::Continue
                 }
             }
+            fiber::Step::Error(error) => {
+                self.finish(cx, id, fiber);
+
+                // Failures of other fibers are only seen by the fibers that join them.
+                if is_root_fiber {
+                    Step::Error(error)
+                } else {
+                    Step::Continue
+                }
+            }
+            fiber::Step::Fatal(error) => {
+                // The fiber is left in an unknown state, so it can't be run any further.
+                self.pending_arena.borrow_mut(cx.mutation()).remove(id);
+                Step::Fatal(error)
+            }
         }
     }
 
-    pub fn wake(&self, cx: &Context<'gc>, id: Id, res: Value<'gc>) {
+    fn finish(&self, cx: &Context<'gc>, id: Id, fiber: FiberPtr<'gc>) {
+        self.pending_arena.borrow_mut(cx.mutation()).remove(id);
+        let joiners = fiber.borrow_mut(cx.mutation()).take_joiners();
+        self.ready_queue.borrow_mut(cx.mutation()).extend(joiners);
+    }
+
+    pub fn wake(&self, cx: &Context<'gc>, id: Id, res: Value<'gc>) -> Result<(), EngineError> {
         let fiber = self
             .pending_arena
             .borrow_mut(cx.mutation())
             .remove(id)
-            .ok_or(EngineError::WakeNonExistentFiber)
-            .unwrap();
+            .ok_or(EngineError::WakeNonExistentFiber)?;
         fiber.borrow_mut(cx.mutation()).push(cx, res);
         self.ready_queue.borrow_mut(cx.mutation()).push_back(fiber);
+        Ok(())
     }
 }
 
@@ -106,6 +265,8 @@ pub enum Step<'gc> {
     Continue,
     Yield(Id, Value<'gc>),
     Return(Value<'gc>),
+    Error(ErrorPtr<'gc>),
+    Fatal(EngineError),
 }
 
 #[derive(Default)]
@@ -119,14 +280,6 @@ impl<'gc> PendingArena<'gc> {
     fn remove(&mut self, id: Id) -> Option<FiberPtr<'gc>> {
         self.0.remove(id.into())
     }
-
-    fn len(&self) -> usize {
-        self.0.len()
-    }
-
-    fn is_empty(&self) -> bool {
-        self.0.is_empty()
-    }
 }
 
 unsafe impl<'gc> Collect for PendingArena<'gc> {
@@ -143,3 +296,79 @@ unsafe impl<'gc> Collect for PendingArena<'gc> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{Engine, Error};
+
+    fn evaluate(source: &str) -> i64 {
+        Engine::builder().build().evaluate_inline(source).unwrap()
+    }
+
+    fn runtime_error(source: &str) -> String {
+        match Engine::builder().build().evaluate_inline::<i64>(source) {
+            Err(Error::Runtime { message, .. }) => message,
+            result => panic!(
+                "expected a runtime error, got {:?}",
+                result.map_err(|e| e.to_string())
+            ),
+        }
+    }
+
+    #[test]
+    fn join_finished_fiber() {
+        // The loop gives the child plenty of steps to finish before it is joined.
+        let source = "
+            let child = spawn fn() { 1 + 1 };
+            let i = 0;
+            while i < 20 { i = i + 1; }
+            join child + join child
+        ";
+        assert_eq!(evaluate(source), 4);
+    }
+
+    #[test]
+    fn join_failed_fiber() {
+        assert_eq!(
+            runtime_error(r#"let child = spawn fn() { throw "boom"; }; join child"#),
+            "boom"
+        );
+        let source = r#"
+            let child = spawn fn() { throw "boom"; };
+            let caught = try { join child } catch { 1 };
+            caught + try { join child } catch { 2 }
+        "#;
+        assert_eq!(evaluate(source), 3);
+    }
+
+    #[test]
+    fn several_joiners() {
+        let source = "
+            let slow = spawn fn() {
+                let i = 0;
+                while i < 20 { i = i + 1; }
+                i
+            };
+            let a = spawn fn() { join slow + 1 };
+            let b = spawn fn() { join slow + 2 };
+            join a + join b + join slow
+        ";
+        assert_eq!(evaluate(source), 63);
+    }
+
+    #[test]
+    fn failed_fiber_does_not_abort_others() {
+        let source = r#"
+            spawn fn() { throw "boom"; };
+            let sibling = spawn fn() {
+                let i = 0;
+                while i < 20 { i = i + 1; }
+                i
+            };
+            let i = 0;
+            while i < 20 { i = i + 1; }
+            join sibling + i
+        "#;
+        assert_eq!(evaluate(source), 40);
+    }
+}
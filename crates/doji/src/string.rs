@@ -14,9 +14,13 @@ impl<'gc> StringValue {
     pub fn new_ptr(cx: &Context<'gc>, string: String) -> StringPtr<'gc> {
         Gc::new(cx.mutation(), StringValue(string))
     }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
 }
 
-impl<'gc> Display for StringValue {
+impl Display for StringValue {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.0)
     }
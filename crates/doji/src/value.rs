@@ -1,13 +1,27 @@
-use core::fmt::{self, Display, Formatter};
+use core::{
+    cell::RefCell,
+    cmp::Ordering,
+    fmt::{self, Display, Formatter},
+    hash::{Hash, Hasher},
+    mem,
+};
+
+use std::collections::HashMap;
 
-use gc_arena::Collect;
+use gc_arena::{Collect, Gc};
 
 use crate::{
     closure::ClosurePtr,
     context::Context,
     error::{ErrorPtr, ErrorValue},
     fiber::FiberPtr,
+    list::{ListPtr, ListValue},
+    map::{MapPtr, MapValue, convert_field},
+    module::ModulePtr,
+    native::NativeFunctionPtr,
+    record::{RecordPtr, RecordTypePtr},
     string::{StringPtr, StringValue},
+    variant::{EnumTypePtr, VariantPtr},
 };
 
 #[derive(Debug)]
@@ -20,6 +34,14 @@ pub enum ValueType {
     Closure,
     Fiber,
     Error,
+    Module,
+    NativeFunction,
+    Map,
+    List,
+    Record,
+    RecordType,
+    Variant,
+    EnumType,
 }
 
 impl Display for ValueType {
@@ -33,6 +55,14 @@ impl Display for ValueType {
             Self::Closure => write!(f, "closure"),
             Self::Fiber => write!(f, "fiber"),
             Self::Error => write!(f, "error"),
+            Self::Module => write!(f, "module"),
+            Self::NativeFunction => write!(f, "native function"),
+            Self::Map => write!(f, "map"),
+            Self::List => write!(f, "list"),
+            Self::Record => write!(f, "record"),
+            Self::RecordType => write!(f, "record type"),
+            Self::Variant => write!(f, "variant"),
+            Self::EnumType => write!(f, "enum type"),
         }
     }
 }
@@ -52,6 +82,14 @@ enum ValueInner<'gc> {
     Closure(ClosurePtr<'gc>),
     Fiber(FiberPtr<'gc>),
     Error(ErrorPtr<'gc>),
+    Module(ModulePtr<'gc>),
+    NativeFunction(NativeFunctionPtr<'gc>),
+    Map(MapPtr<'gc>),
+    List(ListPtr<'gc>),
+    Record(RecordPtr<'gc>),
+    RecordType(RecordTypePtr<'gc>),
+    Variant(VariantPtr<'gc>),
+    EnumType(EnumTypePtr<'gc>),
 }
 
 impl<'gc> Value<'gc> {
@@ -69,9 +107,97 @@ impl<'gc> Value<'gc> {
             ValueInner::Closure(_) => ValueType::Closure,
             ValueInner::Fiber(_) => ValueType::Fiber,
             ValueInner::Error(_) => ValueType::Error,
+            ValueInner::Module(_) => ValueType::Module,
+            ValueInner::NativeFunction(_) => ValueType::NativeFunction,
+            ValueInner::Map(_) => ValueType::Map,
+            ValueInner::List(_) => ValueType::List,
+            ValueInner::Record(_) => ValueType::Record,
+            ValueInner::RecordType(_) => ValueType::RecordType,
+            ValueInner::Variant(_) => ValueType::Variant,
+            ValueInner::EnumType(_) => ValueType::EnumType,
+        }
+    }
+
+    /// The value if it is an int. Unlike converting to `f64`, floats are never accepted.
+    pub fn as_int(&self) -> Option<i64> {
+        match self.0 {
+            ValueInner::Int(int) => Some(int),
+            _ => None,
+        }
+    }
+
+    /// The value if it is a float. Unlike converting to `f64`, ints are never accepted.
+    pub fn as_float(&self) -> Option<f64> {
+        match self.0 {
+            ValueInner::Float(float) => Some(float),
+            _ => None,
+        }
+    }
+
+    /// The contents of the value if it is a string.
+    pub fn as_str(&self) -> Option<&str> {
+        match &self.0 {
+            ValueInner::String(string) => Some(string.as_str()),
+            _ => None,
         }
     }
 
+    /// Only `nil` and `false` are falsy, everything else is truthy.
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self.0, ValueInner::Nil | ValueInner::Bool(false))
+    }
+
+    /// Whether the values are equal. Ints and floats are compared by their numeric value, strings
+    /// by their contents, variants by their fields, and everything else by identity. Values of
+    /// different types are never equal.
+    pub fn equals(&self, other: &Value<'gc>) -> bool {
+        match (self.0, other.0) {
+            (ValueInner::Nil, ValueInner::Nil) => true,
+            (ValueInner::Bool(a), ValueInner::Bool(b)) => a == b,
+            (ValueInner::Int(a), ValueInner::Int(b)) => a == b,
+            (ValueInner::Float(a), ValueInner::Float(b)) => a == b,
+            (ValueInner::Int(a), ValueInner::Float(b))
+            | (ValueInner::Float(b), ValueInner::Int(a)) => a as f64 == b,
+            (ValueInner::String(a), ValueInner::String(b)) => a.as_str() == b.as_str(),
+            (ValueInner::Closure(a), ValueInner::Closure(b)) => Gc::ptr_eq(a, b),
+            (ValueInner::Fiber(a), ValueInner::Fiber(b)) => Gc::ptr_eq(a, b),
+            (ValueInner::Error(a), ValueInner::Error(b)) => Gc::ptr_eq(a, b),
+            (ValueInner::Module(a), ValueInner::Module(b)) => Gc::ptr_eq(a, b),
+            (ValueInner::NativeFunction(a), ValueInner::NativeFunction(b)) => Gc::ptr_eq(a, b),
+            (ValueInner::Map(a), ValueInner::Map(b)) => Gc::ptr_eq(a, b),
+            (ValueInner::List(a), ValueInner::List(b)) => Gc::ptr_eq(a, b),
+            (ValueInner::Record(a), ValueInner::Record(b)) => Gc::ptr_eq(a, b),
+            (ValueInner::RecordType(a), ValueInner::RecordType(b)) => Gc::ptr_eq(a, b),
+            (ValueInner::Variant(a), ValueInner::Variant(b)) => a.equals(&b),
+            (ValueInner::EnumType(a), ValueInner::EnumType(b)) => Gc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+
+    /// Orders numbers by their numeric value and strings lexicographically. Returns `None` if the
+    /// values cannot be ordered, which is also the case for NaN.
+    pub fn compare(&self, other: &Value<'gc>) -> Option<Ordering> {
+        match (self.0, other.0) {
+            (ValueInner::Int(a), ValueInner::Int(b)) => Some(a.cmp(&b)),
+            (ValueInner::Float(a), ValueInner::Float(b)) => a.partial_cmp(&b),
+            (ValueInner::Int(a), ValueInner::Float(b)) => (a as f64).partial_cmp(&b),
+            (ValueInner::Float(a), ValueInner::Int(b)) => a.partial_cmp(&(b as f64)),
+            (ValueInner::String(a), ValueInner::String(b)) => Some(a.as_str().cmp(b.as_str())),
+            _ => None,
+        }
+    }
+
+    /// Whether values of these types can be ordered against each other.
+    pub fn is_comparable(&self, other: &Value<'gc>) -> bool {
+        matches!(
+            (self.0, other.0),
+            (
+                ValueInner::Int(_) | ValueInner::Float(_),
+                ValueInner::Int(_) | ValueInner::Float(_)
+            ) | (ValueInner::String(_), ValueInner::String(_))
+        )
+    }
+
     pub fn try_into<T>(self, cx: &Context<'gc>) -> Result<T, ErrorPtr<'gc>>
     where
         T: TryFromValue<'gc>,
@@ -80,12 +206,151 @@ impl<'gc> Value<'gc> {
     }
 }
 
+/// A value that can be used as a key of a map. Keys are hashed consistently with
+/// [`Value::equals`], so strings are compared by their contents and everything else by identity.
+/// Floats are the only values that cannot be keys, since they are equal to ints.
+#[derive(Clone, Collect, Copy, Debug)]
+#[collect(no_drop)]
+pub struct HashKey<'gc>(Value<'gc>);
+
+impl<'gc> HashKey<'gc> {
+    pub fn new(cx: &Context<'gc>, value: Value<'gc>) -> Result<Self, ErrorPtr<'gc>> {
+        Self::try_new(value).ok_or_else(|| {
+            ErrorValue::new_ptr(
+                cx,
+                StringValue::new_ptr(
+                    cx,
+                    format!("values of type {} cannot be used as map keys", value.ty()),
+                ),
+                value,
+            )
+        })
+    }
+
+    /// A key of the string, such as the name of a field.
+    pub fn string(cx: &Context<'gc>, string: &str) -> Self {
+        Self(StringValue::new_ptr(cx, string.to_string()).into())
+    }
+
+    /// Returns `None` if the value cannot be a key.
+    pub fn try_new(value: Value<'gc>) -> Option<Self> {
+        match value.0 {
+            ValueInner::Float(_) => None,
+            _ => Some(Self(value)),
+        }
+    }
+
+    pub fn value(&self) -> Value<'gc> {
+        self.0
+    }
+}
+
+impl<'gc> PartialEq for HashKey<'gc> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.equals(&other.0)
+    }
+}
+
+impl<'gc> Eq for HashKey<'gc> {}
+
+impl<'gc> Hash for HashKey<'gc> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        mem::discriminant(&self.0.0).hash(state);
+        match self.0.0 {
+            ValueInner::Nil => {}
+            ValueInner::Bool(bool) => bool.hash(state),
+            ValueInner::Int(int) => int.hash(state),
+            ValueInner::Float(_) => unreachable!("floats cannot be keys"),
+            ValueInner::String(string) => string.as_str().hash(state),
+            ValueInner::Closure(closure) => Gc::as_ptr(closure).hash(state),
+            ValueInner::Fiber(fiber) => Gc::as_ptr(fiber).hash(state),
+            ValueInner::Error(error) => Gc::as_ptr(error).hash(state),
+            ValueInner::Module(module) => Gc::as_ptr(module).hash(state),
+            ValueInner::NativeFunction(native) => Gc::as_ptr(native).hash(state),
+            ValueInner::Map(map) => Gc::as_ptr(map).hash(state),
+            ValueInner::List(list) => Gc::as_ptr(list).hash(state),
+            ValueInner::Record(record) => Gc::as_ptr(record).hash(state),
+            ValueInner::RecordType(ty) => Gc::as_ptr(ty).hash(state),
+            // Equal variants may be different objects, so only what they have in common can be
+            // hashed.
+            ValueInner::Variant(variant) => {
+                Gc::as_ptr(variant.ty()).hash(state);
+                variant.index().hash(state);
+            }
+            ValueInner::EnumType(ty) => Gc::as_ptr(ty).hash(state),
+        }
+    }
+}
+
 impl<'gc> Default for Value<'gc> {
     fn default() -> Self {
         Self::NIL
     }
 }
 
+impl<'gc> Display for Value<'gc> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            ValueInner::Nil => write!(f, "nil"),
+            ValueInner::Bool(bool) => write!(f, "{}", bool),
+            ValueInner::Int(int) => write!(f, "{}", int),
+            // Debug formatting always includes the decimal point.
+            ValueInner::Float(float) => write!(f, "{:?}", float),
+            ValueInner::String(string) => write!(f, "{}", string),
+            ValueInner::Closure(closure) => match closure.function().name() {
+                Some(name) => write!(f, "<fn {}>", name),
+                None => write!(f, "<fn>"),
+            },
+            ValueInner::Fiber(_) => write!(f, "<fiber>"),
+            ValueInner::Error(error) => write!(f, "error: {}", error),
+            ValueInner::Module(module) => write!(f, "<module {}>", module.borrow().path()),
+            ValueInner::NativeFunction(native) => write!(f, "<native fn {}>", native.name()),
+            ValueInner::Map(map) => write!(f, "{}", map.borrow()),
+            ValueInner::List(list) => write!(f, "{}", list.borrow()),
+            ValueInner::Record(record) => write!(f, "{}", record.borrow()),
+            ValueInner::RecordType(ty) => write!(f, "{}", ty),
+            ValueInner::Variant(variant) => write!(f, "{}", variant),
+            ValueInner::EnumType(ty) => write!(f, "{}", ty),
+        }
+    }
+}
+
+thread_local! {
+    // The containers being displayed on this thread, from the outermost inwards.
+    static DISPLAYING: RefCell<Vec<*const ()>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Displays the contents of a container with `contents`, unless the container is already being
+/// displayed further out, in which case it contains itself and `cycle` is written instead.
+pub(crate) fn fmt_container<T>(
+    f: &mut Formatter<'_>,
+    container: &T,
+    cycle: &str,
+    contents: impl FnOnce(&mut Formatter<'_>) -> fmt::Result,
+) -> fmt::Result {
+    struct Guard;
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            DISPLAYING.with_borrow_mut(|displaying| displaying.pop());
+        }
+    }
+
+    let ptr = container as *const T as *const ();
+    let entered = DISPLAYING.with_borrow_mut(|displaying| {
+        if displaying.contains(&ptr) {
+            return false;
+        }
+        displaying.push(ptr);
+        true
+    });
+    if !entered {
+        return write!(f, "{}", cycle);
+    }
+    let _guard = Guard;
+    contents(f)
+}
+
 macro_rules! impl_from_for_value {
     ($ty:ty, $variant:ident) => {
         impl<'gc> From<$ty> for Value<'gc> {
@@ -103,25 +368,62 @@ impl_from_for_value!(StringPtr<'gc>, String);
 impl_from_for_value!(ClosurePtr<'gc>, Closure);
 impl_from_for_value!(FiberPtr<'gc>, Fiber);
 impl_from_for_value!(ErrorPtr<'gc>, Error);
+impl_from_for_value!(ModulePtr<'gc>, Module);
+impl_from_for_value!(NativeFunctionPtr<'gc>, NativeFunction);
+impl_from_for_value!(MapPtr<'gc>, Map);
+impl_from_for_value!(ListPtr<'gc>, List);
+impl_from_for_value!(RecordPtr<'gc>, Record);
+impl_from_for_value!(RecordTypePtr<'gc>, RecordType);
+impl_from_for_value!(VariantPtr<'gc>, Variant);
+impl_from_for_value!(EnumTypePtr<'gc>, EnumType);
+
+impl<'gc> From<()> for Value<'gc> {
+    fn from(_: ()) -> Self {
+        Value::NIL
+    }
+}
+
+impl<'gc> From<f32> for Value<'gc> {
+    fn from(value: f32) -> Self {
+        Value(ValueInner::Float(value.into()))
+    }
+}
+
+// Narrower integers always fit in an int. Wider ones are checked by `IntoValue` instead.
+macro_rules! impl_from_int_for_value {
+    ($($ty:ty),*) => {
+        $(
+            impl<'gc> From<$ty> for Value<'gc> {
+                fn from(value: $ty) -> Self {
+                    Value(ValueInner::Int(value.into()))
+                }
+            }
+        )*
+    };
+}
+
+impl_from_int_for_value!(i8, i16, i32, u8, u16, u32);
 
 pub trait TryFromValue<'gc>: Sized {
     fn try_from_value(value: Value<'gc>, cx: &Context<'gc>) -> Result<Self, ErrorPtr<'gc>>;
 }
 
+/// The error for a value that isn't of the type it is being converted to.
+fn type_error<'gc>(cx: &Context<'gc>, value: Value<'gc>, expected: ValueType) -> ErrorPtr<'gc> {
+    ErrorValue::new_ptr(
+        cx,
+        StringValue::new_ptr(cx, format!("expected {}, found {}", expected, value.ty())),
+        value,
+    )
+}
+
 macro_rules! impl_try_from_value {
     ($ty:ty, $variant:ident, $expected:expr) => {
         impl<'gc> TryFromValue<'gc> for $ty {
             fn try_from_value(value: Value<'gc>, cx: &Context<'gc>) -> Result<Self, ErrorPtr<'gc>> {
                 match value.0 {
                     ValueInner::$variant(v) => Ok(v),
-                    _ => Err(ErrorValue::new_ptr(
-                        cx,
-                        StringValue::new_ptr(
-                            cx,
-                            format!("tried to convert {} to {}", value.ty(), $expected),
-                        ),
-                        value,
-                    )),
+                    _ => Err(type_error(cx, value, $expected)),
                 }
             }
         }
@@ -130,32 +432,533 @@ macro_rules! impl_try_from_value {
 
 impl_try_from_value!(bool, Bool, ValueType::Bool);
 impl_try_from_value!(i64, Int, ValueType::Int);
-impl_try_from_value!(f64, Float, ValueType::Float);
 impl_try_from_value!(ClosurePtr<'gc>, Closure, ValueType::Closure);
 impl_try_from_value!(FiberPtr<'gc>, Fiber, ValueType::Fiber);
 impl_try_from_value!(ErrorPtr<'gc>, Error, ValueType::Error);
+impl_try_from_value!(ModulePtr<'gc>, Module, ValueType::Module);
+impl_try_from_value!(
+    NativeFunctionPtr<'gc>,
+    NativeFunction,
+    ValueType::NativeFunction
+);
+impl_try_from_value!(StringPtr<'gc>, String, ValueType::String);
+impl_try_from_value!(MapPtr<'gc>, Map, ValueType::Map);
+impl_try_from_value!(ListPtr<'gc>, List, ValueType::List);
+impl_try_from_value!(RecordPtr<'gc>, Record, ValueType::Record);
+impl_try_from_value!(RecordTypePtr<'gc>, RecordType, ValueType::RecordType);
+impl_try_from_value!(VariantPtr<'gc>, Variant, ValueType::Variant);
+impl_try_from_value!(EnumTypePtr<'gc>, EnumType, ValueType::EnumType);
+
+impl<'gc> TryFromValue<'gc> for String {
+    fn try_from_value(value: Value<'gc>, cx: &Context<'gc>) -> Result<Self, ErrorPtr<'gc>> {
+        let string: StringPtr = value.try_into(cx)?;
+        Ok(string.as_str().to_string())
+    }
+}
+
+/// Borrows the contents of a string for as long as the arena is being mutated.
+impl<'gc> TryFromValue<'gc> for &'gc str {
+    fn try_from_value(value: Value<'gc>, cx: &Context<'gc>) -> Result<Self, ErrorPtr<'gc>> {
+        let string: StringPtr = value.try_into(cx)?;
+        Ok(Gc::as_ref(string).as_str())
+    }
+}
+
+/// Ints are widened to floats, rounding to the nearest float if they are too large to be exact.
+impl<'gc> TryFromValue<'gc> for f64 {
+    fn try_from_value(value: Value<'gc>, cx: &Context<'gc>) -> Result<Self, ErrorPtr<'gc>> {
+        match value.0 {
+            ValueInner::Float(float) => Ok(float),
+            ValueInner::Int(int) => Ok(int as f64),
+            _ => Err(type_error(cx, value, ValueType::Float)),
+        }
+    }
+}
+
+/// Floats are rounded to the nearest `f32`, but must not be too large to fit in one.
+impl<'gc> TryFromValue<'gc> for f32 {
+    fn try_from_value(value: Value<'gc>, cx: &Context<'gc>) -> Result<Self, ErrorPtr<'gc>> {
+        let float: f64 = value.try_into(cx)?;
+        if float.is_finite() && float.abs() > f32::MAX as f64 {
+            return Err(out_of_range(cx, value, "f32"));
+        }
+        Ok(float as f32)
+    }
+}
+
+/// Only `nil` can be converted to `()`.
+impl<'gc> TryFromValue<'gc> for () {
+    fn try_from_value(value: Value<'gc>, cx: &Context<'gc>) -> Result<Self, ErrorPtr<'gc>> {
+        match value.0 {
+            ValueInner::Nil => Ok(()),
+            _ => Err(type_error(cx, value, ValueType::Nil)),
+        }
+    }
+}
+
+impl<'gc, T> TryFromValue<'gc> for Vec<T>
+where
+    T: TryFromValue<'gc>,
+{
+    fn try_from_value(value: Value<'gc>, cx: &Context<'gc>) -> Result<Self, ErrorPtr<'gc>> {
+        let list: ListPtr = value.try_into(cx)?;
+        list.borrow().convert(cx)
+    }
+}
+
+impl<'gc, T> TryFromValue<'gc> for HashMap<String, T>
+where
+    T: TryFromValue<'gc>,
+{
+    fn try_from_value(value: Value<'gc>, cx: &Context<'gc>) -> Result<Self, ErrorPtr<'gc>> {
+        let map: MapPtr = value.try_into(cx)?;
+        let map = map.borrow();
+        map.iter()
+            .map(|(key, value)| {
+                let key: String = key.try_into(cx)?;
+                let value = convert_field(cx, &key, value)?;
+                Ok((key, value))
+            })
+            .collect()
+    }
+}
+
+/// `nil` is converted to `None`, and anything else to `Some`.
+impl<'gc, T> TryFromValue<'gc> for Option<T>
+where
+    T: TryFromValue<'gc>,
+{
+    fn try_from_value(value: Value<'gc>, cx: &Context<'gc>) -> Result<Self, ErrorPtr<'gc>> {
+        match value.0 {
+            ValueInner::Nil => Ok(None),
+            _ => Ok(Some(value.try_into(cx)?)),
+        }
+    }
+}
+
+macro_rules! impl_try_from_value_for_int {
+    ($($ty:ty),*) => {
+        $(
+            impl<'gc> TryFromValue<'gc> for $ty {
+                fn try_from_value(
+                    value: Value<'gc>,
+                    cx: &Context<'gc>,
+                ) -> Result<Self, ErrorPtr<'gc>> {
+                    let int: i64 = value.try_into(cx)?;
+                    <$ty>::try_from(int).map_err(|_| out_of_range(cx, value, stringify!($ty)))
+                }
+            }
+        )*
+    };
+}
 
+impl_try_from_value_for_int!(i8, i16, i32, i128, isize, u8, u16, u32, u64, u128, usize);
+
+/// The error for a value that doesn't fit in the type it is being converted to.
+fn out_of_range<'gc>(cx: &Context<'gc>, value: Value<'gc>, ty: &str) -> ErrorPtr<'gc> {
+    ErrorValue::new_ptr(
+        cx,
+        StringValue::new_ptr(cx, format!("{} is out of range for {}", value, ty)),
+        value,
+    )
+}
+
+/// Converts Rust values into values, failing if they cannot be represented, such as integers too
+/// large for an int.
 pub trait IntoValue<'gc>: Sized {
-    fn into_value(self, cx: &Context<'gc>) -> Value<'gc>;
+    fn into_value(self, cx: &Context<'gc>) -> Result<Value<'gc>, ErrorPtr<'gc>>;
 }
 
 impl<'gc, T> IntoValue<'gc> for T
 where
     T: Into<Value<'gc>>,
 {
-    fn into_value(self, _cx: &Context<'gc>) -> Value<'gc> {
-        self.into()
+    fn into_value(self, _cx: &Context<'gc>) -> Result<Value<'gc>, ErrorPtr<'gc>> {
+        Ok(self.into())
+    }
+}
+
+// Wider integers are only converted if they fit in an int.
+macro_rules! impl_into_value_for_wide_int {
+    ($($ty:ty),*) => {
+        $(
+            impl<'gc> IntoValue<'gc> for $ty {
+                fn into_value(self, cx: &Context<'gc>) -> Result<Value<'gc>, ErrorPtr<'gc>> {
+                    match i64::try_from(self) {
+                        Ok(int) => Ok(int.into()),
+                        Err(_) => Err(ErrorValue::new_message_ptr(
+                            cx,
+                            format!("{} is out of range for int", self),
+                        )),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_into_value_for_wide_int!(i128, isize, u64, u128, usize);
+
+/// Converts Rust values into the arguments of a call, one for each element of the tuple.
+pub trait IntoArgs {
+    fn into_args<'gc>(self, cx: &Context<'gc>) -> Result<Vec<Value<'gc>>, ErrorPtr<'gc>>;
+}
+
+impl IntoArgs for () {
+    fn into_args<'gc>(self, _cx: &Context<'gc>) -> Result<Vec<Value<'gc>>, ErrorPtr<'gc>> {
+        Ok(Vec::new())
+    }
+}
+
+macro_rules! impl_into_args {
+    ($($ty:ident $arg:ident),+) => {
+        impl<$($ty),+> IntoArgs for ($($ty,)+)
+        where
+            $($ty: for<'gc> IntoValue<'gc>),+
+        {
+            fn into_args<'gc>(self, cx: &Context<'gc>) -> Result<Vec<Value<'gc>>, ErrorPtr<'gc>> {
+                let ($($arg,)+) = self;
+                Ok(vec![$($arg.into_value(cx)?),+])
+            }
+        }
+    };
+}
+
+impl_into_args!(A a);
+impl_into_args!(A a, B b);
+impl_into_args!(A a, B b, C c);
+impl_into_args!(A a, B b, C c, D d);
+impl_into_args!(A a, B b, C c, D d, E e);
+impl_into_args!(A a, B b, C c, D d, E e, F f);
+
+impl<'gc, T> IntoValue<'gc> for Option<T>
+where
+    T: IntoValue<'gc>,
+{
+    fn into_value(self, cx: &Context<'gc>) -> Result<Value<'gc>, ErrorPtr<'gc>> {
+        match self {
+            Some(value) => value.into_value(cx),
+            None => Ok(Value::NIL),
+        }
     }
 }
 
 impl<'gc> IntoValue<'gc> for &str {
-    fn into_value(self, cx: &Context<'gc>) -> Value<'gc> {
+    fn into_value(self, cx: &Context<'gc>) -> Result<Value<'gc>, ErrorPtr<'gc>> {
         self.to_string().into_value(cx)
     }
 }
 
 impl<'gc> IntoValue<'gc> for String {
-    fn into_value(self, cx: &Context<'gc>) -> Value<'gc> {
-        Value(ValueInner::String(StringValue::new_ptr(cx, self)))
+    fn into_value(self, cx: &Context<'gc>) -> Result<Value<'gc>, ErrorPtr<'gc>> {
+        Ok(Value(ValueInner::String(StringValue::new_ptr(cx, self))))
+    }
+}
+
+impl<'gc, T> IntoValue<'gc> for Vec<T>
+where
+    T: IntoValue<'gc>,
+{
+    fn into_value(self, cx: &Context<'gc>) -> Result<Value<'gc>, ErrorPtr<'gc>> {
+        let items = self
+            .into_iter()
+            .map(|item| item.into_value(cx))
+            .collect::<Result<_, _>>()?;
+        Ok(ListValue::new_ptr(cx, items).into())
+    }
+}
+
+impl<'gc, T> IntoValue<'gc> for HashMap<String, T>
+where
+    T: IntoValue<'gc>,
+{
+    fn into_value(self, cx: &Context<'gc>) -> Result<Value<'gc>, ErrorPtr<'gc>> {
+        let entries = self
+            .into_iter()
+            .map(|(key, value)| Ok((HashKey::string(cx, &key), value.into_value(cx)?)))
+            .collect::<Result<Vec<_>, ErrorPtr>>()?;
+        Ok(MapValue::new_ptr(cx, entries).into())
+    }
+}
+
+// Tuples are converted to and from lists with an item for each element.
+macro_rules! impl_tuple_conversions {
+    ($len:literal; $($ty:ident $item:ident $index:tt),+) => {
+        impl<'gc, $($ty),+> IntoValue<'gc> for ($($ty,)+)
+        where
+            $($ty: IntoValue<'gc>),+
+        {
+            fn into_value(self, cx: &Context<'gc>) -> Result<Value<'gc>, ErrorPtr<'gc>> {
+                let ($($item,)+) = self;
+                Ok(ListValue::new_ptr(cx, vec![$($item.into_value(cx)?),+]).into())
+            }
+        }
+
+        impl<'gc, $($ty),+> TryFromValue<'gc> for ($($ty,)+)
+        where
+            $($ty: TryFromValue<'gc>),+
+        {
+            fn try_from_value(
+                value: Value<'gc>,
+                cx: &Context<'gc>,
+            ) -> Result<Self, ErrorPtr<'gc>> {
+                let list: ListPtr = value.try_into(cx)?;
+                let list = list.borrow();
+                if list.len() != $len {
+                    return Err(ErrorValue::new_ptr(
+                        cx,
+                        StringValue::new_ptr(
+                            cx,
+                            format!("expected a list of {} items, found {}", $len, list.len()),
+                        ),
+                        value,
+                    ));
+                }
+                Ok(($(list.convert_item(cx, $index)?,)+))
+            }
+        }
+    };
+}
+
+impl_tuple_conversions!(1; A a 0);
+impl_tuple_conversions!(2; A a 0, B b 1);
+impl_tuple_conversions!(3; A a 0, B b 1, C c 2);
+impl_tuple_conversions!(4; A a 0, B b 1, C c 2, D d 3);
+impl_tuple_conversions!(5; A a 0, B b 1, C c 2, D d 3, E e 4);
+impl_tuple_conversions!(6; A a 0, B b 1, C c 2, D d 3, E e 4, F f 5);
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{Engine, Error, IntoValue, TryFromValue};
+
+    fn evaluate<T>(source: &str) -> Result<T, String>
+    where
+        T: for<'gc> TryFromValue<'gc>,
+    {
+        Engine::builder()
+            .build()
+            .evaluate_inline(source)
+            .map_err(|error| match error {
+                Error::Runtime { message, .. } => message,
+                error => panic!("expected a runtime error, got {}", error),
+            })
+    }
+
+    #[test]
+    fn numeric_equality() {
+        assert_eq!(evaluate::<bool>("1 == 1.0"), Ok(true));
+        assert_eq!(evaluate::<bool>("1.0 == 1"), Ok(true));
+        assert_eq!(evaluate::<bool>("1 != 1.5"), Ok(true));
+        assert_eq!(
+            evaluate::<bool>("let nan = 0.0 / 0.0; nan == nan"),
+            Ok(false)
+        );
+        assert_eq!(evaluate::<bool>("1 == true"), Ok(false));
+        assert_eq!(evaluate::<bool>(r#"1 == "1""#), Ok(false));
+        assert_eq!(evaluate::<bool>("nil == false"), Ok(false));
+        assert_eq!(evaluate::<bool>("nil == nil"), Ok(true));
+    }
+
+    #[test]
+    fn string_equality_and_identity() {
+        // Strings are compared by their contents, even when they are different objects.
+        assert_eq!(
+            evaluate::<bool>(r#"fn make() { "doji" } make() == "doji""#),
+            Ok(true)
+        );
+        assert_eq!(evaluate::<bool>(r#""doji" != "Doji""#), Ok(true));
+        // Functions are only equal to themselves.
+        assert_eq!(evaluate::<bool>("fn f() {} f == f"), Ok(true));
+        assert_eq!(evaluate::<bool>("fn f() {} fn g() {} f == g"), Ok(false));
+        assert_eq!(
+            evaluate::<bool>("fn make() { fn() { 1 } } make() == make()"),
+            Ok(false)
+        );
+    }
+
+    #[test]
+    fn ordering() {
+        assert_eq!(
+            evaluate::<bool>("1 < 2 and 2 <= 2 and 3 > 2 and 3 >= 3"),
+            Ok(true)
+        );
+        assert_eq!(evaluate::<bool>("1 < 1.5 and 2.5 > 2"), Ok(true));
+        assert_eq!(
+            evaluate::<bool>(r#""apple" < "banana" and "b" > "abc""#),
+            Ok(true)
+        );
+        assert_eq!(
+            evaluate::<bool>("let nan = 0.0 / 0.0; nan < 1 or nan >= 1"),
+            Ok(false)
+        );
+    }
+
+    #[test]
+    fn ordering_unorderable_values() {
+        assert_eq!(
+            evaluate::<bool>(r#"1 < "2""#),
+            Err("cannot compare int with string".into())
+        );
+        assert_eq!(
+            evaluate::<bool>("nil >= nil"),
+            Err("cannot compare nil with nil".into())
+        );
+        assert_eq!(
+            evaluate::<bool>("true > false"),
+            Err("cannot compare bool with bool".into())
+        );
+    }
+
+    #[test]
+    fn logical_operators() {
+        assert_eq!(evaluate::<i64>("nil or 2"), Ok(2));
+        assert_eq!(evaluate::<i64>("1 and 2"), Ok(2));
+        assert_eq!(evaluate::<bool>("false and 1 / 0"), Ok(false));
+        assert_eq!(evaluate::<bool>("true or 1 / 0"), Ok(true));
+        assert_eq!(evaluate::<bool>("not nil and not false"), Ok(true));
+    }
+
+    #[test]
+    fn convert_scalars() {
+        assert_eq!(evaluate::<bool>("1 < 2"), Ok(true));
+        assert_eq!(evaluate::<i64>("1 + 2"), Ok(3));
+        assert_eq!(evaluate::<f32>("1.5"), Ok(1.5));
+        assert_eq!(evaluate::<String>(r#""doji""#), Ok("doji".to_string()));
+        assert_eq!(evaluate::<Option<i64>>("nil"), Ok(None));
+        assert_eq!(evaluate::<Option<i64>>("1"), Ok(Some(1)));
+        assert_eq!(evaluate::<()>("nil"), Ok(()));
+    }
+
+    #[test]
+    fn convert_wrong_type() {
+        assert_eq!(
+            evaluate::<i64>(r#""1""#),
+            Err("expected int, found string".into())
+        );
+        assert_eq!(
+            evaluate::<String>("nil"),
+            Err("expected string, found nil".into())
+        );
+        assert_eq!(
+            evaluate::<Option<bool>>("1"),
+            Err("expected bool, found int".into())
+        );
+        assert_eq!(evaluate::<()>("1"), Err("expected nil, found int".into()));
+    }
+
+    #[test]
+    fn convert_out_of_range() {
+        assert_eq!(evaluate::<u8>("255"), Ok(255));
+        assert_eq!(
+            evaluate::<u8>("256"),
+            Err("256 is out of range for u8".into())
+        );
+        assert_eq!(
+            evaluate::<u32>("-1"),
+            Err("-1 is out of range for u32".into())
+        );
+    }
+
+    #[test]
+    fn convert_floats() {
+        assert_eq!(evaluate::<f64>("2"), Ok(2.0));
+        assert_eq!(evaluate::<f32>("-3"), Ok(-3.0));
+        assert_eq!(evaluate::<f32>("1.0 / 0.0"), Ok(f32::INFINITY));
+        assert_eq!(
+            evaluate::<f32>(&format!("1{}.0", "0".repeat(39))),
+            Err("1e39 is out of range for f32".into())
+        );
+        assert_eq!(
+            evaluate::<f64>("true"),
+            Err("expected float, found bool".into())
+        );
+    }
+
+    #[test]
+    fn borrow_strings() {
+        let engine = Engine::builder()
+            .function("shout", |cx, args| {
+                let string: &str = args[0].try_into(cx)?;
+                string.to_uppercase().into_value(cx)
+            })
+            .build();
+        let value: String = engine.evaluate_inline(r#"shout("doji")"#).unwrap();
+        assert_eq!(value, "DOJI");
+    }
+
+    #[test]
+    fn wide_ints_into_values() {
+        let engine = Engine::builder().build();
+        engine.set_global("small", u64::MAX >> 1).unwrap();
+        engine.set_global("index", 3usize).unwrap();
+        engine.set_global("negative", -5i128).unwrap();
+        let value: i64 = engine
+            .evaluate_inline("small - index * 0 + negative + 5")
+            .unwrap();
+        assert_eq!(value, i64::MAX);
+
+        let message = |result: Result<(), Error>| match result {
+            Err(Error::Runtime { message, .. }) => message,
+            result => panic!("expected a runtime error, got {:?}", result.is_ok()),
+        };
+        assert_eq!(
+            message(engine.set_global("big", u64::MAX)),
+            "18446744073709551615 is out of range for int"
+        );
+        assert_eq!(
+            message(engine.set_global("big", i128::MIN)),
+            "-170141183460469231731687303715884105728 is out of range for int"
+        );
+        assert_eq!(engine.get_global::<i64>("big").unwrap(), None);
+        assert_eq!(
+            message(engine.call::<i64, _>("small", (usize::MAX,)).map(|_| ())),
+            format!("{} is out of range for int", usize::MAX)
+        );
+    }
+
+    #[test]
+    fn convert_collections() {
+        assert_eq!(evaluate::<Vec<i64>>("[1, 2]"), Ok(vec![1, 2]));
+        assert_eq!(
+            evaluate::<Vec<i64>>(r#"[1, "2"]"#),
+            Err("expected int, found string at index 1".into())
+        );
+        assert_eq!(
+            evaluate::<HashMap<String, i64>>("{ a: 1 }"),
+            Ok(HashMap::from([("a".to_string(), 1)]))
+        );
+        assert_eq!(
+            evaluate::<HashMap<String, i64>>("{ a: true }"),
+            Err("expected int, found bool at field `a`".into())
+        );
+    }
+
+    #[test]
+    fn convert_tuples() {
+        assert_eq!(
+            evaluate::<(i64, String)>(r#"[1, "a"]"#),
+            Ok((1, "a".to_string()))
+        );
+        assert_eq!(
+            evaluate::<(i64, String)>("[1, 2]"),
+            Err("expected string, found int at index 1".into())
+        );
+        assert_eq!(
+            evaluate::<(i64, i64)>("[1, 2, 3]"),
+            Err("expected a list of 2 items, found 3".into())
+        );
+        assert_eq!(
+            evaluate::<(i64,)>("1"),
+            Err("expected list, found int".into())
+        );
+    }
+
+    #[test]
+    fn tuples_into_values() {
+        let engine = Engine::builder().global("pair", (1i64, "a")).build();
+        let result: (String, i64) = engine.evaluate_inline("[pair[1], pair[0] + 1]").unwrap();
+        assert_eq!(result, ("a".to_string(), 2));
     }
 }
@@ -0,0 +1,266 @@
+use core::fmt::{self, Display, Formatter};
+
+use gc_arena::{Collect, Gc};
+
+use crate::{
+    context::Context,
+    error::{ErrorPtr, ErrorValue},
+    value::Value,
+};
+
+pub type EnumTypePtr<'gc> = Gc<'gc, EnumType>;
+
+pub type VariantPtr<'gc> = Gc<'gc, VariantValue<'gc>>;
+
+/// A type declared with `enum`, whose values are each one of a fixed set of variants.
+#[derive(Collect, Debug)]
+#[collect(require_static)]
+pub struct EnumType {
+    name: String,
+    variants: Box<[Variant]>,
+}
+
+impl EnumType {
+    pub fn new_ptr<'gc>(
+        cx: &Context<'gc>,
+        name: String,
+        variants: Box<[Variant]>,
+    ) -> EnumTypePtr<'gc> {
+        Gc::new(cx.mutation(), Self { name, variants })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn variants(&self) -> &[Variant] {
+        &self.variants
+    }
+
+    /// The position of the variant in the declaration of this type.
+    pub fn variant_index(&self, name: &str) -> Option<usize> {
+        self.variants
+            .iter()
+            .position(|variant| variant.name == name)
+    }
+}
+
+#[derive(Debug)]
+pub struct Variant {
+    name: String,
+    fields: Box<[String]>,
+}
+
+impl Variant {
+    pub fn new(name: String, fields: Box<[String]>) -> Self {
+        Self { name, fields }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn fields(&self) -> &[String] {
+        &self.fields
+    }
+}
+
+/// A value of an enum type, which is one of its variants along with the values of its fields.
+/// Variants cannot be changed once created.
+#[derive(Collect, Debug)]
+#[collect(no_drop)]
+pub struct VariantValue<'gc> {
+    ty: EnumTypePtr<'gc>,
+    index: usize,
+    fields: Box<[Value<'gc>]>,
+}
+
+impl<'gc> VariantValue<'gc> {
+    /// Creates a value of the named variant, which must be given exactly as many fields as it
+    /// was declared with.
+    pub fn new_ptr(
+        cx: &Context<'gc>,
+        ty: EnumTypePtr<'gc>,
+        name: &str,
+        fields: Vec<Value<'gc>>,
+    ) -> Result<VariantPtr<'gc>, ErrorPtr<'gc>> {
+        let index = ty
+            .variant_index(name)
+            .ok_or_else(|| no_variant(cx, &ty, name))?;
+        let expected = ty.variants[index].fields.len();
+        if fields.len() != expected {
+            return Err(ErrorValue::new_message_ptr(
+                cx,
+                format!(
+                    "wrong number of fields for {}.{}: expected {}, got {}",
+                    ty.name,
+                    name,
+                    expected,
+                    fields.len()
+                ),
+            ));
+        }
+        let fields = fields.into_boxed_slice();
+        Ok(Gc::new(cx.mutation(), Self { ty, index, fields }))
+    }
+
+    pub fn ty(&self) -> EnumTypePtr<'gc> {
+        self.ty
+    }
+
+    /// The position of the variant, as given by [`EnumType::variant_index`].
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn variant(&self) -> &Variant {
+        &self.ty.variants[self.index]
+    }
+
+    pub fn fields(&self) -> &[Value<'gc>] {
+        &self.fields
+    }
+
+    /// The value of the field with the name, if the variant has one.
+    pub fn field(&self, name: &str) -> Option<Value<'gc>> {
+        let index = self
+            .variant()
+            .fields
+            .iter()
+            .position(|field| field == name)?;
+        Some(self.fields[index])
+    }
+
+    /// Variants are equal if they are the same variant of the same type, and their fields are
+    /// equal.
+    pub fn equals(&self, other: &VariantValue<'gc>) -> bool {
+        Gc::ptr_eq(self.ty, other.ty)
+            && self.index == other.index
+            && self
+                .fields
+                .iter()
+                .zip(&other.fields)
+                .all(|(a, b)| a.equals(b))
+    }
+}
+
+pub fn no_variant<'gc>(cx: &Context<'gc>, ty: &EnumType, name: &str) -> ErrorPtr<'gc> {
+    ErrorValue::new_message_ptr(cx, format!("{} has no variant named `{}`", ty.name(), name))
+}
+
+impl Display for EnumType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "<enum {}>", self.name)
+    }
+}
+
+impl<'gc> Display for VariantValue<'gc> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.ty.name(), self.variant().name)?;
+        if self.fields.is_empty() {
+            return Ok(());
+        }
+        write!(f, "(")?;
+        for (i, value) in self.fields.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", value)?;
+        }
+        write!(f, ")")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Engine, Error};
+
+    const SHAPE: &str = "enum Shape { Circle(r), Rect(w, h), Empty }\n";
+
+    const AREA: &str = "fn area(s) {
+        match s {
+            Shape.Circle(r) => 3 * r * r,
+            Shape.Rect(w, h) => w * h,
+        }
+    }\n";
+
+    fn warnings(engine: &Engine) -> Vec<String> {
+        engine
+            .warnings()
+            .iter()
+            .flat_map(|warnings| warnings.iter())
+            .map(|warning| warning.message().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn match_variants() {
+        let engine = Engine::builder().build();
+        let areas: Vec<i64> = engine
+            .evaluate_inline(format!(
+                "{}{}[area(Shape.Circle(2)), area(Shape.Rect(2, 3))]",
+                SHAPE, AREA
+            ))
+            .unwrap();
+        assert_eq!(areas, [12, 6]);
+    }
+
+    #[test]
+    fn non_exhaustive_match_warns_when_evaluated() {
+        let engine = Engine::builder().build();
+        let area: i64 = engine
+            .evaluate_inline(format!("{}{}area(Shape.Rect(2, 3))", SHAPE, AREA))
+            .unwrap();
+        assert_eq!(area, 6);
+        assert_eq!(warnings(&engine), ["match does not cover `Shape.Empty`"]);
+    }
+
+    #[test]
+    fn non_exhaustive_match_fails_on_missing_variant() {
+        let engine = Engine::builder().build();
+        let result = engine.evaluate_inline::<i64>(format!("{}{}area(Shape.Empty)", SHAPE, AREA));
+        match result {
+            Err(Error::Runtime { message, .. }) => {
+                assert_eq!(message, "no arm matches Shape.Empty")
+            }
+            result => panic!(
+                "expected a runtime error, got {:?}",
+                result.map_err(|e| e.to_string())
+            ),
+        }
+    }
+
+    #[test]
+    fn exhaustive_match_does_not_warn() {
+        let engine = Engine::builder().build();
+        let source = "fn area(s) {
+            match s {
+                Shape.Circle(r) => 3 * r * r,
+                Shape.Rect(w, h) => w * h,
+                Shape.Empty => 0,
+            }
+        }
+        area(Shape.Empty)";
+        let area: i64 = engine
+            .evaluate_inline(format!("{}{}", SHAPE, source))
+            .unwrap();
+        assert_eq!(area, 0);
+        assert!(warnings(&engine).is_empty());
+
+        let wildcard = "match Shape.Empty { Shape.Circle(_) => 1, _ => 0 }";
+        let _: i64 = engine
+            .evaluate_inline(format!("{}{}", SHAPE, wildcard))
+            .unwrap();
+        assert!(warnings(&engine).is_empty());
+    }
+
+    #[test]
+    fn arms_after_a_catch_all_are_unreachable() {
+        let engine = Engine::builder().build();
+        let value: i64 = engine
+            .evaluate_inline("match 2 { 1 => 10, x => x, 3 => 30 }")
+            .unwrap();
+        assert_eq!(value, 2);
+        assert_eq!(warnings(&engine), ["unreachable match arm"]);
+    }
+}